@@ -5,10 +5,15 @@ fn main() {
 
     println!("cargo:rustc-cfg=target_arch=\"i586\""); // specify target arch
     println!("cargo:rustc-cfg=target_platform=\"ibmpc\""); // specify target platform
-    //println!("cargo:rustc-cfg=debug_messages"); // enable debug messages (useful if things break)
+    //println!("cargo:rustc-cfg=debug_messages"); // always enable debug messages (useful if things break before the command line is read), otherwise boot with loglevel=7
 
     // compile our asm boot shim
-    cc::Build::new().file("src/arch/i586/boot.S").compile("boot");
+    let mut boot = cc::Build::new();
+    boot.file("src/arch/i586/boot.S");
+    if std::env::var("CARGO_FEATURE_FRAMEBUFFER").is_ok() {
+        boot.define("MULTIBOOT_FRAMEBUFFER", None); // ask the bootloader for a framebuffer
+    }
+    boot.compile("boot");
     cc::Build::new().file("src/arch/i586/tasks.S").compile("tasks");
     cc::Build::new().file("src/platform/ibmpc/irq.S").compile("irq");
 }
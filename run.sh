@@ -1,6 +1,10 @@
 #!/bin/bash
 
+./symbols.sh target/i586-unknown-none/release/ockernel || exit 1
+
 echo "(ctrl+c to exit)"
 
 #qemu-system-i386 -machine type=pc-i440fx-3.1 -kernel target/i586-unknown-none/release/ockernel -display none -serial stdio
+# pentium has no APIC, so interrupts go through the 8259s. use pentium3 or anything newer to get the APIC
+#qemu-system-i386 -cpu pentium3 -machine type=pc-i440fx-3.1 -kernel target/i586-unknown-none/release/ockernel -serial stdio
 qemu-system-i386 -cpu pentium -machine type=pc-i440fx-3.1 -kernel target/i586-unknown-none/release/ockernel -serial stdio
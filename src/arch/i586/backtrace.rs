@@ -0,0 +1,134 @@
+//! stack backtraces
+//! the kernel is built with frame pointers, so every stack frame starts with the caller's ebp followed by the return address
+
+use core::{
+    arch::asm,
+    fmt,
+    mem::size_of,
+};
+use crate::util::symbols::SymbolTable;
+use super::{LINKED_BASE, MAX_STACK_FRAMES};
+
+extern "C" {
+    /// start of the embedded symbol table, filled in by symbols.sh
+    static ksymbols_start: u8;
+
+    /// end of the space reserved for the embedded symbol table
+    static ksymbols_end: u8;
+
+    /// start of kernel code
+    static text_start: u8;
+
+    /// end of kernel code
+    static text_end: u8;
+}
+
+/// start of a stack frame, as set up by a function's prologue
+#[repr(C)]
+struct StackFrame {
+    /// the caller's frame
+    prev: *const StackFrame,
+
+    /// where this function will return to
+    return_addr: usize,
+}
+
+/// gets the kernel's embedded symbol table
+pub fn kernel_symbols() -> SymbolTable<'static> {
+    unsafe {
+        let start = &ksymbols_start as *const u8;
+        let len = (&ksymbols_end as *const u8) as usize - start as usize;
+
+        SymbolTable::new(core::slice::from_raw_parts(start, len))
+    }
+}
+
+/// whether the given address is in kernel code
+pub fn is_kernel_text(addr: usize) -> bool {
+    unsafe { addr >= (&text_start as *const u8) as usize && addr < (&text_end as *const u8) as usize }
+}
+
+/// gets the current frame pointer
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let ebp: usize;
+    unsafe { asm!("mov {}, ebp", out(reg) ebp); }
+    ebp
+}
+
+/// walks the chain of stack frames starting at the given frame pointer, calling the closure with each return address.
+/// stops at a null frame pointer (the bottom of the kernel stack), a frame outside of kernel memory
+/// (i.e. we came from user mode), or a frame that doesn't move up the stack (the stack is corrupted)
+pub unsafe fn walk_stack(ebp: usize, mut f: impl FnMut(usize)) {
+    let mut frame = ebp as *const StackFrame;
+
+    for _i in 0..MAX_STACK_FRAMES {
+        let addr = frame as usize;
+
+        if addr < LINKED_BASE || addr % size_of::<usize>() != 0 || addr > usize::MAX - size_of::<StackFrame>() {
+            break;
+        }
+
+        f((*frame).return_addr);
+
+        let prev = (*frame).prev;
+
+        if (prev as usize) <= addr {
+            break;
+        }
+
+        frame = prev;
+    }
+}
+
+/// a function call in a backtrace
+pub struct Frame {
+    /// how far up the stack this frame is
+    pub index: usize,
+
+    /// return address of the call
+    pub addr: usize,
+
+    /// symbol the return address is in and the offset into it, if it could be found
+    pub symbol: Option<(&'static str, usize)>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbol {
+            Some((name, offset)) => write!(f, "#{} {:#010x} {}+{:#x}", self.index, self.addr, name, offset),
+            None => write!(f, "#{} {:#010x} ?", self.index, self.addr),
+        }
+    }
+}
+
+/// calls the closure with every frame in kernel code, starting at the given frame pointer.
+/// return addresses outside of kernel code are skipped, these show up when a frame was pushed by an interrupt
+pub unsafe fn backtrace(ebp: usize, mut f: impl FnMut(Frame)) {
+    let symbols = kernel_symbols();
+    let mut index = 0;
+
+    walk_stack(ebp, |addr| {
+        if is_kernel_text(addr) {
+            f(Frame { index, addr, symbol: symbols.lookup(addr) });
+            index += 1;
+        }
+    });
+}
+
+/// prints a backtrace starting at the given frame pointer
+pub unsafe fn print_backtrace_from(ebp: usize) {
+    error!("backtrace:");
+
+    backtrace(ebp, |frame| error!("  {}", frame));
+
+    if kernel_symbols().is_empty() {
+        error!("(no symbols, run symbols.sh on the kernel to get function names)");
+    }
+}
+
+/// prints a backtrace of the current stack
+#[inline(always)]
+pub fn print_backtrace() {
+    unsafe { print_backtrace_from(frame_pointer()); }
+}
@@ -0,0 +1,253 @@
+//! cpu identification, optional features and model specific registers
+
+use core::{
+    arch::{
+        asm,
+        x86::{__cpuid, has_cpuid},
+    },
+    fmt,
+};
+use bitmask_enum::bitmask;
+
+/// cpu features we know about. these aren't cpuid's bits since they come from more than one leaf
+#[bitmask(u32)]
+pub enum CpuFeatures {
+    /// no optional features
+    None    = Self(0),
+
+    /// x87 floating point unit
+    Fpu     = Self(1 << 0),
+
+    /// 4mb pages
+    Pse     = Self(1 << 1),
+
+    /// time stamp counter
+    Tsc     = Self(1 << 2),
+
+    /// model specific registers
+    Msr     = Self(1 << 3),
+
+    /// physical address extension
+    Pae     = Self(1 << 4),
+
+    /// on-chip local APIC
+    Apic    = Self(1 << 5),
+
+    /// global pages
+    Pge     = Self(1 << 6),
+
+    /// streaming SIMD extensions
+    Sse     = Self(1 << 7),
+
+    /// SSE2
+    Sse2    = Self(1 << 8),
+
+    /// no execute page flag, only usable with PAE
+    Nx      = Self(1 << 9),
+}
+
+/// where each feature is in cpuid leaf 1's edx, and what linux calls it
+const LEAF_1_FEATURES: [(u32, CpuFeatures, &str); 9] = [
+    (0, CpuFeatures::Fpu, "fpu"),
+    (3, CpuFeatures::Pse, "pse"),
+    (4, CpuFeatures::Tsc, "tsc"),
+    (5, CpuFeatures::Msr, "msr"),
+    (6, CpuFeatures::Pae, "pae"),
+    (9, CpuFeatures::Apic, "apic"),
+    (13, CpuFeatures::Pge, "pge"),
+    (25, CpuFeatures::Sse, "sse"),
+    (26, CpuFeatures::Sse2, "sse2"),
+];
+
+/// extended leaf with more feature flags in edx
+const EXTENDED_FEATURES_LEAF: u32 = 0x80000001;
+
+/// edx flag for the no execute bit in the extended features leaf
+const EXTENDED_NX: u32 = 1 << 20;
+
+/// extended leaves with the processor's name in them
+const BRAND_LEAVES: [u32; 3] = [0x80000002, 0x80000003, 0x80000004];
+
+/// cr4 flags for the features we turn on
+const CR4_PSE: u32 = 1 << 4;
+const CR4_PGE: u32 = 1 << 7;
+
+/// what we found out about the cpu
+pub struct CpuInfo {
+    /// vendor string, like GenuineIntel
+    pub vendor: [u8; 12],
+
+    /// processor name, if the cpu has one
+    pub brand: [u8; 48],
+
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+
+    pub features: CpuFeatures,
+}
+
+impl CpuInfo {
+    const fn new() -> Self {
+        Self {
+            vendor: [0; 12],
+            brand: [0; 48],
+            family: 0,
+            model: 0,
+            stepping: 0,
+            features: CpuFeatures::None,
+        }
+    }
+
+    /// the vendor string, or "unknown" if there's no cpuid
+    pub fn vendor(&self) -> &str {
+        match core::str::from_utf8(&self.vendor).map(|vendor| vendor.trim_end_matches('\0')) {
+            Ok(vendor) if !vendor.is_empty() => vendor,
+            _ => "unknown",
+        }
+    }
+
+    /// the processor's name, if it has one
+    pub fn brand(&self) -> Option<&str> {
+        core::str::from_utf8(&self.brand).ok().map(|brand| brand.trim_matches(|c: char| c == '\0' || c == ' ')).filter(|brand| !brand.is_empty())
+    }
+}
+
+impl fmt::Display for CpuInfo {
+    /// formatted like linux's /proc/cpuinfo, so programs that read that can read this
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "processor\t: 0")?;
+        writeln!(f, "vendor_id\t: {}", self.vendor())?;
+        writeln!(f, "cpu family\t: {}", self.family)?;
+        writeln!(f, "model\t\t: {}", self.model)?;
+        writeln!(f, "model name\t: {}", self.brand().unwrap_or("unknown"))?;
+        writeln!(f, "stepping\t: {}", self.stepping)?;
+
+        write!(f, "flags\t\t:")?;
+        for (_, feature, name) in LEAF_1_FEATURES.iter() {
+            if self.features & *feature != 0 {
+                write!(f, " {}", name)?;
+            }
+        }
+        if self.features & CpuFeatures::Nx != 0 {
+            write!(f, " nx")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// what the cpu can do, filled in by init
+static mut CPU_INFO: CpuInfo = CpuInfo::new();
+
+/// gets what we found out about the cpu
+pub fn cpu_info() -> &'static CpuInfo {
+    unsafe { &CPU_INFO }
+}
+
+/// checks whether the cpu has all the given features
+pub fn has_feature(features: CpuFeatures) -> bool {
+    cpu_info().features & features == features
+}
+
+/// whether the cpu has a local APIC we can reach through its MSR
+pub fn has_apic() -> bool {
+    has_feature(CpuFeatures::Apic | CpuFeatures::Msr)
+}
+
+/// copies the registers of a cpuid leaf into a byte buffer in the order they spell out strings
+fn copy_registers(buf: &mut [u8], registers: [u32; 4]) {
+    for (chunk, register) in buf.chunks_mut(4).zip(registers.iter()) {
+        chunk.copy_from_slice(&register.to_le_bytes());
+    }
+}
+
+/// finds out what the cpu is and what it can do. cpus without cpuid (some 486s) are left with no features
+pub fn init() {
+    let info = unsafe { &mut CPU_INFO };
+
+    if !has_cpuid() {
+        warn!("cpu has no cpuid, assuming it has no optional features");
+        return;
+    }
+
+    unsafe {
+        let leaf_0 = __cpuid(0);
+        copy_registers(&mut info.vendor, [leaf_0.ebx, leaf_0.edx, leaf_0.ecx, 0]);
+
+        if leaf_0.eax >= 1 {
+            let leaf_1 = __cpuid(1);
+            let signature = leaf_1.eax;
+
+            info.stepping = signature & 0xf;
+            info.model = (signature >> 4) & 0xf;
+            info.family = (signature >> 8) & 0xf;
+
+            // newer cpus ran out of bits, so the rest is kept elsewhere
+            if info.family == 0xf {
+                info.family += (signature >> 20) & 0xff;
+            }
+            if info.family == 0x6 || info.family >= 0xf {
+                info.model |= ((signature >> 16) & 0xf) << 4;
+            }
+
+            for (bit, feature, _) in LEAF_1_FEATURES.iter() {
+                if leaf_1.edx & (1 << bit) != 0 {
+                    info.features |= *feature;
+                }
+            }
+        }
+
+        let max_extended = __cpuid(0x80000000).eax;
+
+        if max_extended >= EXTENDED_FEATURES_LEAF && __cpuid(EXTENDED_FEATURES_LEAF).edx & EXTENDED_NX != 0 {
+            info.features |= CpuFeatures::Nx;
+        }
+
+        if max_extended >= BRAND_LEAVES[2] {
+            for (chunk, leaf) in info.brand.chunks_mut(16).zip(BRAND_LEAVES.iter()) {
+                let result = __cpuid(*leaf);
+                copy_registers(chunk, [result.eax, result.ebx, result.ecx, result.edx]);
+            }
+        }
+    }
+
+    log!("cpu: {} family {} model {} stepping {} ({})", info.vendor(), info.family, info.model, info.stepping, info.brand().unwrap_or("unknown"));
+
+    if !has_feature(CpuFeatures::Fpu) {
+        warn!("cpu has no FPU, floating point in user mode won't work");
+    }
+}
+
+/// reads cr4
+fn read_cr4() -> u32 {
+    let cr4: u32;
+    unsafe { asm!("mov {0}, cr4", out(reg) cr4); }
+    cr4
+}
+
+/// writes cr4
+fn write_cr4(cr4: u32) {
+    unsafe { asm!("mov cr4, {0}", in(reg) cr4); }
+}
+
+/// lets page directory entries map 4mb pages, only call this if the cpu has PSE
+pub fn enable_large_pages() {
+    write_cr4(read_cr4() | CR4_PSE);
+}
+
+/// keeps pages marked global in the TLB across page directory switches, only call this if the cpu has PGE
+pub fn enable_global_pages() {
+    write_cr4(read_cr4() | CR4_PGE);
+}
+
+/// reads a model specific register
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    ((high as u64) << 32) | low as u64
+}
+
+/// writes a model specific register
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nomem, nostack, preserves_flags));
+}
@@ -0,0 +1,119 @@
+//! lazy x87 FPU context switching
+//! the FPU state is only saved and restored when a task other than the one that last used it tries to use it,
+//! which is caught with the task switched flag in cr0 and the device not available exception
+
+use core::arch::asm;
+use alloc::boxed::Box;
+use crate::tasks::{get_current_task, get_current_task_mut, get_task_mut, pid_to_id};
+
+/// cr0 monitor coprocessor flag, makes wait/fwait respect the task switched flag
+const CR0_MP: u32 = 1 << 1;
+
+/// cr0 emulation flag, if set all FPU instructions trap
+const CR0_EM: u32 = 1 << 2;
+
+/// cr0 task switched flag, if set the next FPU instruction traps
+const CR0_TS: u32 = 1 << 3;
+
+/// cr0 numeric error flag, enables native FPU error reporting
+const CR0_NE: u32 = 1 << 5;
+
+/// saved state of the FPU, in the format used by fnsave/frstor
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+pub struct FpuState([u8; 108]);
+
+/// pid of the task whose state is currently loaded in the FPU
+static mut FPU_OWNER: Option<usize> = None;
+
+/// reads cr0
+fn read_cr0() -> u32 {
+    let cr0: u32;
+    unsafe { asm!("mov {0}, cr0", out(reg) cr0); }
+    cr0
+}
+
+/// writes cr0
+fn write_cr0(cr0: u32) {
+    unsafe { asm!("mov cr0, {0}", in(reg) cr0); }
+}
+
+/// saves the FPU state into the provided buffer, this also reinitializes the FPU
+fn save(state: &mut FpuState) {
+    unsafe { asm!("fnsave [{0}]", in(reg) state.0.as_mut_ptr()); }
+}
+
+/// loads the FPU state from the provided buffer
+fn restore(state: &FpuState) {
+    unsafe { asm!("frstor [{0}]", in(reg) state.0.as_ptr()); }
+}
+
+/// initializes the FPU and sets up cr0 for lazy context switching
+pub fn init() {
+    write_cr0((read_cr0() & !(CR0_EM | CR0_TS)) | CR0_MP | CR0_NE);
+
+    unsafe { asm!("fninit"); }
+}
+
+/// called on every task switch, makes the next FPU instruction trap unless the new task owns the FPU
+pub fn switched_to(pid: usize) {
+    if unsafe { FPU_OWNER } == Some(pid) {
+        unsafe { asm!("clts"); }
+    } else {
+        write_cr0(read_cr0() | CR0_TS);
+    }
+}
+
+/// called when a task tries to use the FPU after a task switch, swaps the FPU state to the current task's state
+pub fn device_not_available() {
+    unsafe { asm!("clts"); }
+
+    let pid = get_current_task().map(|task| task.id);
+
+    if pid == unsafe { FPU_OWNER } {
+        return;
+    }
+
+    // save the state of the previous owner, if it's still around
+    if let Some(task) = unsafe { FPU_OWNER }.and_then(pid_to_id).and_then(get_task_mut) {
+        save(task.state.fpu_state.get_or_insert_with(|| Box::new(FpuState([0; 108]))));
+    }
+
+    // load our own state, or start with a clean FPU if we haven't used it before
+    match get_current_task_mut().and_then(|task| task.state.fpu_state.as_ref()) {
+        Some(state) => restore(state),
+        None => unsafe { asm!("fninit"); },
+    }
+
+    unsafe { FPU_OWNER = pid; }
+}
+
+/// makes sure the saved FPU state of the given task is up to date, i.e. before copying it
+pub fn sync(pid: usize) {
+    if unsafe { FPU_OWNER } != Some(pid) {
+        return;
+    }
+
+    if let Some(task) = pid_to_id(pid).and_then(get_task_mut) {
+        let cr0 = read_cr0();
+
+        unsafe { asm!("clts"); }
+
+        let state = task.state.fpu_state.get_or_insert_with(|| Box::new(FpuState([0; 108])));
+        save(state);
+
+        // fnsave reinitializes the FPU, so load the state back in
+        restore(state);
+
+        write_cr0(cr0);
+    }
+}
+
+/// called when a task exits, so its state isn't saved once it no longer exists
+pub fn task_exited(pid: usize) {
+    unsafe {
+        if FPU_OWNER == Some(pid) {
+            FPU_OWNER = None;
+        }
+    }
+}
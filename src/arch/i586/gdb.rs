@@ -0,0 +1,97 @@
+//! i586 side of the gdb stub: register layout, single stepping and the debug/breakpoint exception handlers
+
+use core::arch::asm;
+use super::ints::{IDT, IDTEntry, IDTFlags, Exceptions, SyscallRegisters};
+
+/// registers saved when the kernel stops for the debugger
+pub type Registers = SyscallRegisters;
+
+/// how many registers gdb's i386 target has: eax, ecx, edx, ebx, esp, ebp, esi, edi, eip, eflags, cs, ss, ds, es, fs, gs
+pub const NUM_REGISTERS: usize = 16;
+
+/// number of the instruction pointer in gdb's register list
+pub const PC_REGISTER: usize = 8;
+
+/// eflags bit that makes the cpu raise a debug exception after every instruction
+const TRAP_FLAG: u32 = 1 << 8;
+
+/// gets a register by its number in gdb's register list
+pub fn get_register(regs: &Registers, num: usize) -> Option<u32> {
+    Some(match num {
+        0 => regs.eax,
+        1 => regs.ecx,
+        2 => regs.edx,
+        3 => regs.ebx,
+        4 => regs.useresp, // the pusha copy is the handler's stack pointer, not the one we interrupted
+        5 => regs.ebp,
+        6 => regs.esi,
+        7 => regs.edi,
+        8 => regs.eip,
+        9 => regs.eflags,
+        10 => regs.cs,
+        11 => regs.ss,
+        12..=15 => regs.ds, // es, fs and gs always get the same value as ds
+        _ => return None,
+    })
+}
+
+/// sets a register by its number in gdb's register list, returning false if there's no such register.
+/// segment registers are left alone, changing them out from under the kernel isn't worth the trouble
+pub fn set_register(regs: &mut Registers, num: usize, value: u32) -> bool {
+    match num {
+        0 => regs.eax = value,
+        1 => regs.ecx = value,
+        2 => regs.edx = value,
+        3 => regs.ebx = value,
+        4 => regs.useresp = value,
+        5 => regs.ebp = value,
+        6 => regs.esi = value,
+        7 => regs.edi = value,
+        8 => regs.eip = value,
+        9 => regs.eflags = value,
+        10..=15 => (),
+        _ => return false,
+    }
+
+    true
+}
+
+/// makes the cpu stop again after running one instruction, or stops it from doing so
+pub fn set_single_step(regs: &mut Registers, enabled: bool) {
+    if enabled {
+        regs.eflags |= TRAP_FLAG;
+    } else {
+        regs.eflags &= !TRAP_FLAG;
+    }
+}
+
+/// stops and hands control to the debugger
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { asm!("int3"); }
+}
+
+extern "C" {
+    /// wrapper around debug_trap_handler for debug exceptions (single stepping)
+    fn debug_exception_wrapper() -> !;
+
+    /// wrapper around debug_trap_handler for breakpoints
+    fn breakpoint_exception_wrapper() -> !;
+}
+
+/// handles debug and breakpoint exceptions by stopping for the debugger
+#[no_mangle]
+pub unsafe extern "C" fn debug_trap_handler(_vector: u32, mut regs: Registers) {
+    // single stepping only lasts one instruction, gdb asks again if it wants more
+    set_single_step(&mut regs, false);
+
+    crate::gdb::handle_trap(&mut regs);
+}
+
+/// takes over the debug and breakpoint exceptions for the gdb stub
+pub unsafe fn init() {
+    IDT[Exceptions::Debug as usize] = IDTEntry::new(debug_exception_wrapper as *const (), IDTFlags::Exception);
+
+    // kernel only, so user tasks can't stop the whole kernel with int3
+    IDT[Exceptions::Breakpoint as usize] = IDTEntry::new(breakpoint_exception_wrapper as *const (), IDTFlags::Exception);
+}
@@ -9,6 +9,7 @@ use x86::dtables::{DescriptorTablePointer, lidt};
 use bitmask_enum::bitmask;
 use super::{
     halt,
+    backtrace::print_backtrace_from,
     paging::{PAGE_DIR, PageTableFlags},
 };
 use crate::{
@@ -228,7 +229,8 @@ impl fmt::Display for PageFaultErrorCode {
     }
 }
 
-unsafe fn generic_exception(name: &str, frame: ExceptionStackFrame) {
+unsafe fn generic_exception(name: &str, regs: &SyscallRegisters) {
+    let eip = regs.eip;
     let was_in_task = IN_TASK;
     IN_TASK = false;
 
@@ -244,8 +246,8 @@ unsafe fn generic_exception(name: &str, frame: ExceptionStackFrame) {
 
         let id = get_current_task().unwrap().id;
 
-        log!("{} in task {} (pid {}) @ {:#x}", name, CURRENT_TASK, id, frame.instruction_pointer);
-        log!("{:#?}", frame);
+        log!("{} in task {} (pid {}) @ {:#x}", name, CURRENT_TASK, id, eip);
+        log!("{:#?}", regs);
 
         if let Some(console) = get_console() {
             console.set_color(old_color);
@@ -257,8 +259,13 @@ unsafe fn generic_exception(name: &str, frame: ExceptionStackFrame) {
             console.set_color(PANIC_COLOR);
         }
 
-        log!("PANIC: {} @ {:#x}", name, frame.instruction_pointer);
-        log!("{:#?}", frame);
+        log!("PANIC: {} @ {:#x}", name, eip);
+        log!("{:#?}", regs);
+        print_backtrace_from(regs.ebp as usize);
+
+        if crate::monitor::enter(name, Some(regs)) {
+            return; // try again
+        }
         
         if cfg!(test) {
             exit_failure();
@@ -268,7 +275,8 @@ unsafe fn generic_exception(name: &str, frame: ExceptionStackFrame) {
     }
 }
 
-unsafe fn generic_exception_error_code(name: &str, frame: ExceptionStackFrame, error_code: u32) {
+unsafe fn generic_exception_error_code(name: &str, regs: &SyscallRegisters, error_code: u32) {
+    let eip = regs.eip;
     let was_in_task = IN_TASK;
     IN_TASK = false;
 
@@ -284,8 +292,8 @@ unsafe fn generic_exception_error_code(name: &str, frame: ExceptionStackFrame, e
 
         let id = get_current_task().unwrap().id;
 
-        log!("{} in task {} (pid {}) @ {:#x}, error code {:#x}", name, CURRENT_TASK, id, frame.instruction_pointer, error_code);
-        debug!("{:#?}", frame);
+        log!("{} in task {} (pid {}) @ {:#x}, error code {:#x}", name, CURRENT_TASK, id, eip, error_code);
+        debug!("{:#?}", regs);
 
         if let Some(console) = get_console() {
             console.set_color(old_color);
@@ -297,8 +305,13 @@ unsafe fn generic_exception_error_code(name: &str, frame: ExceptionStackFrame, e
             console.set_color(PANIC_COLOR);
         }
 
-        log!("PANIC: {} @ {:#x}, error code {:#x}", name, frame.instruction_pointer, error_code);
-        debug!("{:#?}", frame);
+        log!("PANIC: {} @ {:#x}, error code {:#x}", name, eip, error_code);
+        debug!("{:#?}", regs);
+        print_backtrace_from(regs.ebp as usize);
+
+        if crate::monitor::enter(name, Some(regs)) {
+            return; // try again
+        }
         
         if cfg!(test) {
             exit_failure();
@@ -309,8 +322,8 @@ unsafe fn generic_exception_error_code(name: &str, frame: ExceptionStackFrame, e
 }
 
 /// exception handler for divide by zero
-unsafe extern "x86-interrupt" fn divide_by_zero_handler(frame: ExceptionStackFrame) {
-    generic_exception("divide by zero", frame);
+unsafe fn divide_by_zero_handler(regs: &SyscallRegisters) {
+    generic_exception("divide by zero", regs);
 }
 
 /// exception handler for breakpoint
@@ -324,18 +337,19 @@ unsafe extern "x86-interrupt" fn overflow_handler(frame: ExceptionStackFrame) {
 }
 
 /// exception handler for bound range exceeded
-unsafe extern "x86-interrupt" fn bound_range_handler(frame: ExceptionStackFrame) {
-    generic_exception("bound range exceeded", frame);
+unsafe fn bound_range_handler(regs: &SyscallRegisters) {
+    generic_exception("bound range exceeded", regs);
 }
 
 /// exception handler for invalid opcode
-unsafe extern "x86-interrupt" fn invalid_opcode_handler(frame: ExceptionStackFrame) {
-    generic_exception("invalid opcode", frame);
+unsafe fn invalid_opcode_handler(regs: &SyscallRegisters) {
+    generic_exception("invalid opcode", regs);
 }
 
 /// exception handler for device not available
-unsafe extern "x86-interrupt" fn device_not_available_handler(frame: ExceptionStackFrame) {
-    generic_exception("device not available", frame);
+/// this is raised when a task uses the FPU after a task switch, so we can swap FPU state lazily
+unsafe extern "x86-interrupt" fn device_not_available_handler(_frame: ExceptionStackFrame) {
+    super::fpu::device_not_available();
 }
 
 /// exception handler for double fault
@@ -360,29 +374,29 @@ unsafe extern "x86-interrupt" fn double_fault_handler(frame: ExceptionStackFrame
 }
 
 /// exception handler for invalid tss
-unsafe extern "x86-interrupt" fn invalid_tss_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("invalid TSS", frame, error_code);
+unsafe fn invalid_tss_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("invalid TSS", regs, error_code);
 }
 
 /// exception handler for segment not present
-unsafe extern "x86-interrupt" fn segment_not_present_handler(frame: ExceptionStackFrame, error_code: u32) {
+unsafe fn segment_not_present_handler(regs: &SyscallRegisters, error_code: u32) {
     // TODO: swap/page file
 
-    generic_exception_error_code("segment not present", frame, error_code);
+    generic_exception_error_code("segment not present", regs, error_code);
 }
 
 /// exception handler for stack-segment fault
-unsafe extern "x86-interrupt" fn stack_segment_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("stack-segment fault", frame, error_code);
+unsafe fn stack_segment_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("stack-segment fault", regs, error_code);
 }
 
 /// exception handler for general protection fault
-unsafe extern "x86-interrupt" fn general_protection_fault_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("general protection fault", frame, error_code);
+unsafe fn general_protection_fault_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("general protection fault", regs, error_code);
 }
 
 /// exception handler for page fault
-unsafe extern "x86-interrupt" fn page_fault_handler(frame: ExceptionStackFrame, error_code: u32) {
+unsafe fn page_fault_handler(regs: &SyscallRegisters, error_code: u32) {
     let mut address: u32;
     asm!("mov {0}, cr2", out(reg) address);
 
@@ -401,7 +415,7 @@ unsafe extern "x86-interrupt" fn page_fault_handler(frame: ExceptionStackFrame,
         // is there a current task?
         if let Some(current) = get_current_task_mut() {
             // get current task's page entry for given address
-            if let Some(page) = current.state.pages.get_page(address, false) {
+            if let Some(page) = current.state.get_page(address, false) {
                 let page = &mut *page;
 
                 // get flags
@@ -448,7 +462,7 @@ unsafe extern "x86-interrupt" fn page_fault_handler(frame: ExceptionStackFrame,
                         page_to.set_unused();
 
                         // switch back to task's page directory
-                        current.state.pages.switch_to();
+                        current.state.pages.borrow().switch_to();
 
                         debug!("copied {:#x} -> {:#x}", old_addr, addr);
                         
@@ -466,50 +480,93 @@ unsafe extern "x86-interrupt" fn page_fault_handler(frame: ExceptionStackFrame,
             true // panic
         }
     {
-        generic_exception_error_code("page fault", frame, error_code);
+        generic_exception_error_code("page fault", regs, error_code);
     }
 
     IN_TASK = was_in_task;
 }
 
 /// exception handler for x87 floating point exception
-unsafe extern "x86-interrupt" fn x87_fpu_exception_handler(frame: ExceptionStackFrame) {
-    generic_exception("x87 FPU exception", frame);
+unsafe fn x87_fpu_exception_handler(regs: &SyscallRegisters) {
+    generic_exception("x87 FPU exception", regs);
 }
 
 /// exception handler for alignment check
-unsafe extern "x86-interrupt" fn alignment_check_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("alignment check", frame, error_code);
+unsafe fn alignment_check_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("alignment check", regs, error_code);
 }
 
 /// exception handler for SIMD floating point exception
-unsafe extern "x86-interrupt" fn simd_fpu_exception_handler(frame: ExceptionStackFrame) {
-    generic_exception("SIMD FPU exception", frame);
+unsafe fn simd_fpu_exception_handler(regs: &SyscallRegisters) {
+    generic_exception("SIMD FPU exception", regs);
 }
 
 /// exception handler for virtualization exception
-unsafe extern "x86-interrupt" fn virtualization_exception_handler(frame: ExceptionStackFrame) {
-    generic_exception("virtualization exception", frame);
+unsafe fn virtualization_exception_handler(regs: &SyscallRegisters) {
+    generic_exception("virtualization exception", regs);
 }
 
 /// exception handler for control protection exception
-unsafe extern "x86-interrupt" fn control_protection_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("control protection exception", frame, error_code);
+unsafe fn control_protection_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("control protection exception", regs, error_code);
 }
 
 /// exception handler for hypervisor injection exception
-unsafe extern "x86-interrupt" fn hypervisor_injection_handler(frame: ExceptionStackFrame) {
-    generic_exception("hypervisor injection exception", frame);
+unsafe fn hypervisor_injection_handler(regs: &SyscallRegisters) {
+    generic_exception("hypervisor injection exception", regs);
 }
 
 /// exception handler for VMM communication exception
-unsafe extern "x86-interrupt" fn vmm_exception_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("VMM commuication exception", frame, error_code);
+unsafe fn vmm_exception_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("VMM commuication exception", regs, error_code);
 }
 
 /// exception handler for security exception
-unsafe extern "x86-interrupt" fn security_exception_handler(frame: ExceptionStackFrame, error_code: u32) {
-    generic_exception_error_code("security exception", frame, error_code);
+unsafe fn security_exception_handler(regs: &SyscallRegisters, error_code: u32) {
+    generic_exception_error_code("security exception", regs, error_code);
+}
+
+/// handles the exceptions that come through the fault wrappers in tasks.S, which save every register
+#[no_mangle]
+pub unsafe extern "C" fn fault_handler(vector: u32, error_code: u32, regs: SyscallRegisters) {
+    match vector {
+        0 => divide_by_zero_handler(&regs),
+        5 => bound_range_handler(&regs),
+        6 => invalid_opcode_handler(&regs),
+        10 => invalid_tss_handler(&regs, error_code),
+        11 => segment_not_present_handler(&regs, error_code),
+        12 => stack_segment_handler(&regs, error_code),
+        13 => general_protection_fault_handler(&regs, error_code),
+        14 => page_fault_handler(&regs, error_code),
+        16 => x87_fpu_exception_handler(&regs),
+        17 => alignment_check_handler(&regs, error_code),
+        19 => simd_fpu_exception_handler(&regs),
+        20 => virtualization_exception_handler(&regs),
+        21 => control_protection_handler(&regs, error_code),
+        28 => hypervisor_injection_handler(&regs),
+        29 => vmm_exception_handler(&regs, error_code),
+        30 => security_exception_handler(&regs, error_code),
+        _ => generic_exception_error_code("unknown exception", &regs, error_code),
+    }
+}
+
+extern "C" {
+    fn divide_by_zero_wrapper() -> !;
+    fn bound_range_wrapper() -> !;
+    fn invalid_opcode_wrapper() -> !;
+    fn invalid_tss_wrapper() -> !;
+    fn segment_not_present_wrapper() -> !;
+    fn stack_segment_wrapper() -> !;
+    fn general_protection_fault_wrapper() -> !;
+    fn page_fault_wrapper() -> !;
+    fn x87_fpu_exception_wrapper() -> !;
+    fn alignment_check_wrapper() -> !;
+    fn simd_fpu_exception_wrapper() -> !;
+    fn virtualization_exception_wrapper() -> !;
+    fn control_protection_wrapper() -> !;
+    fn hypervisor_injection_wrapper() -> !;
+    fn vmm_exception_wrapper() -> !;
+    fn security_exception_wrapper() -> !;
 }
 
 /// structure of registers saved in the syscall handler
@@ -532,6 +589,36 @@ pub struct SyscallRegisters {
     pub ss: u32,
 }
 
+/// takes a snapshot of the registers where it's called, for places like the panic handler that don't have an interrupt frame
+#[inline(always)]
+pub fn current_registers() -> SyscallRegisters {
+    let mut regs = SyscallRegisters::default();
+
+    unsafe {
+        asm!(
+            "mov [{0} + 4], edi",
+            "mov [{0} + 8], esi",
+            "mov [{0} + 12], ebp",
+            "mov [{0} + 16], esp",
+            "mov [{0} + 20], ebx",
+            "mov [{0} + 24], edx",
+            "mov [{0} + 28], ecx",
+            "mov [{0} + 32], eax",
+            "call 2f",
+            "2: pop dword ptr [{0} + 36]",
+            "mov [{0} + 40], cs",
+            "pushfd",
+            "pop dword ptr [{0} + 44]",
+            "mov [{0} + 48], esp",
+            "mov [{0} + 52], ss",
+            "mov [{0}], ds",
+            in(reg) &mut regs as *mut SyscallRegisters,
+        );
+    }
+
+    regs
+}
+
 extern "C" {
     /// wrapper around syscall_handler to save and restore state
     fn syscall_handler_wrapper() -> !;
@@ -540,32 +627,29 @@ extern "C" {
 /// set up idt(r) and enable interrupts
 pub unsafe fn init() {
     // set up exception handlers
-    IDT[Exceptions::DivideByZero as usize] = IDTEntry::new(divide_by_zero_handler as *const (), IDTFlags::Exception);
+    IDT[Exceptions::DivideByZero as usize] = IDTEntry::new(divide_by_zero_wrapper as *const (), IDTFlags::Exception);
     IDT[Exceptions::Breakpoint as usize] = IDTEntry::new(breakpoint_handler as *const (), IDTFlags::Exception);
     IDT[Exceptions::Overflow as usize] = IDTEntry::new(overflow_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::BoundRangeExceeded as usize] = IDTEntry::new(bound_range_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::InvalidOpcode as usize] = IDTEntry::new(invalid_opcode_handler as *const (), IDTFlags::Exception);
+    IDT[Exceptions::BoundRangeExceeded as usize] = IDTEntry::new(bound_range_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::InvalidOpcode as usize] = IDTEntry::new(invalid_opcode_wrapper as *const (), IDTFlags::Exception);
     IDT[Exceptions::DeviceNotAvailable as usize] = IDTEntry::new(device_not_available_handler as *const (), IDTFlags::Exception);
     IDT[Exceptions::DoubleFault as usize] = IDTEntry::new(double_fault_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::InvalidTSS as usize] = IDTEntry::new(invalid_tss_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::SegmentNotPresent as usize] = IDTEntry::new(segment_not_present_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::StackSegmentFault as usize] = IDTEntry::new(stack_segment_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::GeneralProtectionFault as usize] = IDTEntry::new(general_protection_fault_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::PageFault as usize] = IDTEntry::new(page_fault_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::FloatingPoint as usize] = IDTEntry::new(x87_fpu_exception_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::AlignmentCheck as usize] = IDTEntry::new(alignment_check_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::SIMDFloatingPoint as usize] = IDTEntry::new(simd_fpu_exception_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::Virtualization as usize] = IDTEntry::new(virtualization_exception_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::ControlProtection as usize] = IDTEntry::new(control_protection_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::HypervisorInjection as usize] = IDTEntry::new(hypervisor_injection_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::VMMCommunication as usize] = IDTEntry::new(vmm_exception_handler as *const (), IDTFlags::Exception);
-    IDT[Exceptions::Security as usize] = IDTEntry::new(security_exception_handler as *const (), IDTFlags::Exception);
+    IDT[Exceptions::InvalidTSS as usize] = IDTEntry::new(invalid_tss_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::SegmentNotPresent as usize] = IDTEntry::new(segment_not_present_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::StackSegmentFault as usize] = IDTEntry::new(stack_segment_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::GeneralProtectionFault as usize] = IDTEntry::new(general_protection_fault_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::PageFault as usize] = IDTEntry::new(page_fault_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::FloatingPoint as usize] = IDTEntry::new(x87_fpu_exception_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::AlignmentCheck as usize] = IDTEntry::new(alignment_check_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::SIMDFloatingPoint as usize] = IDTEntry::new(simd_fpu_exception_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::Virtualization as usize] = IDTEntry::new(virtualization_exception_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::ControlProtection as usize] = IDTEntry::new(control_protection_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::HypervisorInjection as usize] = IDTEntry::new(hypervisor_injection_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::VMMCommunication as usize] = IDTEntry::new(vmm_exception_wrapper as *const (), IDTFlags::Exception);
+    IDT[Exceptions::Security as usize] = IDTEntry::new(security_exception_wrapper as *const (), IDTFlags::Exception);
 
     IDT[0x80] = IDTEntry::new(syscall_handler_wrapper as *const (), IDTFlags::Call);
 
-    // init irq handlers
-    crate::platform::irq::init();
-    
     // load interrupt handler table
     let idt_desc = DescriptorTablePointer::new(&IDT);
     lidt(&idt_desc);
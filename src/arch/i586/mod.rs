@@ -1,5 +1,10 @@
+pub mod backtrace;
+pub mod cpu;
+pub mod fpu;
+pub mod gdb;
 pub mod ints;
 pub mod gdt;
+pub mod multiboot;
 pub mod paging;
 pub mod syscalls;
 pub mod tasks;
@@ -10,6 +15,8 @@ use core::arch::asm;
 pub const MEM_TOP: usize = 0xffffffff;
 pub const LINKED_BASE: usize = 0xc0000000;
 pub const KHEAP_START: usize = LINKED_BASE + 0x10000000;
+pub const FRAMEBUFFER_START: usize = LINKED_BASE + 0x30000000;
+pub const PHYS_MAP_START: usize = LINKED_BASE + 0x38000000;
 
 pub const PAGE_SIZE: usize = 0x1000;
 pub const INV_PAGE_SIZE: usize = !(PAGE_SIZE - 1);
@@ -37,12 +44,73 @@ pub fn halt() -> ! {
     }
 }
 
+/// reads the control registers, for debugging
+pub fn control_registers() -> [(&'static str, usize); 4] {
+    let (cr0, cr2, cr3, cr4): (usize, usize, usize, usize);
+
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0);
+        asm!("mov {}, cr2", out(reg) cr2);
+        asm!("mov {}, cr3", out(reg) cr3);
+        asm!("mov {}, cr4", out(reg) cr4);
+    }
+
+    [("cr0", cr0), ("cr2", cr2), ("cr3", cr3), ("cr4", cr4)]
+}
+
+/// resets the cpu by loading an empty IDT and causing an exception, for when nothing nicer works
+pub fn triple_fault() -> ! {
+    let empty_idt = [0u16; 3];
+
+    unsafe {
+        asm!("lidt [{0}]", "int3", in(reg) &empty_idt);
+    }
+
+    halt();
+}
+
+/// lets interrupts in
+#[inline(always)]
+pub unsafe fn enable_interrupts() {
+    asm!("sti");
+}
+
+/// keeps interrupts out
+#[inline(always)]
+pub unsafe fn disable_interrupts() {
+    asm!("cli");
+}
+
+/// runs the provided closure with interrupts disabled, restoring the previous interrupt state afterwards
+pub fn without_interrupts<T, F: FnOnce() -> T>(func: F) -> T {
+    let flags: u32;
+
+    unsafe {
+        asm!("pushfd", "pop {0}", "cli", out(reg) flags);
+    }
+
+    let result = func();
+
+    // only re-enable interrupts if they were enabled before
+    if flags & 0x200 != 0 {
+        unsafe { asm!("sti"); }
+    }
+
+    result
+}
+
 /// initialize sub-modules
 pub fn init() {
+    debug!("detecting cpu features");
+    cpu::init(); // paging needs to know what it can use
     debug!("initializing GDT");
     unsafe { gdt::init(); }
     debug!("initializing interrupts");
     unsafe { ints::init(); }
     debug!("initializing paging");
     unsafe { paging::init(); }
+    debug!("initializing platform");
+    unsafe { crate::platform::init(); } // this maps firmware tables and interrupt controller registers, so it has to wait for paging
+    debug!("initializing FPU");
+    fpu::init();
 }
@@ -0,0 +1,133 @@
+//! multiboot information passed to us by the bootloader
+
+use super::LINKED_BASE;
+
+/// value the bootloader puts in eax to say it's multiboot compliant
+pub const BOOTLOADER_MAGIC: u32 = 0x2badb002;
+
+/// flag for the command line being present
+const INFO_CMDLINE: u32 = 1 << 2;
+
+/// flag for the framebuffer fields being present
+const INFO_FRAMEBUFFER: u32 = 1 << 12;
+
+/// framebuffer type for direct RGB color
+pub const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// amount of memory mapped at LINKED_BASE when we start, anything the bootloader gives us has to be in here for us to read it
+const MAPPED_LOW_MEMORY: u32 = 0x400000;
+
+extern "C" {
+    /// what the bootloader put in eax
+    static mboot_sig: u32;
+
+    /// physical address of the multiboot info structure
+    static mboot_ptr: u32;
+}
+
+/// multiboot info structure, only the fields we care about are named
+#[repr(C, packed)]
+struct MultibootInfo {
+    flags: u32,
+    _unused: [u32; 3],
+    cmdline: u32,
+    _unused_2: [u32; 17],
+    framebuffer_addr: u64,
+    framebuffer_pitch: u32,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    framebuffer_bpp: u8,
+    framebuffer_type: u8,
+    color_info: [u8; 6],
+}
+
+/// a framebuffer the bootloader set up for us
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    /// physical address of the framebuffer
+    pub addr: u64,
+
+    /// bytes per line
+    pub pitch: u32,
+
+    /// width in pixels
+    pub width: u32,
+
+    /// height in pixels
+    pub height: u32,
+
+    /// bits per pixel
+    pub bpp: u8,
+
+    /// type of framebuffer, 1 for RGB
+    pub kind: u8,
+
+    /// bit position and size of the red, green and blue fields of a pixel
+    pub red: (u8, u8),
+    pub green: (u8, u8),
+    pub blue: (u8, u8),
+}
+
+/// turns a physical address into a pointer, if it's in the memory we have mapped
+fn phys_to_virt<T>(addr: u32) -> Option<*const T> {
+    if addr != 0 && addr < MAPPED_LOW_MEMORY {
+        Some((addr as usize + LINKED_BASE) as *const T)
+    } else {
+        None
+    }
+}
+
+/// gets the multiboot info structure, if we were booted by a multiboot bootloader
+fn get_info() -> Option<&'static MultibootInfo> {
+    unsafe {
+        if mboot_sig != BOOTLOADER_MAGIC {
+            return None;
+        }
+
+        Some(&*phys_to_virt::<MultibootInfo>(mboot_ptr)?)
+    }
+}
+
+/// gets the framebuffer the bootloader set up, if it did
+pub fn get_framebuffer() -> Option<FramebufferInfo> {
+    let info = get_info()?;
+
+    if info.flags & INFO_FRAMEBUFFER == 0 {
+        return None;
+    }
+
+    let color = info.color_info;
+
+    Some(FramebufferInfo {
+        addr: info.framebuffer_addr,
+        pitch: info.framebuffer_pitch,
+        width: info.framebuffer_width,
+        height: info.framebuffer_height,
+        bpp: info.framebuffer_bpp,
+        kind: info.framebuffer_type,
+        red: (color[0], color[1]),
+        green: (color[2], color[3]),
+        blue: (color[4], color[5]),
+    })
+}
+
+
+/// gets the kernel command line the bootloader gave us
+pub fn get_cmdline() -> Option<&'static str> {
+    let info = get_info()?;
+
+    if info.flags & INFO_CMDLINE == 0 {
+        return None;
+    }
+
+    let start = phys_to_virt::<u8>(info.cmdline)?;
+
+    // don't go past the memory we have mapped looking for the end
+    let max_len = (MAPPED_LOW_MEMORY - info.cmdline) as usize;
+
+    unsafe {
+        let len = (0..max_len).find(|&i| *start.add(i) == 0).unwrap_or(max_len);
+
+        core::str::from_utf8(core::slice::from_raw_parts(start, len)).ok()
+    }
+}
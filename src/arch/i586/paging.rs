@@ -11,8 +11,10 @@ use bitmask_enum::bitmask;
 use crate::{
     util::array::BitSet,
     mm::KHEAP_INITIAL_SIZE,
+    tasks::get_current_task_mut,
 };
-use super::{MEM_SIZE, LINKED_BASE, KHEAP_START, PAGE_SIZE};
+use super::cpu::{CpuFeatures, has_feature, enable_large_pages, enable_global_pages};
+use super::{MEM_SIZE, MEM_TOP, LINKED_BASE, KHEAP_START, PHYS_MAP_START, PAGE_SIZE, INV_PAGE_SIZE};
 
 extern "C" {
     /// located at end of kernel, used for calculating placement address
@@ -261,7 +263,7 @@ pub enum PageDirFlags {
     Dirty               = Self(1 << 6),
 
     /// enables large (4mb) pages
-    /// no support currently
+    /// requires page size extension
     PageSize            = Self(1 << 7),
 
     /// tells cpu to not invalidate this page table entry in cache when page tables are reloaded
@@ -413,8 +415,13 @@ impl PageDirectory {
         }
     }
 
-    /// gets a page from the directory if one exists, makes one if requested
+    /// gets a page from the directory if one exists, makes one if requested.
+    /// addresses in large pages don't have a page table entry, so there's never one to get or make for them (see get_entry)
     pub fn get_page(&mut self, mut addr: u32, make: bool) -> Option<*mut PageTableEntry> {
+        if self.is_large_page(addr) {
+            return None;
+        }
+
         addr >>= 12;
         let table_idx = (addr / 1024) as usize;
         if !self.tables[table_idx].is_null() { // page table already exists
@@ -499,9 +506,28 @@ impl PageDirectory {
 
     /// transform a virtual address to a physical address
     pub fn virt_to_phys(&mut self, addr: u32) -> Option<u32> {
-        let page = self.get_page(addr, false)?;
+        let entry = self.get_entry(addr)?;
+
+        Some(entry.get_address() | (addr & (PAGE_SIZE as u32 - 1)))
+    }
+
+    /// checks whether the given address is mapped by a large page rather than a page table
+    pub fn is_large_page(&self, addr: u32) -> bool {
+        unsafe { (*self.tables_physical)[(addr >> 22) as usize] & PageDirFlags::PageSize.0 as u32 != 0 }
+    }
+
+    /// gets a copy of the page table entry for the given address, if there's a page table for it.
+    /// for addresses in large pages, this is what the entry would be if the large page was split up into a page table
+    pub fn get_entry(&mut self, addr: u32) -> Option<PageTableEntry> {
+        if self.is_large_page(addr) {
+            let dir_entry = unsafe { (*self.tables_physical)[(addr >> 22) as usize] };
+            let flags = (dir_entry & !(PageDirFlags::PageSize.0 as u32)) & 0x0fff;
+            let phys = (dir_entry & !(LARGE_PAGE_SIZE as u32 - 1)) | (addr & (LARGE_PAGE_SIZE as u32 - 1) & 0xfffff000);
+
+            return Some(PageTableEntry(phys | flags));
+        }
 
-        Some((unsafe { *page }).get_address() | (addr & (PAGE_SIZE as u32 - 1)))
+        self.get_page(addr, false).map(|page| unsafe { *page })
     }
 }
 
@@ -523,6 +549,59 @@ unsafe fn alloc_region(dir: &mut PageDirectory, start: u32, size: u32) {
     debug!("mapped {:#x} - {:#x}", start, end);
 }
 
+/// maps the first 4mb of kernel memory with one large page instead of a page table, if it's mapped straight to the first 4mb of ram.
+/// the page table is dropped from the directory so nothing can change entries the cpu isn't looking at any more
+unsafe fn use_large_kernel_page(dir: &mut PageDirectory) {
+    let idx = LINKED_BASE >> 22;
+
+    let table =
+        match dir.tables[idx].as_ref() {
+            Some(table) => table,
+            None => return,
+        };
+
+    if table.entries.iter().enumerate().any(|(i, entry)| entry.is_unused() || entry.get_address() != (i * PAGE_SIZE) as u32) {
+        debug!("kernel memory isn't contiguous, not using a large page");
+        return;
+    }
+
+    enable_large_pages();
+
+    // user mode still needs to get at kernel memory for now, see alloc_region
+    (*dir.tables_physical)[idx] = (PageDirFlags::Present | PageDirFlags::ReadWrite | PageDirFlags::UserSupervisor | PageDirFlags::PageSize).0 as u32;
+    dir.tables[idx] = core::ptr::null_mut();
+
+    debug!("mapped kernel memory with a large page");
+}
+
+/// marks everything mapped in kernel memory so far as global, so switching page directories doesn't flush it from the TLB.
+/// kernel memory is the same in every page directory, so this is safe
+unsafe fn use_global_pages(dir: &mut PageDirectory) {
+    for idx in (LINKED_BASE >> 22)..1024 {
+        if (*dir.tables_physical)[idx] & PageDirFlags::PageSize.0 as u32 != 0 {
+            (*dir.tables_physical)[idx] |= PageDirFlags::Global.0 as u32;
+            continue;
+        }
+
+        let table =
+            match dir.tables[idx].as_mut() {
+                Some(table) => table,
+                None => continue,
+            };
+
+        for entry in table.entries.iter_mut().filter(|entry| !entry.is_unused()) {
+            entry.set_flags(PageTableFlags::from(entry.get_flags()) | PageTableFlags::Global);
+        }
+    }
+
+    enable_global_pages();
+
+    debug!("kernel memory is global");
+}
+
+/// how much memory a large page maps
+pub const LARGE_PAGE_SIZE: usize = 0x400000;
+
 /// our page directory
 pub static mut PAGE_DIR: Option<PageDirectory> = None;
 
@@ -537,8 +616,6 @@ pub unsafe fn init() {
     // set up page directory struct
     let mut dir = PageDirectory::new();
 
-    // FIXME: map initial kernel memory allocations as global so they won't be invalidated from TLB flushes
-
     debug!("mapping kernel memory");
 
     // map first 4mb of memory to LINKED_BASE
@@ -549,6 +626,15 @@ pub unsafe fn init() {
     // map initial memory for kernel heap
     alloc_region(&mut dir, KHEAP_START as u32, KHEAP_INITIAL_SIZE as u32);
 
+    // make kernel memory cheaper to get at if the cpu can
+    if has_feature(CpuFeatures::Pse) {
+        use_large_kernel_page(&mut dir);
+    }
+
+    if has_feature(CpuFeatures::Pge) {
+        use_global_pages(&mut dir);
+    }
+
     debug!("creating page table");
 
     // holy fuck we need maybeuninit so bad
@@ -595,6 +681,54 @@ pub fn free_page(addr: usize) {
     }
 }
 
+/// maps memory that isn't ram, like a framebuffer, into kernel memory at the given address
+pub fn map_physical_region(addr: usize, phys: usize, size: usize) {
+    map_physical_pages(addr, phys, size, PageTableFlags::Present | PageTableFlags::ReadWrite);
+}
+
+/// maps physical pages into kernel memory with the given flags
+fn map_physical_pages(addr: usize, phys: usize, size: usize, flags: PageTableFlags) {
+    assert!(addr % PAGE_SIZE == 0 && phys % PAGE_SIZE == 0, "address is not page aligned");
+
+    let dir = unsafe { PAGE_DIR.as_mut().unwrap() };
+
+    for offset in (0..size).step_by(PAGE_SIZE) {
+        let page = dir.get_page((addr + offset).try_into().unwrap(), true).unwrap();
+
+        unsafe {
+            // these frames aren't in the frame set, so they won't ever be handed out as ram
+            (*page).set_flags(flags);
+            (*page).set_address((phys + offset) as u32);
+            asm!("invlpg [{0}]", in(reg) addr + offset);
+        }
+    }
+
+    dir.page_updates = dir.page_updates.wrapping_add(1);
+
+    debug!("mapped {:#x} - {:#x} to phys {:#x}", addr, addr + size, phys);
+}
+
+/// next free address in the physical mapping area
+static mut PHYS_MAP_NEXT: usize = PHYS_MAP_START;
+
+/// maps a range of physical memory that doesn't have to be page aligned, like device registers or firmware tables,
+/// returning where it ended up. caching is turned off since device registers can change under us.
+/// mappings are never taken down, so this is only for things that stick around
+pub fn map_physical(phys: usize, size: usize) -> usize {
+    let offset = phys % PAGE_SIZE;
+    let size = (offset + size + PAGE_SIZE - 1) & INV_PAGE_SIZE;
+
+    unsafe {
+        let addr = PHYS_MAP_NEXT;
+        assert!(MEM_TOP - addr >= size, "out of space for physical mappings");
+
+        map_physical_pages(addr, phys - offset, size, PageTableFlags::Present | PageTableFlags::ReadWrite | PageTableFlags::PageCacheDisable);
+        PHYS_MAP_NEXT += size;
+
+        addr + offset
+    }
+}
+
 /// convert virtual to physical address
 pub fn virt_to_phys(addr: usize) -> Option<usize> {
     let dir = unsafe { PAGE_DIR.as_mut()? };
@@ -610,6 +744,28 @@ pub fn virt_to_phys(addr: usize) -> Option<usize> {
     }
 }
 
+/// runs the closure on the page directory that's loaded right now, which is either the current task's or the kernel's
+pub fn with_current_directory<T>(func: impl FnOnce(&mut PageDirectory) -> T) -> Option<T> {
+    let cr3: u32;
+    unsafe { asm!("mov {}, cr3", out(reg) cr3); }
+
+    if let Some(task) = get_current_task_mut() {
+        if let Ok(mut dir) = task.state.pages.try_borrow_mut() {
+            if dir.tables_physical_addr == cr3 {
+                return Some(func(&mut dir));
+            }
+        }
+    }
+
+    unsafe { PAGE_DIR.as_mut().map(func) }
+}
+
+/// checks whether the given address is mapped in the address space that's loaded right now,
+/// for debugging code that wants to look at memory without faulting
+pub fn is_mapped(addr: usize) -> bool {
+    with_current_directory(|dir| dir.get_entry(addr as u32).map_or(false, |entry| !entry.is_unused())).unwrap_or(false)
+}
+
 /// bump allocate some memory
 pub unsafe fn bump_alloc<T>(size: usize, alignment: usize) -> *mut T {
     let offset: usize = 
@@ -1,83 +1,438 @@
 //! i586 syscall handlers
+//!
+//! syscalls are made with `int 0x80`, with the syscall number in eax and up to six arguments in
+//! ebx, ecx, edx, esi, edi and ebp (in that order). the return value is written to eax,
+//! with errors returned as negative errno values. all other registers are preserved
 
-use core::ffi::CStr;
+use alloc::{
+    string::String,
+    vec::Vec,
+};
 use crate::{
-    tasks::{IN_TASK, CURRENT_TASK, get_current_task, get_current_task_mut},
-    arch::tasks::{exit_current_task, fork_task},
+    tasks::{
+        IN_TASK, CURRENT_TASK, TASKS,
+        get_current_task, get_current_task_mut, wait_futex, wake_futex,
+    },
+    arch::{
+        LINKED_BASE, PAGE_SIZE,
+        tasks::{fork_task, create_thread, kill_task, kill_thread_group, return_to_task},
+        paging::PageTableFlags,
+    },
+    errno::Errno,
+    fs::{
+        MAX_FILES,
+        ops::{FileDescriptor, open as open_file},
+    },
+    platform::irq::{TICKS, TIMER_RATE},
+    signals::{Signal, send_signal_to_group},
+    syscalls::{FutexOp, SyscallResult, REBOOT_MAGIC1, REBOOT_MAGIC2},
 };
 use super::ints::SyscallRegisters;
 
 /// amount of syscalls we have
-pub const NUM_SYSCALLS: usize = 5;
+pub const NUM_SYSCALLS: usize = 19;
 
 /// list of function pointers for all available syscalls
-pub static SYSCALL_LIST: [fn(&mut SyscallRegisters) -> (); NUM_SYSCALLS] = [
+/// each one gets the registers of the calling task and the arguments it passed
+pub static SYSCALL_LIST: [fn(&mut SyscallRegisters, [u32; 6]) -> SyscallResult; NUM_SYSCALLS] = [
     is_computer_on,
     test_log,
     fork,
     exit,
     get_pid,
+    thread_create,
+    get_tid,
+    exit_group,
+    futex,
+    kill,
+    open,
+    close,
+    read,
+    write,
+    ioctl,
+    set_pgid,
+    get_pgid,
+    reboot,
+    power_off,
 ];
 
+/// maximum length of a path passed to a syscall
+const MAX_PATH_LEN: usize = 4096;
+
+/// checks that a buffer in the current task's memory is in user space and mapped, and gets it as a slice
+pub fn user_buffer(addr: u32, len: usize) -> Result<&'static mut [u8], Errno> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+
+    let end = (addr as usize).checked_add(len).ok_or(Errno::BadAddress)?;
+
+    if end > LINKED_BASE {
+        return Err(Errno::BadAddress);
+    }
+
+    let current = get_current_task_mut().ok_or(Errno::BadAddress)?;
+
+    let mut page = addr as usize & !(PAGE_SIZE - 1);
+
+    while page < end {
+        let entry = unsafe { *current.state.get_page(page as u32, false).ok_or(Errno::BadAddress)? };
+        let flags: PageTableFlags = entry.get_flags().into();
+
+        if flags & PageTableFlags::Present == 0 || flags & PageTableFlags::UserSupervisor == 0 {
+            return Err(Errno::BadAddress);
+        }
+
+        page += PAGE_SIZE;
+    }
+
+    Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) })
+}
+
+/// reads a null terminated string from the current task's memory
+fn user_string(addr: u32, max_len: usize) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut addr = addr as usize;
+
+    // check one page at a time, since the string can end before an unmapped page
+    loop {
+        let len = PAGE_SIZE - (addr % PAGE_SIZE);
+        let buf = user_buffer(addr as u32, len)?;
+
+        if let Some(end) = buf.iter().position(|c| *c == 0) {
+            bytes.extend_from_slice(&buf[..end]);
+            break;
+        }
+
+        bytes.extend_from_slice(buf);
+
+        if bytes.len() > max_len {
+            return Err(Errno::FilenameTooLong);
+        }
+
+        addr += len;
+    }
+
+    // characters can be split across pages, so the string can only be checked once it's all been copied
+    String::from_utf8(bytes).map_err(|_| Errno::IllegalSequence)
+}
+
+/// runs the provided function on one of the current task's open files
+fn with_file<T, F: FnOnce(&mut FileDescriptor) -> Result<T, Errno>>(fd: u32, func: F) -> Result<T, Errno> {
+    let current = get_current_task().ok_or(Errno::BadFile)?;
+    let mut files = current.files.borrow_mut();
+
+    match files.get_mut(fd as usize) {
+        Some(Some(file)) => func(file),
+        _ => Err(Errno::BadFile),
+    }
+}
+
 /// is computer on?
-/// sets ebx to 1 (true) if computer is on
+/// returns 1 (true) if computer is on
 /// if computer is off, behavior is undefined
-pub fn is_computer_on(regs: &mut SyscallRegisters) {
-    regs.ebx = 1;
+pub fn is_computer_on(_regs: &mut SyscallRegisters, _args: [u32; 6]) -> SyscallResult {
+    Ok(1)
 }
 
-/// test syscall- logs a string
-pub fn test_log(regs: &mut SyscallRegisters) {
-    unsafe { IN_TASK = false; }
-
-    let string = unsafe { CStr::from_ptr(regs.ebx as *const _).to_string_lossy().into_owned() };
+/// test syscall- logs the string pointed to by the first argument
+pub fn test_log(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let string = user_string(args[0], PAGE_SIZE)?;
     log!("{}", string);
 
-    unsafe { IN_TASK = true; }
+    Ok(0)
 }
 
 /// forks task
-/// sets ebx to the child pid in parent task, 0 in child task
-pub fn fork(regs: &mut SyscallRegisters) {
-    unsafe { IN_TASK = false; }
-
+/// returns the child pid in parent task, 0 in child task
+pub fn fork(regs: &mut SyscallRegisters, _args: [u32; 6]) -> SyscallResult {
     // save state of current task
     get_current_task_mut().unwrap().state.save(regs);
 
     let new_task =
         match fork_task(unsafe { CURRENT_TASK }) {
             Ok(task) => task,
-            Err(msg) => panic!("could not fork task: {}", msg), // do we really want to bring the whole system down if we can't fork a process?
+            Err(msg) => {
+                log!("could not fork task: {}", msg);
+                return Err(Errno::TryAgain);
+            },
         };
 
     // identify parent and child tasks
-    regs.ebx = new_task.id.try_into().unwrap();
-    new_task.state.registers.ebx = 0;
+    new_task.state.set_syscall_result(0);
+
+    Ok(new_task.id.try_into().unwrap())
+}
+
+/// exits the current thread, leaving the rest of its thread group running
+pub fn exit(_regs: &mut SyscallRegisters, _args: [u32; 6]) -> SyscallResult {
+    kill_task(unsafe { CURRENT_TASK }).map_err(Errno::Other)?;
+
+    Ok(0)
+}
+
+/// gets id of the current task's thread group (its process id)
+pub fn get_pid(_regs: &mut SyscallRegisters, _args: [u32; 6]) -> SyscallResult {
+    Ok(get_current_task().expect("no current task").tgid.try_into().unwrap())
+}
+
+/// creates a new thread in the current task's thread group, sharing its address space
+/// the first argument is the entry point, the second is the new thread's stack pointer and the third is passed to the new thread in ebx
+/// returns the new thread's id
+pub fn thread_create(regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    // save state of current task
+    get_current_task_mut().unwrap().state.save(regs);
+
+    match create_thread(unsafe { CURRENT_TASK }, args[0], args[1], args[2]) {
+        Ok(task) => Ok(task.id.try_into().unwrap()),
+        Err(msg) => {
+            log!("could not create thread: {}", msg);
+            Err(Errno::TryAgain)
+        },
+    }
+}
+
+/// gets id of the current thread
+pub fn get_tid(_regs: &mut SyscallRegisters, _args: [u32; 6]) -> SyscallResult {
+    Ok(get_current_task().expect("no current task").id.try_into().unwrap())
+}
+
+/// exits every thread in the current task's thread group
+pub fn exit_group(_regs: &mut SyscallRegisters, _args: [u32; 6]) -> SyscallResult {
+    kill_thread_group(unsafe { CURRENT_TASK }).map_err(Errno::Other)?;
+    kill_task(unsafe { CURRENT_TASK }).map_err(Errno::Other)?;
 
-    unsafe { IN_TASK = true; }
+    Ok(0)
 }
 
-/// exits task
-pub fn exit(_regs: &mut SyscallRegisters) {
-    exit_current_task();
+/// gets the physical address of a futex word in the current task's address space, if it's a valid futex word
+fn futex_key(addr: u32) -> Option<usize> {
+    // futex words have to be aligned and in user memory
+    if addr % 4 != 0 || addr as usize >= LINKED_BASE {
+        return None;
+    }
+
+    let current = get_current_task_mut()?;
+    let page = unsafe { *current.state.get_page(addr & !(PAGE_SIZE as u32 - 1), false)? };
+
+    let flags: PageTableFlags = page.get_flags().into();
+
+    if flags & PageTableFlags::Present != 0 {
+        Some((page.get_address() | (addr & (PAGE_SIZE as u32 - 1))) as usize)
+    } else {
+        None
+    }
 }
 
-/// gets id of current task
-/// sets ebx to id
-pub fn get_pid(regs: &mut SyscallRegisters) {
-    unsafe { IN_TASK = false; }
+/// waits on or wakes up tasks waiting on a futex
+/// the first argument is the address of the futex word and the second is the operation (see FutexOp)
+/// for FutexOp::Wait, the third argument is the value the futex word is expected to contain and the fourth is a timeout
+/// in milliseconds (0 for none). returns 0 once woken up, or fails with TryAgain if the value didn't match
+/// and ConnectionTimedOut if the timeout was reached
+/// for FutexOp::Wake, the third argument is the maximum amount of tasks to wake up. returns the amount of tasks woken up
+pub fn futex(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let key = futex_key(args[0]).ok_or(Errno::BadAddress)?;
+
+    match args[1] as usize {
+        op if op == FutexOp::Wait as usize => {
+            // we're still in the task's address space, and we know the word is mapped
+            let value = unsafe { *(args[0] as *const u32) };
+
+            let wake_at =
+                if args[3] == 0 {
+                    None
+                } else {
+                    // round up to the next tick, we don't want to wake up early
+                    let ticks = (args[3] as u64 * unsafe { TIMER_RATE } as u64 + 999) / 1000;
+                    Some(unsafe { TICKS } + ticks)
+                };
 
-    regs.ebx = get_current_task().expect("no current task").id.try_into().unwrap();
+            // we'll be switched away from on the way out, and if nothing wakes us up we time out.
+            // waking us up will overwrite this result
+            wait_futex(get_current_task_mut().unwrap(), key, value, args[2], wake_at)?;
 
-    unsafe { IN_TASK = true; }
+            Err(Errno::ConnectionTimedOut)
+        },
+        op if op == FutexOp::Wake as usize => {
+            Ok(wake_futex(key, args[2] as usize, |task| task.state.set_syscall_result(0)) as u32)
+        },
+        _ => Err(Errno::InvalidArgument),
+    }
 }
 
-/// platform-specific syscall handler
+/// sends a signal to every thread in a thread group
+/// the first argument is the id of the thread group and the second is the signal number
+pub fn kill(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let signal = Signal::try_from(args[1]).map_err(|_| Errno::InvalidArgument)?;
+
+    send_signal_to_group(args[0] as usize, signal)?;
+
+    Ok(0)
+}
+
+/// opens the file at the path pointed to by the first argument
+/// returns the new file descriptor
+pub fn open(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let path = user_string(args[0], MAX_PATH_LEN)?;
+
+    // paths in the vfs tree aren't absolute
+    let file = open_file(path.trim_start_matches('/'))?;
+
+    let current = get_current_task().ok_or(Errno::BadFile)?;
+    let mut files = current.files.borrow_mut();
+
+    // use the lowest free file descriptor
+    let fd =
+        match files.iter().position(|file| file.is_none()) {
+            Some(fd) => fd,
+            None => {
+                if files.len() >= MAX_FILES {
+                    return Err(Errno::FileDescTooBig);
+                }
+                files.push(None);
+                files.len() - 1
+            },
+        };
+
+    files[fd] = Some(file);
+
+    Ok(fd as u32)
+}
+
+/// closes the file descriptor given in the first argument
+pub fn close(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let current = get_current_task().ok_or(Errno::BadFile)?;
+    let mut files = current.files.borrow_mut();
+
+    match files.get_mut(args[0] as usize) {
+        Some(file @ Some(_)) => {
+            *file = None;
+            Ok(0)
+        },
+        _ => Err(Errno::BadFile),
+    }
+}
+
+/// reads from the file descriptor given in the first argument into the buffer pointed to by the second argument,
+/// with the third argument being the size of the buffer
+/// returns the amount of bytes read. if the file has nothing to read yet, this blocks until it does
+pub fn read(regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let buf = user_buffer(args[1], args[2] as usize)?;
+
+    match with_file(args[0], |file| file.read(buf)) {
+        Ok(amt) => Ok(amt as u32),
+        Err(Errno::OperationWouldBlock) if !get_current_task().map_or(true, |task| task.is_runnable()) => {
+            // the file blocked us, so run this syscall again once we're woken up
+            regs.eip -= 2; // size of int 0x80
+            Ok(regs.eax)
+        },
+        Err(err) => Err(err),
+    }
+}
+
+/// writes the buffer pointed to by the second argument to the file descriptor given in the first argument,
+/// with the third argument being the size of the buffer
+/// returns the amount of bytes written
+pub fn write(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let buf = user_buffer(args[1], args[2] as usize)?;
+
+    with_file(args[0], |file| file.write(buf)).map(|amt| amt as u32)
+}
+
+/// performs a device specific operation on the file descriptor given in the first argument
+/// the second argument is the request, and the third is its argument
+pub fn ioctl(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    with_file(args[0], |file| file.ioctl(args[1], args[2]))
+}
+
+/// moves the process given in the first argument (or the current process if 0) into the process group given in the second argument
+/// if the process group is 0, the process' id is used, making it the leader of a new group
+pub fn set_pgid(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let pid = if args[0] == 0 { get_current_task().expect("no current task").tgid } else { args[0] as usize };
+    let pgid = if args[1] == 0 { pid } else { args[1] as usize };
+
+    let mut found = false;
+
+    for task in unsafe { TASKS.iter_mut() }.filter(|task| task.tgid == pid) {
+        task.pgid = pgid;
+        found = true;
+    }
+
+    if found {
+        Ok(0)
+    } else {
+        Err(Errno::NoSuchProcess)
+    }
+}
+
+/// gets the process group of the process given in the first argument, or the current process if 0
+pub fn get_pgid(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    let pgid =
+        if args[0] == 0 {
+            get_current_task().expect("no current task").pgid
+        } else {
+            unsafe { TASKS.iter() }.find(|task| task.tgid == args[0] as usize).ok_or(Errno::NoSuchProcess)?.pgid
+        };
+
+    Ok(pgid.try_into().unwrap())
+}
+
+/// checks the magic numbers the reboot and power off syscalls have to be given
+fn check_reboot_magic(args: [u32; 6]) -> Result<(), Errno> {
+    if args[0] == REBOOT_MAGIC1 && args[1] == REBOOT_MAGIC2 {
+        Ok(())
+    } else {
+        Err(Errno::InvalidArgument)
+    }
+}
+
+/// restarts the computer, doesn't return if the first two arguments are REBOOT_MAGIC1 and REBOOT_MAGIC2
+pub fn reboot(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    check_reboot_magic(args)?;
+    crate::platform::reboot();
+}
+
+/// turns the computer off, doesn't return if the first two arguments are REBOOT_MAGIC1 and REBOOT_MAGIC2
+pub fn power_off(_regs: &mut SyscallRegisters, args: [u32; 6]) -> SyscallResult {
+    check_reboot_magic(args)?;
+    crate::platform::power_off();
+}
+
+/// converts the result of a syscall into the value returned in eax
+pub fn result_to_register(result: SyscallResult) -> u32 {
+    match result {
+        Ok(value) => value,
+        Err(err) => (-(err.number() as i32)) as u32,
+    }
+}
+
+/// runs the syscall the given registers ask for, and writes its result to eax
+pub fn dispatch(regs: &mut SyscallRegisters) {
+    let syscall_num = regs.eax as usize;
+    let args = [regs.ebx, regs.ecx, regs.edx, regs.esi, regs.edi, regs.ebp];
+
+    let result =
+        match SYSCALL_LIST.get(syscall_num) {
+            Some(handler) => handler(regs, args),
+            None => Err(Errno::FuncNotSupported),
+        };
+
+    trace!("syscall {} {:x?} -> {:?}", syscall_num, args, result);
+
+    regs.eax = result_to_register(result);
+}
+
+/// platform-specific syscall handler, called by syscall_handler_wrapper
 #[no_mangle]
 pub unsafe extern "C" fn syscall_handler(mut regs: SyscallRegisters) {
-    let syscall_num = regs.eax as usize;
+    // we're in the kernel now, so we don't want to be preempted
+    IN_TASK = false;
 
-    if syscall_num < NUM_SYSCALLS {
-        SYSCALL_LIST[syscall_num](&mut regs);
-    }
+    // this has to happen before switching tasks, since a blocked task's saved registers are what it'll get back
+    dispatch(&mut regs);
+
+    // handle signals and switch away from the current task if it exited or blocked
+    return_to_task(&mut regs);
+
+    IN_TASK = true;
 }
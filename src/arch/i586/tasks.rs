@@ -1,23 +1,51 @@
 //! low level i586-specific task switching
 
 use super::{
+    fpu::{self, FpuState},
     ints::SyscallRegisters,
-    paging::{PAGE_DIR, PageDirectory, PageTableFlags},
+    paging::{PAGE_DIR, PageDirectory, PageTableEntry, PageTableFlags},
+};
+use core::{
+    arch::asm,
+    cell::RefCell,
+};
+use alloc::{
+    boxed::Box,
+    rc::Rc,
+    vec,
+    vec::Vec,
 };
-use core::arch::asm;
 use crate::{
     arch::{PAGE_SIZE, LINKED_BASE},
+    signals::handle_signals,
     tasks::{
-        CURRENT_TASK, IN_TASK,
+        CURRENT_TASK, CURRENT_TERMINATED, IN_TASK, NEED_RESCHED,
         Task,
-        remove_task, get_task, get_task_mut, add_task, pid_to_id,
+        TASKS,
+        remove_task, get_task_mut, get_current_task_mut, add_task, pid_to_id, switch_tasks,
     },
 };
 
+/// size of the stack given to each kernel thread
+pub const KERNEL_THREAD_STACK_SIZE: usize = 0x4000; // 16k
+
+/// stack of the last kernel thread that exited while running
+/// it can't be freed right away since the thread is still using it, so it's freed when the next one exits
+pub static mut DEAD_STACK: Option<Vec<u32>> = None;
+
 pub struct TaskState {
     pub registers: SyscallRegisters,
-    pub pages: PageDirectory,
+
+    /// page directory of this task, shared between all threads in a thread group
+    pub pages: Rc<RefCell<PageDirectory>>,
+
     pub page_updates: usize,
+
+    /// stack for kernel threads, user tasks don't have one
+    pub kernel_stack: Option<Vec<u32>>,
+
+    /// saved FPU state, allocated once the task has used the FPU and another task takes it over
+    pub fpu_state: Option<Box<FpuState>>,
 }
 
 impl TaskState {
@@ -27,8 +55,10 @@ impl TaskState {
 
         let mut state = Self {
             registers: Default::default(),
-            pages: PageDirectory::new(),
+            pages: Rc::new(RefCell::new(PageDirectory::new())),
             page_updates: global_dir.page_updates,
+            kernel_stack: None,
+            fpu_state: None,
         };
 
         state.copy_pages_from(global_dir, 0, 1024);
@@ -36,6 +66,48 @@ impl TaskState {
         state
     }
 
+    /// creates a new task state for a kernel thread, which runs the provided function in kernel mode on its own stack
+    pub fn new_kernel_thread(func: Box<dyn FnOnce() + Send>) -> Self {
+        let mut state = Self::new();
+
+        let mut stack = vec![0_u32; KERNEL_THREAD_STACK_SIZE / 4];
+
+        // the function is passed to the entry point as its only argument, right above a fake return address
+        let len = stack.len();
+        stack[len - 1] = Box::into_raw(Box::new(func)) as u32;
+        stack[len - 2] = 0;
+
+        let stack_pointer = (stack.as_ptr() as u32) + ((len - 2) * 4) as u32;
+
+        state.registers = SyscallRegisters {
+            ds: 0x10, // kernel data segment
+            eip: kernel_thread_entry as *const () as u32,
+            cs: 0x08, // kernel code segment
+            eflags: 0x202, // interrupts enabled
+            useresp: stack_pointer,
+            ss: 0x10,
+            ..Default::default()
+        };
+        state.kernel_stack = Some(stack);
+
+        state
+    }
+
+    /// checks whether this task runs in kernel mode
+    pub fn is_kernel_thread(&self) -> bool {
+        self.registers.cs & 3 == 0
+    }
+
+    /// gets a page from this task's page directory if one exists, makes one if requested
+    pub fn get_page(&mut self, addr: u32, make: bool) -> Option<*mut PageTableEntry> {
+        self.pages.borrow_mut().get_page(addr, make)
+    }
+
+    /// sets the value returned to this task by the syscall it's currently in
+    pub fn set_syscall_result(&mut self, value: u32) {
+        self.registers.eax = value;
+    }
+
     /// copies registers to task state
     pub fn save(&mut self, regs: &SyscallRegisters) {
         self.registers = *regs;
@@ -51,11 +123,13 @@ impl TaskState {
         assert!(start <= end);
         assert!(end <= 1024);
 
+        let mut pages = self.pages.borrow_mut();
+
         for i in start..end {
-            self.pages.tables[i] = dir.tables[i];
+            pages.tables[i] = dir.tables[i];
 
             unsafe {
-                (*self.pages.tables_physical)[i] = (*dir.tables_physical)[i];
+                (*pages.tables_physical)[i] = (*dir.tables_physical)[i];
             }
         }
     }
@@ -67,16 +141,18 @@ impl TaskState {
         assert!(start <= end);
         assert!(end <= 1024);
 
+        let mut pages = self.pages.borrow_mut();
+
         for i in start..end {
             if dir.tables[i].is_null() {
-                self.pages.tables[i] = core::ptr::null_mut();
+                pages.tables[i] = core::ptr::null_mut();
 
                 unsafe {
-                    (*self.pages.tables_physical)[i] = 0;
+                    (*pages.tables_physical)[i] = 0;
                 }
             } else {
                 for addr in ((i << 22)..((i + 1) << 22)).step_by(PAGE_SIZE) {
-                    let page = unsafe { &mut *self.pages.get_page(addr as u32, true).expect("couldn't create page table") };
+                    let page = unsafe { &mut *pages.get_page(addr as u32, true).expect("couldn't create page table") };
                     let orig_page = unsafe { &mut *dir.get_page(addr as u32, false).expect("couldn't get page table") };
 
                     // disable write flag, enable copy on write
@@ -99,7 +175,7 @@ impl TaskState {
     pub fn alloc_page(&mut self, addr: u32, is_kernel: bool, is_writeable: bool, invalidate: bool) {
         assert!(addr % PAGE_SIZE as u32 == 0, "address is not page aligned");
 
-        let page = self.pages.get_page(addr, true).unwrap();
+        let page = self.get_page(addr, true).unwrap();
 
         unsafe {
             let dir = PAGE_DIR.as_mut().unwrap();
@@ -116,7 +192,7 @@ impl TaskState {
     pub fn free_page(&mut self, addr: u32) {
         assert!(addr % PAGE_SIZE as u32 == 0, "address is not page aligned");
 
-        if let Some(page) = self.pages.get_page(addr, false) {
+        if let Some(page) = self.get_page(addr, false) {
             unsafe {
                 let dir = PAGE_DIR.as_mut().unwrap();
 
@@ -134,8 +210,78 @@ impl Default for TaskState {
     }
 }
 
-/// exits current task, cpu idles until next task switch
-pub fn exit_current_task() {
+/// saves the state of the current task from the given registers, switches to the next task and loads its state into them
+pub unsafe fn context_switch(regs: &mut SyscallRegisters) {
+    // has the current task been terminated?
+    if CURRENT_TERMINATED {
+        // it no longer exists, so all we need to do is clear the flag
+        CURRENT_TERMINATED = false;
+    } else {
+        // save state of current task
+        get_current_task_mut().expect("no tasks?").state.save(regs);
+    }
+
+    // switch to next task
+    switch_tasks();
+
+    // load state of new current task
+    let current = get_current_task_mut().expect("no tasks?");
+
+    current.state.load(regs);
+
+    // get reference to global page directory
+    let dir = PAGE_DIR.as_mut().expect("paging not initialized");
+
+    // has the kernel page directory been updated?
+    if current.state.page_updates != dir.page_updates {
+        // get page directory index of the start of the kernel's address space
+        let idx = LINKED_BASE >> 22;
+
+        // copy from the kernel's page directory to the task's
+        current.state.copy_pages_from(dir, idx, 1024);
+
+        // the task's page directory is now up to date (at least for our purposes)
+        current.state.page_updates = dir.page_updates;
+    }
+
+    // switch to task's page directory
+    current.state.pages.borrow().switch_to();
+
+    // make sure the FPU state gets swapped if the new task uses it
+    fpu::switched_to(current.id);
+}
+
+/// called on the way out of the kernel, before returning to the current task with the given registers
+/// handles pending signals, and switches to another task if the current one can't or shouldn't keep running
+pub unsafe fn return_to_task(regs: &mut SyscallRegisters) {
+    let mut switch = NEED_RESCHED || CURRENT_TERMINATED || !get_current_task_mut().map_or(false, |task| task.is_runnable());
+
+    loop {
+        if switch {
+            NEED_RESCHED = false;
+            context_switch(regs);
+        }
+
+        // the task we're returning to might have been killed or stopped by a signal, in which case we have to try again
+        if handle_signals() {
+            switch = true;
+        } else {
+            break;
+        }
+    }
+}
+
+/// entry point for kernel threads, runs the thread's function and exits once it returns
+extern "C" fn kernel_thread_entry(func: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let func = unsafe { Box::from_raw(func) };
+
+    func();
+
+    exit_current_task();
+}
+
+/// exits current task from outside of a syscall (i.e. in an exception handler), cpu idles until next task switch
+pub fn exit_current_task() -> ! {
     if let Err(msg) = kill_task(unsafe { CURRENT_TASK }) {
         panic!("couldn't kill task: {}", msg);
     }
@@ -152,11 +298,19 @@ pub fn exit_current_task() {
 pub fn kill_task(id: usize) -> Result<(), &'static str> {
     // TODO: signals, etc
 
-    if let Some(task) = get_task(id) {
+    if let Some(task) = get_task_mut(id) {
         let pid = task.id;
+
+        // if we're killing the current task and it's a kernel thread, we're still running on its stack
+        // so keep it around until the next one exits, by which point we'll have switched away from it
+        if id == unsafe { CURRENT_TASK } && task.state.kernel_stack.is_some() {
+            unsafe { DEAD_STACK = task.state.kernel_stack.take(); }
+        }
         
         remove_task(id);
 
+        fpu::task_exited(pid);
+
         log!("task {} (pid {}) exited", id, pid);
 
         Ok(())
@@ -183,21 +337,76 @@ pub fn fork_task(id: usize) -> Result<&'static mut Task, &'static str> {
             return Err("couldn't get task")
         };
 
+    // make sure the parent's FPU state is saved so the child gets an up to date copy of it
+    fpu::sync(current.id);
+
     // create new task state
     let mut state = TaskState {
         registers: current.state.registers,
-        pages: PageDirectory::new(),
+        pages: Rc::new(RefCell::new(PageDirectory::new())),
         page_updates: current.state.page_updates,
+        kernel_stack: None,
+        fpu_state: current.state.fpu_state.clone(),
     };
 
     // copy kernel pages, copy parent task's pages as copy on write
     let kernel_start = LINKED_BASE >> 22;
     let dir = unsafe { PAGE_DIR.as_mut().expect("no paging?") };
-    state.copy_on_write_from(&mut current.state.pages, 0, kernel_start);
+    state.copy_on_write_from(&mut current.state.pages.borrow_mut(), 0, kernel_start);
     state.copy_pages_from(dir, kernel_start, 1024);
     
     // create new task with provided state
-    let task = Task::from_state(state);
+    let mut task = Task::from_state(state);
+    task.pgid = current.pgid;
+
+    // the child gets its own file table, but its files are the parent's open files, offsets and all
+    task.files = Rc::new(RefCell::new(
+        current.files.borrow().iter()
+            .map(|file| file.as_ref().and_then(|file| file.duplicate().ok()))
+            .collect()
+    ));
+
+    let id = task.id;
+
+    add_task(task);
+
+    // return reference to new task
+    Ok(get_task_mut(pid_to_id(id).unwrap()).unwrap())
+}
+
+/// creates a new thread in the same thread group as the given task, sharing its address space
+/// the new thread starts executing at the given entry point with the given stack pointer, and with arg in ebx
+pub fn create_thread(id: usize, entry: u32, stack: u32, arg: u32) -> Result<&'static mut Task, &'static str> {
+    let current =
+        if let Some(task) = get_task_mut(id) {
+            task
+        } else {
+            return Err("couldn't get task")
+        };
+
+    if current.state.is_kernel_thread() {
+        return Err("can't create threads from kernel threads");
+    }
+
+    // the new thread has its own registers and stack, but everything else is shared
+    let mut registers = current.state.registers;
+    registers.eip = entry;
+    registers.useresp = stack;
+    registers.ebx = arg;
+
+    let state = TaskState {
+        registers,
+        pages: current.state.pages.clone(),
+        page_updates: current.state.page_updates,
+        kernel_stack: None,
+        fpu_state: None,
+    };
+
+    let mut task = Task::from_state(state);
+    task.tgid = current.tgid;
+    task.pgid = current.pgid;
+    task.files = current.files.clone();
+
     let id = task.id;
 
     add_task(task);
@@ -205,3 +414,20 @@ pub fn fork_task(id: usize) -> Result<&'static mut Task, &'static str> {
     // return reference to new task
     Ok(get_task_mut(pid_to_id(id).unwrap()).unwrap())
 }
+
+/// kills every task in the thread group of the given task except for the given task itself
+pub fn kill_thread_group(id: usize) -> Result<(), &'static str> {
+    let (pid, tgid) =
+        if let Some(task) = get_task_mut(id) {
+            (task.id, task.tgid)
+        } else {
+            return Err("couldn't get task")
+        };
+
+    // indices shift around as tasks are removed, so look the next one up every time
+    while let Some(other) = unsafe { TASKS.iter().position(|task| task.tgid == tgid && task.id != pid) } {
+        kill_task(other)?;
+    }
+
+    Ok(())
+}
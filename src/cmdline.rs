@@ -0,0 +1,253 @@
+//! kernel command line
+//! the bootloader hands us a string like `console=ttyS0 loglevel=7`, and subsystems look up their parameters in it
+
+use crate::arch::multiboot;
+
+/// maximum length of the command line we keep, anything past this is cut off
+pub const MAX_CMDLINE_LEN: usize = 1024;
+
+/// types of values a parameter can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    /// on or off, `name` on its own means on
+    Bool,
+
+    /// an unsigned number, in decimal or hex with 0x
+    Int,
+
+    /// any string
+    String,
+}
+
+/// a parameter we know about
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ParamType,
+
+    /// value used when the parameter isn't given, if any
+    pub default: Option<&'static str>,
+
+    pub description: &'static str,
+}
+
+/// all the parameters we know about
+pub const PARAMS: &[Param] = &[
+    Param {
+        name: "loglevel",
+        kind: ParamType::String,
+        default: Some("info"),
+        description: "least important kernel messages that get logged: error, warn, info, debug or trace (or 0-8 like linux)",
+    },
+    Param {
+        name: "console_loglevel",
+        kind: ParamType::String,
+        default: Some("info"),
+        description: "least important kernel messages that show up on the console, everything logged goes to serial",
+    },
+    Param {
+        name: "log_filter",
+        kind: ParamType::String,
+        default: None,
+        description: "log levels for specific modules, like fs:debug,arch::i586::paging:trace",
+    },
+    Param {
+        name: "console",
+        kind: ParamType::String,
+        default: Some("tty1"),
+        description: "tty used for /dev/console and the first process, like tty2 or ttyS0",
+    },
+    Param {
+        name: "init",
+        kind: ParamType::String,
+        default: Some("/bin/init"),
+        description: "program to run as the first process",
+    },
+    Param {
+        name: "timer_hz",
+        kind: ParamType::Int,
+        default: Some("100"),
+        description: "how many times a second the timer interrupt fires",
+    },
+    Param {
+        name: "noapic",
+        kind: ParamType::Bool,
+        default: Some("false"),
+        description: "use the 8259 PIC and the PIT even if there's an APIC",
+    },
+    Param {
+        name: "vga",
+        kind: ParamType::String,
+        default: Some("80x25"),
+        description: "VGA text mode to switch to at boot: 80x25, 80x50, 90x30 or 90x60. ignored when there's a framebuffer",
+    },
+    Param {
+        name: "font",
+        kind: ParamType::String,
+        default: Some("bios"),
+        description: "font for VGA text mode: bios, or fixed for the built in 8x13 font (only fits modes with 16 scanline characters)",
+    },
+    Param {
+        name: "scrollback",
+        kind: ParamType::Int,
+        default: Some("256"),
+        description: "lines of scrollback kept by each virtual console, 0 turns it off",
+    },
+    Param {
+        name: "root",
+        kind: ParamType::String,
+        default: None,
+        description: "device to mount as the root filesystem",
+    },
+    Param {
+        name: "gdb",
+        kind: ParamType::String,
+        default: None,
+        description: "serial port to run the gdb stub on, like ttyS1. ttyS0 carries log output so it's a bad pick",
+    },
+    Param {
+        name: "gdb_wait",
+        kind: ParamType::Bool,
+        default: Some("false"),
+        description: "stop at boot and wait for gdb to attach",
+    },
+    Param {
+        name: "monitor",
+        kind: ParamType::String,
+        default: None,
+        description: "where to run the kernel monitor when something goes wrong: console or a serial port like ttyS0",
+    },
+];
+
+/// copy of the command line, the bootloader's copy could get overwritten once we start allocating memory
+static mut CMDLINE: [u8; MAX_CMDLINE_LEN] = [0; MAX_CMDLINE_LEN];
+
+/// length of the command line
+static mut CMDLINE_LEN: usize = 0;
+
+/// gets the command line
+pub fn get_cmdline() -> &'static str {
+    unsafe { core::str::from_utf8(&CMDLINE[..CMDLINE_LEN]).unwrap_or("") }
+}
+
+/// splits a command line into parameters and their values, values can be quoted to include spaces
+pub fn parse_params(cmdline: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let mut rest = cmdline;
+
+    core::iter::from_fn(move || {
+        rest = rest.trim_start();
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        // find the end of this parameter, skipping over spaces in quotes
+        let mut in_quotes = false;
+        let end = rest.char_indices().find(|&(_, c)| {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            }
+            c.is_whitespace() && !in_quotes
+        }).map_or(rest.len(), |(i, _)| i);
+
+        let param = &rest[..end];
+        rest = &rest[end..];
+
+        Some(match param.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim_matches('"'))),
+            None => (param, None),
+        })
+    })
+}
+
+/// finds a parameter in the command line, returning the last value given for it
+/// parameters without a value give an empty string
+fn find_param(name: &str) -> Option<&'static str> {
+    parse_params(get_cmdline()).filter(|(param, _)| *param == name).last().map(|(_, value)| value.unwrap_or(""))
+}
+
+/// gets the parameter with the given name from the registry
+fn get_param(name: &str) -> Option<&'static Param> {
+    PARAMS.iter().find(|param| param.name == name)
+}
+
+/// parses an unsigned number, in decimal or hex with 0x
+pub fn parse_int(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// parses a boolean, `name` on its own (an empty value) counts as true
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "" | "1" | "y" | "yes" | "on" | "true" => Some(true),
+        "0" | "n" | "no" | "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// checks whether a value is valid for the given type
+fn is_valid(kind: ParamType, value: &str) -> bool {
+    match kind {
+        ParamType::Bool => parse_bool(value).is_some(),
+        ParamType::Int => parse_int(value).is_some(),
+        ParamType::String => true,
+    }
+}
+
+/// gets the value of a parameter as a string, falling back to its default if it wasn't given or isn't valid
+pub fn get_str(name: &str) -> Option<&'static str> {
+    let param = get_param(name);
+
+    match (find_param(name), param) {
+        (Some(value), Some(param)) if is_valid(param.kind, value) => Some(value),
+        (Some(value), None) => Some(value),
+        (_, param) => param.and_then(|param| param.default),
+    }
+}
+
+/// gets the value of a numeric parameter
+pub fn get_int(name: &str) -> Option<usize> {
+    parse_int(get_str(name)?)
+}
+
+/// gets the value of a boolean parameter
+pub fn get_bool(name: &str) -> Option<bool> {
+    parse_bool(get_str(name)?)
+}
+
+/// checks whether a parameter was given on the command line
+pub fn is_set(name: &str) -> bool {
+    find_param(name).is_some()
+}
+
+/// copies the command line from the bootloader, this has to happen before memory management is set up
+pub fn init() {
+    if let Some(cmdline) = multiboot::get_cmdline() {
+        let mut len = cmdline.len().min(MAX_CMDLINE_LEN);
+        while !cmdline.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        unsafe {
+            CMDLINE[..len].copy_from_slice(&cmdline.as_bytes()[..len]);
+            CMDLINE_LEN = len;
+        }
+    }
+}
+
+/// complains about parameters we don't know about or that have bad values
+/// this is separate from init since logging doesn't work that early
+pub fn check() {
+    log!("command line: {}", get_cmdline());
+
+    for (name, value) in parse_params(get_cmdline()) {
+        match get_param(name) {
+            Some(param) if !is_valid(param.kind, value.unwrap_or("")) =>
+                log!("bad value {:?} for {}, using default {:?}", value.unwrap_or(""), name, param.default),
+            Some(_) => (),
+            None => debug!("unknown parameter {}", name),
+        }
+    }
+}
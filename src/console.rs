@@ -2,15 +2,21 @@
 
 /*
 TODO: define basic trait for text consoles
- - raw (i.e. serial), screen (vt100 emulator) modes
- - screen modes use other trait to write single or multiple bytes with a certain color at a position on the screen,
-    making portability easier
+ - raw (i.e. serial) mode
 */
 
 use num_enum::FromPrimitive;
-use alloc::boxed::Box;
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    vec,
+    vec::Vec,
+};
 use core::fmt::Write;
-use crate::platform::create_console;
+use crate::{
+    cmdline,
+    platform::{create_console, init_display},
+};
 
 /// text colors
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
@@ -61,9 +67,25 @@ pub const PANIC_COLOR: ColorCode = ColorCode {
 /// trait for a text console
 pub trait TextConsole: Write {
     fn puts(&mut self, string: &str);
+    fn write_bytes(&mut self, bytes: &[u8]);
     fn clear(&mut self);
     fn set_color(&mut self, color: ColorCode);
     fn get_color(&self) -> ColorCode;
+
+    /// scrolls the view back into the scrollback buffer by the given amount of lines
+    fn scroll_view_back(&mut self, lines: usize);
+
+    /// scrolls the view forward towards the live screen by the given amount of lines
+    fn scroll_view_forward(&mut self, lines: usize);
+
+    /// passes every byte of the scrollback buffer and the screen to the provided function, oldest first
+    fn dump(&self, out: &mut dyn FnMut(u8));
+
+    /// sets whether this console is the one being shown, inactive consoles only update their off-screen buffer
+    fn set_active(&mut self, active: bool);
+
+    /// changes the size of the console, after the raw console it draws on has changed size
+    fn resize(&mut self, width: u16, height: u16);
 }
 
 /// interface our fancy text console(s) use to talk to lower level 
@@ -72,9 +94,69 @@ pub trait RawTextConsole {
     //fn write_string(&mut self, x: u16, y: u16, color: ColorCode, string: &str);
     fn clear(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, color: ColorCode);
     fn copy(&mut self, y0: u16, y1: u16, height: u16); // we dont need to scroll horizontally
+
+    /// moves the cursor to the given position, if this console has one
+    fn set_cursor_pos(&mut self, _x: u16, _y: u16) {}
+
+    /// shows or hides the cursor
+    fn set_cursor_visible(&mut self, _visible: bool) {}
+
+    /// sets the first and last scanline of the cursor within a character cell
+    fn set_cursor_shape(&mut self, _start: u8, _end: u8) {}
 }
 
-/// simple text console, doesn't implement ANSI control codes
+/// default amount of lines kept in the scrollback buffer
+pub const DEFAULT_SCROLLBACK_LINES: usize = 256;
+
+/// a single character on the screen along with its color
+type Cell = (u8, ColorCode);
+
+/// an empty cell with the given color
+fn blank_cell(color: ColorCode) -> Cell {
+    (b' ', color)
+}
+
+/// maximum amount of parameters we keep track of in a control sequence
+const MAX_ESCAPE_PARAMS: usize = 16;
+
+/// maps ANSI color numbers to our colors
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+
+/// maps bright ANSI color numbers to our colors
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::Yellow,
+    Color::LightBlue,
+    Color::Pink,
+    Color::LightCyan,
+    Color::White,
+];
+
+/// where we are in parsing an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    /// not in an escape sequence
+    Normal,
+
+    /// got an escape character
+    Escape,
+
+    /// in a control sequence (escape followed by '[')
+    ControlSequence,
+}
+
+/// simple text console, implements a subset of the ANSI/VT100 control sequences
 pub struct SimpleConsole {
     pub raw: Box<dyn RawTextConsole + Sync>,
     pub width: u16,
@@ -82,33 +164,448 @@ pub struct SimpleConsole {
     pub cursor_x: u16,
     pub cursor_y: u16,
     pub color: ColorCode,
+
+    /// whether the foreground color should be bright (SGR bold)
+    bold: bool,
+
+    /// first line of the scrolling region
+    scroll_top: u16,
+
+    /// last line of the scrolling region (inclusive)
+    scroll_bottom: u16,
+
+    /// cursor position and color saved with ESC 7 or CSI s
+    saved_cursor: (u16, u16, ColorCode, bool),
+
+    /// state of the escape sequence parser
+    escape_state: EscapeState,
+
+    /// parameters of the control sequence we're parsing
+    escape_params: [u16; MAX_ESCAPE_PARAMS],
+
+    /// how many parameters the control sequence we're parsing has
+    num_escape_params: usize,
+
+    /// whether the control sequence we're parsing is a private one (starts with '?')
+    escape_private: bool,
+
+    /// whether the cursor should be shown
+    cursor_visible: bool,
+
+    /// copy of what's on the screen, since we can't read it back from the raw console
+    screen: Vec<Cell>,
+
+    /// lines that have scrolled off the top of the screen, oldest first
+    scrollback: VecDeque<Vec<Cell>>,
+
+    /// maximum amount of lines kept in the scrollback buffer
+    scrollback_size: usize,
+
+    /// how many lines back into the scrollback buffer we're currently viewing, 0 if we're viewing the screen
+    view_offset: usize,
+
+    /// whether this console is being shown, and so should draw to the raw console
+    active: bool,
 }
 
 impl SimpleConsole {
     pub fn new(raw: Box<dyn RawTextConsole + Sync>, width: u16, height: u16) -> Self {
-        Self {
+        let mut console = Self {
             raw, width, height,
             cursor_x: 0,
             cursor_y: 0,
             color: ColorCode::default(),
+            bold: false,
+            scroll_top: 0,
+            scroll_bottom: height - 1,
+            saved_cursor: (0, 0, ColorCode::default(), false),
+            escape_state: EscapeState::Normal,
+            escape_params: [0; MAX_ESCAPE_PARAMS],
+            num_escape_params: 0,
+            escape_private: false,
+            cursor_visible: true,
+            screen: vec![blank_cell(ColorCode::default()); width as usize * height as usize],
+            scrollback: VecDeque::new(),
+            scrollback_size: DEFAULT_SCROLLBACK_LINES,
+            view_offset: 0,
+            active: true,
+        };
+
+        console.raw.set_cursor_pos(0, 0);
+        console.raw.set_cursor_visible(true);
+
+        console
+    }
+
+    /// sets the maximum amount of lines kept in the scrollback buffer, discarding the oldest lines if needed
+    pub fn set_scrollback_size(&mut self, lines: usize) {
+        self.scrollback_size = lines;
+
+        while self.scrollback.len() > lines {
+            self.scrollback.pop_front();
+        }
+
+        self.view_offset = self.view_offset.min(self.scrollback.len());
+    }
+
+    /// gets a line of the screen
+    fn screen_line(&self, y: u16) -> &[Cell] {
+        let start = y as usize * self.width as usize;
+        &self.screen[start..start + self.width as usize]
+    }
+
+    /// writes a character to the screen
+    fn write_cell(&mut self, x: u16, y: u16, color: ColorCode, c: u8) {
+        self.screen[y as usize * self.width as usize + x as usize] = (c, color);
+        if self.active {
+            self.raw.write_char(x, y, color, c);
+        }
+    }
+
+    /// clears a rectangle on the screen, x1 and y1 are exclusive
+    fn clear_cells(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, color: ColorCode) {
+        for y in y0..y1 {
+            let start = y as usize * self.width as usize;
+            self.screen[start + x0 as usize..start + x1 as usize].fill(blank_cell(color));
+        }
+        if self.active {
+            self.raw.clear(x0, y0, x1, y1, color);
+        }
+    }
+
+    /// copies lines on the screen from y0 to y1
+    fn copy_lines(&mut self, y0: u16, y1: u16, height: u16) {
+        let width = self.width as usize;
+        self.screen.copy_within(y0 as usize * width..(y0 + height) as usize * width, y1 as usize * width);
+        if self.active {
+            self.raw.copy(y0, y1, height);
+        }
+    }
+
+    /// scrolls the scrolling region up by the given amount of lines
+    fn scroll_up(&mut self, lines: u16) {
+        let region = self.scroll_bottom + 1 - self.scroll_top;
+        let lines = lines.min(region);
+
+        // lines scrolling off the top of the screen go into the scrollback buffer
+        if self.scroll_top == 0 && self.scrollback_size > 0 {
+            for y in 0..lines {
+                if self.scrollback.len() >= self.scrollback_size {
+                    self.scrollback.pop_front();
+                }
+                self.scrollback.push_back(self.screen_line(y).to_vec());
+            }
+        }
+
+        if lines < region {
+            self.copy_lines(self.scroll_top + lines, self.scroll_top, region - lines);
+        }
+        self.clear_cells(0, self.scroll_bottom + 1 - lines, self.width, self.scroll_bottom + 1, self.color);
+    }
+
+    /// scrolls the scrolling region down by the given amount of lines
+    fn scroll_down(&mut self, lines: u16) {
+        let region = self.scroll_bottom + 1 - self.scroll_top;
+        let lines = lines.min(region);
+
+        if lines < region {
+            self.copy_lines(self.scroll_top, self.scroll_top + lines, region - lines);
+        }
+        self.clear_cells(0, self.scroll_top, self.width, self.scroll_top + lines, self.color);
+    }
+
+    /// redraws the screen according to the current view offset
+    fn redraw(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        let history = self.scrollback.len();
+
+        for y in 0..self.height {
+            // index of this line if the scrollback buffer and screen were one big buffer
+            let index = history - self.view_offset + y as usize;
+
+            for x in 0..self.width {
+                let (c, color) =
+                    if index < history {
+                        self.scrollback[index].get(x as usize).copied().unwrap_or_else(|| blank_cell(ColorCode::default()))
+                    } else {
+                        self.screen_line((index - history) as u16)[x as usize]
+                    };
+
+                self.raw.write_char(x, y, color, c);
+            }
+        }
+
+        // the cursor doesn't make sense while we're looking at old lines
+        self.raw.set_cursor_visible(self.cursor_visible && self.view_offset == 0);
+    }
+
+    /// goes back to viewing the screen if we're viewing the scrollback buffer
+    fn reset_view(&mut self) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw();
+        }
+    }
+
+    /// sets whether the cursor should be shown
+    fn set_cursor_visible(&mut self, visible: bool) {
+        self.cursor_visible = visible;
+
+        if self.active && self.view_offset == 0 {
+            self.raw.set_cursor_visible(visible);
+        }
+    }
+
+    /// moves the cursor down a line, scrolling if it's at the bottom of the scrolling region
+    fn line_feed(&mut self) {
+        if self.cursor_y == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor_y < self.height - 1 {
+            self.cursor_y += 1;
+        }
+    }
+
+    /// moves the cursor up a line, scrolling if it's at the top of the scrolling region
+    fn reverse_line_feed(&mut self) {
+        if self.cursor_y == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor_y > 0 {
+            self.cursor_y -= 1;
         }
     }
 
     fn newline(&mut self) {
         self.cursor_x = 0;
-        self.cursor_y += 1;
-        if self.cursor_y >= self.height { // scroll screen
-            self.raw.copy(1, 0, self.height - 1);
-            self.raw.clear(0, self.height - 1, self.width, self.height, self.color);
-            self.cursor_y = self.height - 1;
+        self.line_feed();
+    }
+
+    /// gets the color characters are drawn with, taking bold into account
+    fn draw_color(&self) -> ColorCode {
+        if self.bold {
+            ColorCode {
+                foreground: (self.color.foreground as u8 | 8).into(),
+                background: self.color.background,
+            }
+        } else {
+            self.color
+        }
+    }
+
+    /// gets a parameter of the control sequence we're parsing, or the default value if it wasn't provided or is 0
+    fn param(&self, index: usize, default: u16) -> u16 {
+        if index < self.num_escape_params && self.escape_params[index] != 0 {
+            self.escape_params[index]
+        } else {
+            default
+        }
+    }
+
+    /// moves the cursor to the given position, keeping it on screen
+    fn move_cursor(&mut self, x: u16, y: u16) {
+        self.cursor_x = x.min(self.width - 1);
+        self.cursor_y = y.min(self.height - 1);
+    }
+
+    /// handles a byte that follows an escape character
+    fn handle_escape(&mut self, c: u8) {
+        self.escape_state = EscapeState::Normal;
+
+        match c {
+            b'[' => {
+                self.escape_state = EscapeState::ControlSequence;
+                self.escape_params = [0; MAX_ESCAPE_PARAMS];
+                self.num_escape_params = 0;
+                self.escape_private = false;
+            },
+            b'7' => self.saved_cursor = (self.cursor_x, self.cursor_y, self.color, self.bold),
+            b'8' => {
+                let (x, y, color, bold) = self.saved_cursor;
+                self.move_cursor(x, y);
+                self.color = color;
+                self.bold = bold;
+            },
+            b'D' => self.line_feed(),
+            b'E' => self.newline(),
+            b'M' => self.reverse_line_feed(),
+            b'c' => {
+                self.color = ColorCode::default();
+                self.bold = false;
+                self.scroll_top = 0;
+                self.scroll_bottom = self.height - 1;
+                self.set_cursor_visible(true);
+                self.clear();
+            },
+            _ => (), // unsupported, ignore it
+        }
+    }
+
+    /// handles a byte in a control sequence
+    fn handle_control_sequence(&mut self, c: u8) {
+        match c {
+            b'0'..=b'9' => {
+                if self.num_escape_params == 0 {
+                    self.num_escape_params = 1;
+                }
+                if self.num_escape_params <= MAX_ESCAPE_PARAMS {
+                    let param = &mut self.escape_params[self.num_escape_params - 1];
+                    *param = param.saturating_mul(10).saturating_add((c - b'0') as u16);
+                }
+            },
+            b';' => {
+                if self.num_escape_params == 0 {
+                    self.num_escape_params = 1;
+                }
+                self.num_escape_params += 1;
+            },
+            b'?' => self.escape_private = true,
+            0x40..=0x7e => {
+                self.escape_state = EscapeState::Normal;
+                self.num_escape_params = self.num_escape_params.min(MAX_ESCAPE_PARAMS);
+
+                if self.escape_private {
+                    self.run_private_sequence(c);
+                } else {
+                    self.run_control_sequence(c);
+                }
+            },
+            _ => (), // intermediate bytes, we don't support any sequences that use them
+        }
+    }
+
+    /// runs a private (DEC) control sequence once its final byte has been received
+    fn run_private_sequence(&mut self, c: u8) {
+        for i in 0..self.num_escape_params {
+            match (self.escape_params[i], c) {
+                // show/hide cursor
+                (25, b'h') => self.set_cursor_visible(true),
+                (25, b'l') => self.set_cursor_visible(false),
+                _ => (), // unsupported, ignore it
+            }
+        }
+    }
+
+    /// runs a control sequence once its final byte has been received
+    fn run_control_sequence(&mut self, c: u8) {
+        let (x, y) = (self.cursor_x, self.cursor_y);
+
+        match c {
+            // cursor movement
+            b'A' => self.move_cursor(x, y.saturating_sub(self.param(0, 1))),
+            b'B' => self.move_cursor(x, y.saturating_add(self.param(0, 1))),
+            b'C' => self.move_cursor(x.saturating_add(self.param(0, 1)), y),
+            b'D' => self.move_cursor(x.saturating_sub(self.param(0, 1)), y),
+            b'E' => self.move_cursor(0, y.saturating_add(self.param(0, 1))),
+            b'F' => self.move_cursor(0, y.saturating_sub(self.param(0, 1))),
+            b'G' => self.move_cursor(self.param(0, 1) - 1, y),
+            b'd' => self.move_cursor(x, self.param(0, 1) - 1),
+            b'H' | b'f' => self.move_cursor(self.param(1, 1) - 1, self.param(0, 1) - 1),
+
+            // erase in display
+            b'J' => match self.param(0, 0) {
+                0 => {
+                    self.clear_cells(x, y, self.width, y + 1, self.color);
+                    self.clear_cells(0, y + 1, self.width, self.height, self.color);
+                },
+                1 => {
+                    self.clear_cells(0, 0, self.width, y, self.color);
+                    self.clear_cells(0, y, x + 1, y + 1, self.color);
+                },
+                _ => self.clear_cells(0, 0, self.width, self.height, self.color),
+            },
+
+            // erase in line
+            b'K' => match self.param(0, 0) {
+                0 => self.clear_cells(x, y, self.width, y + 1, self.color),
+                1 => self.clear_cells(0, y, x + 1, y + 1, self.color),
+                _ => self.clear_cells(0, y, self.width, y + 1, self.color),
+            },
+
+            // scrolling
+            b'S' => self.scroll_up(self.param(0, 1)),
+            b'T' => self.scroll_down(self.param(0, 1)),
+            b'r' => {
+                let top = self.param(0, 1) - 1;
+                let bottom = self.param(1, self.height).min(self.height) - 1;
+
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_cursor(0, 0);
+                }
+            },
+
+            // saving and restoring the cursor
+            b's' => self.saved_cursor = (x, y, self.color, self.bold),
+            b'u' => {
+                let (x, y, _, _) = self.saved_cursor;
+                self.move_cursor(x, y);
+            },
+
+            // colors
+            b'm' => {
+                // no parameters is the same as a reset
+                for i in 0..self.num_escape_params.max(1) {
+                    self.select_graphic_rendition(self.param(i, 0));
+                }
+            },
+
+            _ => (), // unsupported, ignore it
+        }
+    }
+
+    /// handles a single SGR parameter
+    fn select_graphic_rendition(&mut self, param: u16) {
+        match param {
+            0 => {
+                self.color = ColorCode::default();
+                self.bold = false;
+            },
+            1 => self.bold = true,
+            22 => self.bold = false,
+            7 => self.color = ColorCode {
+                foreground: self.color.background,
+                background: self.color.foreground,
+            },
+            30..=37 => self.color.foreground = ANSI_COLORS[(param - 30) as usize],
+            39 => self.color.foreground = ColorCode::default().foreground,
+            40..=47 => self.color.background = ANSI_COLORS[(param - 40) as usize],
+            49 => self.color.background = ColorCode::default().background,
+            90..=97 => self.color.foreground = ANSI_BRIGHT_COLORS[(param - 90) as usize],
+            100..=107 => self.color.background = ANSI_BRIGHT_COLORS[(param - 100) as usize],
+            _ => (), // unsupported, ignore it
         }
     }
 }
 
 impl TextConsole for SimpleConsole {
     fn puts(&mut self, string: &str) {
-        for c in string.bytes() {
+        self.write_bytes(string.as_bytes());
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        // new output snaps the view back to the screen
+        self.reset_view();
+
+        for &c in bytes {
+            match self.escape_state {
+                EscapeState::Escape => {
+                    self.handle_escape(c);
+                    continue;
+                },
+                EscapeState::ControlSequence => {
+                    self.handle_control_sequence(c);
+                    continue;
+                },
+                EscapeState::Normal => (),
+            }
+
             match c {
+                b'\x1b' => {
+                    self.escape_state = EscapeState::Escape;
+                },
                 b'\n' => {
                     self.newline();
                 },
@@ -127,7 +624,7 @@ impl TextConsole for SimpleConsole {
                     }
                 },
                 _ => {
-                    self.raw.write_char(self.cursor_x, self.cursor_y, self.color, c);
+                    self.write_cell(self.cursor_x, self.cursor_y, self.draw_color(), c);
                     self.cursor_x += 1;
                     if self.cursor_x >= self.width {
                         self.newline();
@@ -135,19 +632,109 @@ impl TextConsole for SimpleConsole {
                 },
             }
         }
+
+        if self.active {
+            self.raw.set_cursor_pos(self.cursor_x, self.cursor_y);
+        }
     }
 
     fn clear(&mut self) {
-        self.raw.clear(0, 0, self.width, self.height, self.color);
+        self.reset_view();
+        self.clear_cells(0, 0, self.width, self.height, self.color);
     }
 
     fn set_color(&mut self, color: ColorCode) {
         self.color = color;
+        self.bold = false;
     }
 
     fn get_color(&self) -> ColorCode {
         self.color
     }
+
+    fn scroll_view_back(&mut self, lines: usize) {
+        let offset = (self.view_offset + lines).min(self.scrollback.len());
+
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+        }
+    }
+
+    fn scroll_view_forward(&mut self, lines: usize) {
+        let offset = self.view_offset.saturating_sub(lines);
+
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+        }
+    }
+
+    fn dump(&self, out: &mut dyn FnMut(u8)) {
+        let lines = self.scrollback.iter().map(|line| &line[..]).chain((0..self.height).map(|y| self.screen_line(y)));
+
+        for line in lines {
+            // trailing spaces aren't interesting
+            let len = line.iter().rposition(|(c, _)| *c != b' ').map(|i| i + 1).unwrap_or(0);
+
+            for (c, _) in &line[..len] {
+                out(*c);
+            }
+            out(b'\r');
+            out(b'\n');
+        }
+    }
+
+    fn set_active(&mut self, active: bool) {
+        if active == self.active {
+            return;
+        }
+
+        self.active = active;
+
+        // the raw console has whatever the last console drew on it, so put ours back
+        if active {
+            self.redraw();
+            self.raw.set_cursor_pos(self.cursor_x, self.cursor_y);
+        }
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        // if the cursor would end up off the bottom, push lines off the top to keep it on screen
+        let shift = (self.cursor_y + 1).saturating_sub(height);
+
+        for y in 0..shift {
+            if self.scrollback_size > 0 {
+                if self.scrollback.len() >= self.scrollback_size {
+                    self.scrollback.pop_front();
+                }
+                self.scrollback.push_back(self.screen_line(y).to_vec());
+            }
+        }
+
+        let mut screen = vec![blank_cell(self.color); width as usize * height as usize];
+        let copy_width = width.min(self.width) as usize;
+
+        for y in 0..height.min(self.height - shift) {
+            let start = y as usize * width as usize;
+            screen[start..start + copy_width].copy_from_slice(&self.screen_line(y + shift)[..copy_width]);
+        }
+
+        self.screen = screen;
+        self.width = width;
+        self.height = height;
+        self.cursor_x = self.cursor_x.min(width - 1);
+        self.cursor_y -= shift;
+        self.scroll_top = 0;
+        self.scroll_bottom = height - 1;
+        self.view_offset = 0;
+
+        // whatever was on the raw console is gone now
+        if self.active {
+            self.redraw();
+            self.raw.set_cursor_pos(self.cursor_x, self.cursor_y);
+        }
+    }
 }
 
 impl core::fmt::Write for SimpleConsole {
@@ -157,18 +744,83 @@ impl core::fmt::Write for SimpleConsole {
     }
 }
 
-/// global text console
-static mut CONSOLE: Option<Box<dyn TextConsole + Sync>> = None;
+/// amount of virtual consoles
+pub const NUM_CONSOLES: usize = 6;
+
+/// virtual consoles, the first one is where kernel messages go
+static mut CONSOLES: Vec<Box<dyn TextConsole + Sync>> = Vec::new();
+
+/// index of the virtual console that's being shown
+static mut ACTIVE_CONSOLE: usize = 0;
 
 pub fn init() {
     debug!("initializing console");
+    init_display();
+
+    let scrollback = cmdline::get_int("scrollback").unwrap_or(DEFAULT_SCROLLBACK_LINES);
+
     unsafe {
-        CONSOLE = Some(Box::new(create_console()));
+        for i in 0..NUM_CONSOLES {
+            let mut console = create_console();
+            console.set_scrollback_size(scrollback);
+
+            // only the first console gets to draw to the screen to begin with
+            console.set_active(i == 0);
+            console.clear();
+
+            CONSOLES.push(Box::new(console));
+        }
     }
 }
 
+/// gets the console kernel messages go to
 pub fn get_console() -> Option<&'static mut Box<(dyn TextConsole + Sync + 'static)>> {
+    get_virtual_console(0)
+}
+
+/// gets a virtual console by its index
+pub fn get_virtual_console(index: usize) -> Option<&'static mut Box<(dyn TextConsole + Sync + 'static)>> {
     unsafe {
-        CONSOLE.as_mut()
+        CONSOLES.get_mut(index)
+    }
+}
+
+/// gets the index of the virtual console that's being shown
+pub fn active_console() -> usize {
+    unsafe { ACTIVE_CONSOLE }
+}
+
+/// gets the virtual console that's being shown
+pub fn get_active_console() -> Option<&'static mut Box<(dyn TextConsole + Sync + 'static)>> {
+    get_virtual_console(active_console())
+}
+
+/// switches which virtual console is shown on the screen
+pub fn switch_console(index: usize) -> Result<(), &'static str> {
+    if index >= unsafe { CONSOLES.len() } {
+        return Err("virtual console doesn't exist");
+    }
+
+    if index != active_console() {
+        if let Some(console) = get_active_console() {
+            console.set_active(false);
+        }
+
+        unsafe { ACTIVE_CONSOLE = index; }
+
+        if let Some(console) = get_active_console() {
+            console.set_active(true);
+        }
+    }
+
+    Ok(())
+}
+
+/// resizes every virtual console, after the screen has changed size
+pub fn resize_consoles(width: u16, height: u16) {
+    for index in 0..NUM_CONSOLES {
+        if let Some(console) = get_virtual_console(index) {
+            console.resize(width, height);
+        }
     }
 }
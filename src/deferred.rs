@@ -0,0 +1,123 @@
+//! deferred interrupt work
+//! interrupt handlers should do as little as they can with interrupts off, so they queue the rest of their work here.
+//! queued work runs in order with interrupts on, on the way out of the next interrupt, or in the deferred work thread
+//! if more piles up than one interrupt should deal with.
+//! work runs one item at a time and doesn't get preempted, so it can touch the same things interrupt handlers could
+
+use crate::{
+    arch::{enable_interrupts, disable_interrupts, without_interrupts},
+    tasks::{BlockReason, IN_TASK, get_current_task_mut, spawn_kernel_thread, wake_blocked},
+    util::ring::RingBuffer,
+};
+
+/// how many work items can be waiting at once
+const QUEUE_SIZE: usize = 64;
+
+/// how many work items get run on the way out of an interrupt, anything left goes to the deferred work thread
+const MAX_WORK_PER_INTERRUPT: usize = 8;
+
+/// a function to run later, along with something to run it on
+pub type WorkFn = fn(usize);
+
+/// a queued work item
+#[derive(Copy, Clone)]
+struct Work {
+    /// what queued it, for debugging
+    name: &'static str,
+
+    func: WorkFn,
+    arg: usize,
+}
+
+/// fills the unused slots in the queue
+fn nothing(_arg: usize) {}
+
+/// work waiting to run
+static mut QUEUE: RingBuffer<Work, QUEUE_SIZE> = RingBuffer::new(Work { name: "", func: nothing, arg: 0 });
+
+/// whether work is running right now, so interrupts that come in while it runs don't start on the next item
+static mut RUNNING: bool = false;
+
+/// how many work items have been run
+pub static mut COMPLETED: u64 = 0;
+
+/// how many work items were thrown away because the queue was full
+pub static mut DROPPED: u64 = 0;
+
+/// queues a function to be run with interrupts on. safe to call from interrupt handlers
+pub fn defer(name: &'static str, func: WorkFn, arg: usize) -> Result<(), &'static str> {
+    without_interrupts(|| unsafe {
+        QUEUE.push(Work { name, func, arg }).map_err(|work| {
+            DROPPED += 1;
+            debug!("deferred work queue is full, dropping work from {}", work.name);
+            "deferred work queue is full"
+        })
+    })
+}
+
+/// how many work items are waiting to run
+pub fn pending() -> usize {
+    without_interrupts(|| unsafe { QUEUE.len() })
+}
+
+/// runs up to the given number of work items. has to be called with interrupts off, and turns them back off before returning.
+/// returns whether there's still work left
+unsafe fn run_queued(limit: usize) -> bool {
+    if RUNNING {
+        return !QUEUE.is_empty();
+    }
+
+    RUNNING = true;
+
+    // the timer mustn't switch tasks in the middle of an item, since we could be on the stack of whatever was interrupted
+    let was_in_task = IN_TASK;
+    IN_TASK = false;
+
+    for _i in 0..limit {
+        let work =
+            match QUEUE.pop() {
+                Some(work) => work,
+                None => break,
+            };
+
+        enable_interrupts();
+        (work.func)(work.arg);
+        disable_interrupts();
+
+        COMPLETED += 1;
+    }
+
+    IN_TASK = was_in_task;
+    RUNNING = false;
+
+    !QUEUE.is_empty()
+}
+
+/// runs queued work on the way out of an interrupt, after it's been acknowledged. has to be called with interrupts off.
+/// work runs with interrupts on, so this can be reached again from an interrupt that came in during an item.
+/// that interrupt leaves the queue alone, the item it interrupted carries on once it returns
+pub unsafe fn run_pending() {
+    if RUNNING {
+        return;
+    }
+
+    if run_queued(MAX_WORK_PER_INTERRUPT) {
+        wake_blocked(BlockReason::DeferredWork);
+    }
+}
+
+/// starts the thread that picks up work interrupts left behind
+pub fn init() {
+    spawn_kernel_thread(|| loop {
+        without_interrupts(|| unsafe {
+            if !run_queued(MAX_WORK_PER_INTERRUPT) {
+                if let Some(task) = get_current_task_mut() {
+                    task.block(BlockReason::DeferredWork, None);
+                }
+            }
+        });
+
+        // sleep until the next task switch takes us off the cpu, or give someone else a turn if there's more to do
+        unsafe { core::arch::asm!("sti; hlt"); }
+    });
+}
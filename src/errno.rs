@@ -89,6 +89,14 @@ pub enum Errno {
     Other(&'static str),    // other error
 }
 
+impl Errno {
+    /// gets the number of this error
+    pub fn number(&self) -> u32 {
+        // since we're repr(u32), the discriminant is always stored in the first 4 bytes
+        unsafe { *(self as *const Self as *const u32) }
+    }
+}
+
 impl fmt::Display for Errno {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}",
@@ -0,0 +1,22 @@
+//! /dev, where device files live
+
+use crate::errno::Errno;
+use alloc::boxed::Box;
+use super::{
+    kernfs::{add_file, create_directory},
+    tree::File,
+    vfs::Permissions,
+};
+
+/// name of the directory device files are put in
+pub const DEV_DIR_NAME: &str = "dev";
+
+/// adds a device file to /dev
+pub fn add_device(file: Box<dyn File>) -> Result<(), Errno> {
+    add_file(DEV_DIR_NAME, file)
+}
+
+/// creates /dev
+pub fn init() {
+    create_directory(DEV_DIR_NAME, Permissions::OwnerRead | Permissions::OwnerWrite | Permissions::OwnerExecute | Permissions::GroupRead | Permissions::GroupExecute | Permissions::OtherRead | Permissions::OtherExecute);
+}
@@ -0,0 +1,171 @@
+//! directories and read only files the kernel fills in itself, shared by /dev and /proc
+
+use crate::errno::Errno;
+use alloc::{
+    vec::Vec,
+    boxed::Box,
+};
+use super::{
+    tree::{File, Directory, LockType, get_directory_from_path},
+    vfs::{Permissions, ROOT_DIR},
+};
+
+/// a directory in the root that the kernel puts files in. it can't be renamed and its permissions can't be changed
+pub struct KernelDirectory {
+    name: &'static str,
+    permissions: Permissions,
+    files: Vec<Box<dyn File>>,
+    directories: Vec<Box<dyn Directory>>,
+}
+
+impl Directory for KernelDirectory {
+    fn get_permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    fn set_permissions(&mut self, _permissions: Permissions) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn get_files(&self) -> &Vec<Box<dyn File>> {
+        &self.files
+    }
+
+    fn get_files_mut(&mut self) -> &mut Vec<Box<dyn File>> {
+        &mut self.files
+    }
+
+    fn get_directories(&self) -> &Vec<Box<dyn Directory>> {
+        &self.directories
+    }
+
+    fn get_directories_mut(&mut self) -> &mut Vec<Box<dyn Directory>> {
+        &mut self.directories
+    }
+
+    fn get_name(&self) -> &str {
+        self.name
+    }
+
+    fn set_name(&mut self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+}
+
+/// creates an empty kernel directory in the root
+pub fn create_directory(name: &'static str, permissions: Permissions) {
+    let root = unsafe { ROOT_DIR.as_mut().expect("file system not initialized") };
+
+    root.get_directories_mut().push(Box::new(KernelDirectory {
+        name,
+        permissions,
+        files: Vec::new(),
+        directories: Vec::new(),
+    }));
+}
+
+/// adds a file to a directory in the root, unless there's already one with the same name
+pub fn add_file(dir_name: &str, file: Box<dyn File>) -> Result<(), Errno> {
+    let root = unsafe { ROOT_DIR.as_mut().expect("file system not initialized") };
+    let dir = get_directory_from_path(root, dir_name).ok_or(Errno::NoSuchFileOrDir)?;
+
+    if dir.get_files().iter().any(|other| other.get_name() == file.get_name()) {
+        return Err(Errno::Exists);
+    }
+
+    debug!("adding {}/{}", dir_name, file.get_name());
+
+    dir.get_files_mut().push(file);
+
+    Ok(())
+}
+
+/// a file anyone can read and nobody can change, whose contents come from the kernel.
+/// implementing this gets a File implementation for free
+pub trait ReadOnlyFile {
+    /// name of the file
+    fn name(&self) -> &str;
+
+    /// how big the contents are right now
+    fn size(&self) -> usize;
+
+    /// copies as much of the contents as fits into bytes, starting at offset. returns how many bytes were copied
+    fn read_contents(&self, bytes: &mut [u8], offset: usize) -> usize;
+}
+
+impl<T: ReadOnlyFile> File for T {
+    fn get_permissions(&self) -> Permissions {
+        Permissions::OwnerRead | Permissions::GroupRead | Permissions::OtherRead
+    }
+
+    fn set_permissions(&mut self, _permissions: Permissions) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn write_at(&mut self, _bytes: &[u8], _offset: usize) -> Result<usize, Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn can_write_at(&self, _space: usize, _offset: usize) -> bool {
+        false
+    }
+
+    fn read_at(&self, bytes: &mut [u8], offset: usize) -> Result<usize, Errno> {
+        Ok(self.read_contents(bytes, offset))
+    }
+
+    fn can_read_at(&self, _space: usize, offset: usize) -> bool {
+        offset < self.size()
+    }
+
+    fn truncate(&mut self, _size: usize) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn lock(&mut self, _kind: LockType, _size: isize) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn get_name(&self) -> &str {
+        self.name()
+    }
+
+    fn set_name(&mut self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn get_size(&self) -> usize {
+        self.size()
+    }
+}
+
+/// a read only file with contents that are made once, when it's created
+pub struct StaticFile {
+    name: &'static str,
+    contents: Vec<u8>,
+}
+
+impl StaticFile {
+    pub fn new(name: &'static str, contents: Vec<u8>) -> Self {
+        Self { name, contents }
+    }
+}
+
+impl ReadOnlyFile for StaticFile {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn size(&self) -> usize {
+        self.contents.len()
+    }
+
+    fn read_contents(&self, bytes: &mut [u8], offset: usize) -> usize {
+        let contents = self.contents.get(offset..).unwrap_or(&[]);
+        let len = bytes.len().min(contents.len());
+
+        bytes[..len].copy_from_slice(&contents[..len]);
+
+        len
+    }
+}
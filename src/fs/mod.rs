@@ -4,6 +4,9 @@
 pub mod vfs;
 pub mod tree;
 pub mod ops;
+pub mod kernfs;
+pub mod devfs;
+pub mod procfs;
 
 use alloc::{
     string::String,
@@ -26,4 +29,11 @@ pub fn basename(path: &str) -> Option<&str> {
 pub fn init() {
     debug!("initializing vfs");
     vfs::init();
+    devfs::init();
+    procfs::init();
+
+    // there aren't any block devices or disk filesystems yet, everything lives in the vfs
+    if let Some(root) = crate::cmdline::get_str("root") {
+        log!("can't mount {} as root, there's no support for disk filesystems", root);
+    }
 }
@@ -20,6 +20,9 @@ static mut OPEN_FILES: Vec<Option<OpenFile>> = Vec::new();
 /// bitset of available system file descriptors
 static mut FILE_DESCRIPTOR_BITSET: VecBitSet = VecBitSet::new();
 
+/// a task's open files, indexed by file descriptor number
+pub type FileTable = Vec<Option<FileDescriptor>>;
+
 /// stores information about an open file
 pub struct OpenFile<'a> {
     /// file descriptor number
@@ -30,6 +33,12 @@ pub struct OpenFile<'a> {
 
     /// absolute path to file
     pub path: String,
+
+    /// offset for reading from and writing into the file, shared by every file descriptor referring to this open file
+    pub offset: usize,
+
+    /// how many file descriptors refer to this open file, it's closed once they're all gone
+    references: usize,
 }
 
 /// opens a file for writing
@@ -52,17 +61,33 @@ pub fn open(path: &str) -> Result<FileDescriptor, Errno> {
             descriptor,
             file,
             path: path.to_string(),
+            offset: 0,
+            references: 1,
         };
 
-        unsafe { OPEN_FILES[descriptor] = Some(open); }
+        unsafe {
+            if descriptor >= OPEN_FILES.len() {
+                OPEN_FILES.resize_with(descriptor + 1, || None);
+            }
+
+            OPEN_FILES[descriptor] = Some(open);
+        }
 
         Ok(FileDescriptor::new(descriptor))
     }
 }
 
-/// closes a file given its descriptor number
+/// drops a reference to an open file given its descriptor number, closing it if nothing else refers to it
 pub fn close_file(descriptor: usize) {
     unsafe {
+        if let Some(Some(file)) = OPEN_FILES.get_mut(descriptor) {
+            file.references -= 1;
+
+            if file.references > 0 {
+                return;
+            }
+        }
+
         FILE_DESCRIPTOR_BITSET.clear(descriptor);
         OPEN_FILES[descriptor] = None;
     }
@@ -70,8 +95,10 @@ pub fn close_file(descriptor: usize) {
 
 /// closes a file descriptor
 pub fn close(file: &mut FileDescriptor) {
-    close_file(file.index);
-    file.valid = false;
+    if file.valid {
+        close_file(file.index);
+        file.valid = false;
+    }
 }
 
 /// controls how FileDescriptor::seek() seeks
@@ -91,9 +118,6 @@ pub struct FileDescriptor {
     /// index of this file descriptor into the file descriptor vec
     index: usize,
 
-    /// offset for writing into the file
-    pub offset: usize,
-
     /// whether this file descriptor is valid or not
     valid: bool,
 }
@@ -103,11 +127,21 @@ impl FileDescriptor {
     fn new(index: usize) -> Self {
         Self {
             index,
-            offset: 0,
             valid: true,
         }
     }
 
+    /// gives another file descriptor referring to the same open file, like after fork or dup. they share the file's offset
+    pub fn duplicate(&self) -> Result<FileDescriptor, Errno> {
+        match self.get_mut_reference() {
+            Some(file) => {
+                file.references += 1;
+                Ok(FileDescriptor::new(self.index))
+            },
+            None => Err(Errno::BadFile),
+        }
+    }
+
     /// get reference to our file
     fn get_reference(&self) -> Option<&OpenFile<'static>> {
         if self.valid {
@@ -148,8 +182,8 @@ impl FileDescriptor {
     pub fn write(&mut self, bytes: &[u8]) -> Result<usize, Errno> {
         match self.get_mut_reference() {
             Some(file) => {
-                let amt = file.file.write_at(bytes, self.offset)?;
-                self.offset += amt;
+                let amt = file.file.write_at(bytes, file.offset)?;
+                file.offset += amt;
                 Ok(amt)
             },
             None => Err(Errno::BadFile),
@@ -167,7 +201,7 @@ impl FileDescriptor {
     /// checks if there's enough room to write the provided amount of bytes into the file
     pub fn can_write(&mut self, space: usize) -> bool {
         match self.get_reference() {
-            Some(file) => file.file.can_write_at(space, self.offset),
+            Some(file) => file.file.can_write_at(space, file.offset),
             None => false,
         }
     }
@@ -185,8 +219,8 @@ impl FileDescriptor {
     pub fn read(&mut self, bytes: &mut [u8]) -> Result<usize, Errno> {
         match self.get_mut_reference() {
             Some(file) => {
-                let amt = file.file.read_at(bytes, self.offset)?;
-                self.offset += amt;
+                let amt = file.file.read_at(bytes, file.offset)?;
+                file.offset += amt;
                 Ok(amt)
             },
             None => Err(Errno::BadFile),
@@ -204,7 +238,7 @@ impl FileDescriptor {
     /// checks if there's enough room to read the provided amount of bytes from the file
     pub fn can_read(&mut self, space: usize) -> bool {
         match self.get_reference() {
-            Some(file) => file.file.can_read_at(space, self.offset),
+            Some(file) => file.file.can_read_at(space, file.offset),
             None => false,
         }
     }
@@ -221,32 +255,32 @@ impl FileDescriptor {
     /// seek file
     /// seek behavior depends on the SeekType provided
     pub fn seek(&mut self, offset: isize, kind: SeekType) -> Result<usize, Errno> {
-        match self.get_reference() {
+        match self.get_mut_reference() {
             Some(file) => {
                 let size = file.file.get_size();
 
                 match kind {
-                    SeekType::Set => self.offset = offset as usize,
+                    SeekType::Set => file.offset = offset as usize,
                     SeekType::Current => {
                         if offset > 0 {
-                            self.offset = self.offset.wrapping_add(offset as usize); // we can wrap since if it goes below zero it'll be bigger than the file size, and thus fail
+                            file.offset = file.offset.wrapping_add(offset as usize); // we can wrap since if it goes below zero it'll be bigger than the file size, and thus fail
                         } else {
-                            self.offset = self.offset.wrapping_sub((-offset) as usize);
+                            file.offset = file.offset.wrapping_sub((-offset) as usize);
                         }
                     },
                     SeekType::End => {
                         if offset > 0 {
                             return Err(Errno::InvalidSeek);
                         } else {
-                            self.offset = size.wrapping_sub((-offset) as usize);
+                            file.offset = size.wrapping_sub((-offset) as usize);
                         }
                     },
                 }
 
-                if self.offset > size {
+                if file.offset > size {
                     Err(Errno::InvalidSeek)
                 } else {
-                    Ok(self.offset)
+                    Ok(file.offset)
                 }
             },
             None => Err(Errno::BadFile),
@@ -288,6 +322,15 @@ impl FileDescriptor {
             None => Err(Errno::BadFile),
         }
     }
+
+
+    /// performs a device specific operation on the file
+    pub fn ioctl(&mut self, request: u32, arg: u32) -> Result<u32, Errno> {
+        match self.get_mut_reference() {
+            Some(file) => file.file.ioctl(request, arg),
+            None => Err(Errno::BadFile),
+        }
+    }
 }
 
 impl Drop for FileDescriptor {
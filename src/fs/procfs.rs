@@ -0,0 +1,31 @@
+//! /proc, where files describing the kernel and hardware live
+
+use crate::errno::Errno;
+use alloc::{
+    boxed::Box,
+    format,
+};
+use super::{
+    kernfs::{StaticFile, add_file, create_directory},
+    tree::File,
+    vfs::Permissions,
+};
+
+/// name of the directory information files are put in
+pub const PROC_DIR_NAME: &str = "proc";
+
+/// adds a file to /proc
+pub fn add_proc_file(file: Box<dyn File>) -> Result<(), Errno> {
+    add_file(PROC_DIR_NAME, file)
+}
+
+/// creates /proc and the files in it. the cpu doesn't change, so /proc/cpuinfo is only put together once
+pub fn init() {
+    create_directory(PROC_DIR_NAME, Permissions::OwnerRead | Permissions::OwnerExecute | Permissions::GroupRead | Permissions::GroupExecute | Permissions::OtherRead | Permissions::OtherExecute);
+
+    let cpuinfo = format!("{}", crate::arch::cpu::cpu_info());
+
+    if let Err(err) = add_proc_file(Box::new(StaticFile::new("cpuinfo", cpuinfo.into_bytes()))) {
+        error!("couldn't add /proc/cpuinfo: {}", err);
+    }
+}
@@ -83,6 +83,13 @@ pub trait File {
 
     /// gets size of file
     fn get_size(&self) -> usize;
+
+
+    /// performs a device specific operation on the file
+    /// only device files support this, so the default is to fail
+    fn ioctl(&mut self, _request: u32, _arg: u32) -> Result<u32, Errno> {
+        Err(Errno::WrongIOControl)
+    }
 }
 
 pub trait Directory {
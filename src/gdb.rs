@@ -0,0 +1,443 @@
+//! gdb remote serial protocol stub
+//! boot with gdb=ttyS1 (and gdb_wait to stop before anything runs), start qemu with `-serial stdio -serial pty`
+//! and point gdb at the pty with `target remote /dev/pts/N`.
+//! everything here runs with interrupts off and talks to the port by polling, so it works no matter what the kernel was doing
+
+use core::fmt::{self, Write};
+use crate::{
+    arch::{
+        PAGE_SIZE,
+        gdb::{Registers, NUM_REGISTERS, PC_REGISTER, breakpoint, get_register, set_register, set_single_step},
+        paging::is_mapped,
+        without_interrupts,
+    },
+    platform::serial::{SerialPort, get_port},
+};
+
+/// byte gdb sends when it wants the kernel to stop
+pub const INTERRUPT: u8 = 0x03;
+
+/// biggest packet we take or send, not counting the framing and checksum
+const PACKET_SIZE: usize = 0x1000;
+
+/// signal reported when gdb interrupted the kernel
+const SIGINT: u8 = 2;
+
+/// signal reported for breakpoints and single steps
+const SIGTRAP: u8 = 5;
+
+/// error sent back for memory that isn't mapped
+const EFAULT: u8 = 14;
+
+/// error sent back for malformed packets or registers that don't exist
+const EINVAL: u8 = 22;
+
+/// serial port the stub talks on, if it's enabled
+static mut GDB_PORT: Option<usize> = None;
+
+/// whether gdb is attached, we don't tell it about stops until it is
+static mut CONNECTED: bool = false;
+
+/// whether the next stop is because gdb asked for it
+static mut INTERRUPTED: bool = false;
+
+/// the last packet received
+static mut PACKET: [u8; PACKET_SIZE] = [0; PACKET_SIZE];
+
+/// reply being put together. these are static since the heap could be locked by whatever we stopped
+static mut REPLY: Reply = Reply::new();
+
+/// a reply packet being put together
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    const fn new() -> Self {
+        Self { buf: [0; PACKET_SIZE], len: 0 }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// adds an error reply with the given errno
+    fn error(&mut self, errno: u8) {
+        let _ = write!(self, "E{:02x}", errno);
+    }
+
+    /// adds a register value, which goes over the wire in target byte order
+    fn register(&mut self, value: u32) {
+        for byte in value.to_le_bytes() {
+            let _ = write!(self, "{:02x}", byte);
+        }
+    }
+}
+
+impl Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+
+        if end > PACKET_SIZE {
+            return Err(fmt::Error);
+        }
+
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+
+        Ok(())
+    }
+}
+
+/// calculates the checksum of a packet's contents
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+/// parses a hex number, like the addresses and lengths in packets
+pub fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 8 {
+        return None;
+    }
+
+    s.iter().try_fold(0, |value, &c| Some((value << 4) | (c as char).to_digit(16)? as usize))
+}
+
+/// parses a hex encoded byte
+fn parse_byte(s: &[u8]) -> Option<u8> {
+    if s.len() == 2 {
+        parse_hex(s).map(|byte| byte as u8)
+    } else {
+        None
+    }
+}
+
+/// parses a register value, which comes in target byte order
+fn parse_register(s: &[u8]) -> Option<u32> {
+    if s.len() != 8 {
+        return None;
+    }
+
+    let mut bytes = [0; 4];
+    for (byte, hex) in bytes.iter_mut().zip(s.chunks(2)) {
+        *byte = parse_byte(hex)?;
+    }
+
+    Some(u32::from_le_bytes(bytes))
+}
+
+/// parses the "addr,length" at the start of memory packets
+pub fn parse_range(s: &[u8]) -> Option<(usize, usize)> {
+    let comma = s.iter().position(|&c| c == b',')?;
+
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
+
+/// checks whether every page in the given range is mapped
+fn range_mapped(addr: usize, len: usize) -> bool {
+    if len == 0 {
+        return true;
+    }
+
+    let end = match addr.checked_add(len - 1) {
+        Some(end) => end,
+        None => return false,
+    };
+
+    (addr / PAGE_SIZE..=end / PAGE_SIZE).all(|page| is_mapped(page * PAGE_SIZE))
+}
+
+/// waits for a byte from gdb
+fn read_byte(port: &mut SerialPort) -> u8 {
+    loop {
+        if let Some(byte) = port.poll_read() {
+            return byte;
+        }
+    }
+}
+
+/// waits for a packet from gdb and acknowledges it, asking for it again if it got mangled
+unsafe fn receive_packet(port: &mut SerialPort) -> &'static [u8] {
+    loop {
+        // anything outside of a packet (acks, ctrl+c) doesn't matter here
+        while read_byte(port) != b'$' {}
+
+        let mut len = 0;
+
+        loop {
+            match read_byte(port) {
+                b'#' => break,
+                b'$' => len = 0, // the last packet got cut off, start over
+                byte => {
+                    if len < PACKET_SIZE {
+                        PACKET[len] = byte;
+                    }
+                    len += 1;
+                },
+            }
+        }
+
+        let sum = parse_byte(&[read_byte(port), read_byte(port)]);
+
+        if len <= PACKET_SIZE && sum == Some(checksum(&PACKET[..len])) {
+            port.write_polled(b'+');
+            return &PACKET[..len];
+        }
+
+        port.write_polled(b'-');
+    }
+}
+
+/// sends a packet to gdb, sending it again until gdb says it got it
+fn send_packet(port: &mut SerialPort, data: &[u8]) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+
+    let sum = checksum(data);
+
+    loop {
+        port.write_polled(b'$');
+        for &byte in data {
+            port.write_polled(byte);
+        }
+        port.write_polled(b'#');
+        port.write_polled(HEX[(sum >> 4) as usize]);
+        port.write_polled(HEX[(sum & 0xf) as usize]);
+
+        loop {
+            match read_byte(port) {
+                b'+' => return,
+                b'-' => break,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// handles a packet from gdb, putting the response in the reply. returns true if the kernel should keep running
+fn handle_packet(packet: &[u8], regs: &mut Registers, signal: u8, reply: &mut Reply) -> bool {
+    let (&command, args) =
+        match packet.split_first() {
+            Some(split) => split,
+            None => return false,
+        };
+
+    match command {
+        // why did we stop?
+        b'?' => {
+            let _ = write!(reply, "S{:02x}", signal);
+        },
+
+        // read all registers
+        b'g' => {
+            for num in 0..NUM_REGISTERS {
+                reply.register(get_register(regs, num).unwrap_or(0));
+            }
+        },
+
+        // write all registers
+        b'G' => {
+            for (num, value) in args.chunks(8).take(NUM_REGISTERS).enumerate() {
+                if let Some(value) = parse_register(value) {
+                    set_register(regs, num, value);
+                }
+            }
+
+            let _ = reply.write_str("OK");
+        },
+
+        // read one register
+        b'p' => match parse_hex(args).and_then(|num| get_register(regs, num)) {
+            Some(value) => reply.register(value),
+            None => reply.error(EINVAL),
+        },
+
+        // write one register
+        b'P' => {
+            let written = args.iter().position(|&c| c == b'=').and_then(|equals| {
+                let num = parse_hex(&args[..equals])?;
+                let value = parse_register(&args[equals + 1..])?;
+
+                Some(set_register(regs, num, value))
+            });
+
+            match written {
+                Some(true) => { let _ = reply.write_str("OK"); },
+                _ => reply.error(EINVAL),
+            }
+        },
+
+        // read memory
+        b'm' => match parse_range(args) {
+            Some((addr, len)) if len <= PACKET_SIZE / 2 => {
+                if range_mapped(addr, len) {
+                    for i in 0..len {
+                        let byte = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+                        let _ = write!(reply, "{:02x}", byte);
+                    }
+                } else {
+                    reply.error(EFAULT);
+                }
+            },
+            _ => reply.error(EINVAL),
+        },
+
+        // write memory
+        b'M' => {
+            let colon = args.iter().position(|&c| c == b':');
+
+            match colon.and_then(|colon| Some((parse_range(&args[..colon])?, &args[colon + 1..]))) {
+                Some(((addr, len), data)) if data.len() == len * 2 => {
+                    if range_mapped(addr, len) {
+                        for (i, hex) in data.chunks(2).enumerate() {
+                            if let Some(byte) = parse_byte(hex) {
+                                unsafe { core::ptr::write_volatile((addr + i) as *mut u8, byte); }
+                            }
+                        }
+
+                        let _ = reply.write_str("OK");
+                    } else {
+                        reply.error(EFAULT);
+                    }
+                },
+                _ => reply.error(EINVAL),
+            }
+        },
+
+        // continue or single step, optionally from a different address
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                set_register(regs, PC_REGISTER, addr as u32);
+            }
+
+            set_single_step(regs, command == b's');
+
+            return true;
+        },
+
+        // detach or kill, either way gdb is going away and the kernel should carry on
+        b'D' | b'k' => {
+            unsafe { CONNECTED = false; }
+            set_single_step(regs, false);
+
+            if command == b'D' {
+                let _ = reply.write_str("OK");
+            }
+
+            return true;
+        },
+
+        // select thread, there's only one as far as gdb is concerned
+        b'H' => { let _ = reply.write_str("OK"); },
+
+        b'q' => {
+            if args.starts_with(b"Supported") {
+                let _ = write!(reply, "PacketSize={:x}", PACKET_SIZE);
+            } else if args.starts_with(b"Attached") {
+                let _ = reply.write_str("1"); // we were already running, so gdb shouldn't kill us when it leaves
+            }
+        },
+
+        // anything else gets an empty reply, meaning it isn't supported
+        _ => (),
+    }
+
+    false
+}
+
+/// talks to gdb until it says to keep going. called by the breakpoint and debug exception handlers, and by IRQs after a break-in
+pub fn handle_trap(regs: &mut Registers) {
+    let port =
+        match unsafe { GDB_PORT }.and_then(get_port) {
+            Some(port) => port,
+            None => return,
+        };
+
+    unsafe {
+        let signal = if core::mem::replace(&mut INTERRUPTED, false) { SIGINT } else { SIGTRAP };
+        let reply = &mut REPLY;
+
+        // gdb is waiting to hear that we stopped, unless it's only just showing up
+        if CONNECTED {
+            reply.clear();
+            let _ = write!(reply, "S{:02x}", signal);
+            send_packet(port, reply.as_bytes());
+        }
+
+        loop {
+            let packet = receive_packet(port);
+            CONNECTED = true;
+
+            reply.clear();
+            let resume = handle_packet(packet, regs, signal, reply);
+
+            if !resume || !reply.is_empty() {
+                send_packet(port, reply.as_bytes());
+            }
+
+            if resume {
+                break;
+            }
+        }
+    }
+}
+
+/// stops the kernel because gdb asked, called when ctrl+c comes in on the stub's port.
+/// this only marks the stop, the IRQ handler stops once it's done so gdb sees the interrupted registers instead of its own
+pub fn break_in() {
+    unsafe {
+        if GDB_PORT.is_some() {
+            INTERRUPTED = true;
+        }
+    }
+}
+
+/// whether ctrl+c came in and the IRQ handler should stop for gdb with the frame it interrupted
+pub fn break_in_pending() -> bool {
+    unsafe { INTERRUPTED }
+}
+
+/// starts the stub on the port given with gdb=, this has to happen before serial ports get ttys
+pub fn init() {
+    let name =
+        match crate::cmdline::get_str("gdb") {
+            Some(name) => name,
+            None => return,
+        };
+
+    let num = name.strip_prefix("ttyS").and_then(|num| num.parse::<usize>().ok());
+
+    match num.and_then(|num| get_port(num).map(|port| (num, port))) {
+        Some((num, port)) => {
+            without_interrupts(|| port.debugger = true);
+
+            unsafe {
+                GDB_PORT = Some(num);
+                crate::arch::gdb::init();
+            }
+
+            if num == 0 {
+                warn!("log output goes out ttyS0 too, gdb might get confused");
+            }
+
+            log!("gdb stub running on {}", name);
+        },
+        None => log!("can't run gdb stub on {}: no such serial port", name),
+    }
+}
+
+/// stops and waits for gdb to attach, if gdb_wait was given
+pub fn wait() {
+    if unsafe { GDB_PORT }.is_some() && crate::cmdline::get_bool("gdb_wait").unwrap_or(false) {
+        log!("waiting for gdb");
+        breakpoint();
+    }
+}
@@ -0,0 +1,434 @@
+//! keyboard input
+//! platform keyboard drivers translate their scancodes into key codes and hand them to us,
+//! and we apply the current keymap and queue up events for whatever's reading input
+
+use bitmask_enum::bitmask;
+use num_enum::TryFromPrimitive;
+use crate::{
+    arch::without_interrupts,
+    console::{get_active_console, switch_console},
+    util::ring::RingBuffer,
+};
+
+/// physical keys on a keyboard
+/// these are numbered after the PC scancode set 1 make codes, with 0x80 added for extended (0xe0 prefixed) keys
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum KeyCode {
+    Escape = 0x01,
+    Key1 = 0x02,
+    Key2 = 0x03,
+    Key3 = 0x04,
+    Key4 = 0x05,
+    Key5 = 0x06,
+    Key6 = 0x07,
+    Key7 = 0x08,
+    Key8 = 0x09,
+    Key9 = 0x0a,
+    Key0 = 0x0b,
+    Minus = 0x0c,
+    Equals = 0x0d,
+    Backspace = 0x0e,
+    Tab = 0x0f,
+    Q = 0x10,
+    W = 0x11,
+    E = 0x12,
+    R = 0x13,
+    T = 0x14,
+    Y = 0x15,
+    U = 0x16,
+    I = 0x17,
+    O = 0x18,
+    P = 0x19,
+    LeftBracket = 0x1a,
+    RightBracket = 0x1b,
+    Enter = 0x1c,
+    LeftControl = 0x1d,
+    A = 0x1e,
+    S = 0x1f,
+    D = 0x20,
+    F = 0x21,
+    G = 0x22,
+    H = 0x23,
+    J = 0x24,
+    K = 0x25,
+    L = 0x26,
+    Semicolon = 0x27,
+    Quote = 0x28,
+    Backtick = 0x29,
+    LeftShift = 0x2a,
+    Backslash = 0x2b,
+    Z = 0x2c,
+    X = 0x2d,
+    C = 0x2e,
+    V = 0x2f,
+    B = 0x30,
+    N = 0x31,
+    M = 0x32,
+    Comma = 0x33,
+    Period = 0x34,
+    Slash = 0x35,
+    RightShift = 0x36,
+    KeypadMultiply = 0x37,
+    LeftAlt = 0x38,
+    Space = 0x39,
+    CapsLock = 0x3a,
+    F1 = 0x3b,
+    F2 = 0x3c,
+    F3 = 0x3d,
+    F4 = 0x3e,
+    F5 = 0x3f,
+    F6 = 0x40,
+    F7 = 0x41,
+    F8 = 0x42,
+    F9 = 0x43,
+    F10 = 0x44,
+    NumLock = 0x45,
+    ScrollLock = 0x46,
+    Keypad7 = 0x47,
+    Keypad8 = 0x48,
+    Keypad9 = 0x49,
+    KeypadMinus = 0x4a,
+    Keypad4 = 0x4b,
+    Keypad5 = 0x4c,
+    Keypad6 = 0x4d,
+    KeypadPlus = 0x4e,
+    Keypad1 = 0x4f,
+    Keypad2 = 0x50,
+    Keypad3 = 0x51,
+    Keypad0 = 0x52,
+    KeypadPeriod = 0x53,
+    NonUsBackslash = 0x56,
+    F11 = 0x57,
+    F12 = 0x58,
+
+    // extended keys
+    KeypadEnter = 0x9c,
+    RightControl = 0x9d,
+    KeypadDivide = 0xb5,
+    PrintScreen = 0xb7,
+    RightAlt = 0xb8,
+    Pause = 0xc5,
+    Home = 0xc7,
+    Up = 0xc8,
+    PageUp = 0xc9,
+    Left = 0xcb,
+    Right = 0xcd,
+    End = 0xcf,
+    Down = 0xd0,
+    PageDown = 0xd1,
+    Insert = 0xd2,
+    Delete = 0xd3,
+    LeftSuper = 0xdb,
+    RightSuper = 0xdc,
+    Menu = 0xdd,
+}
+
+impl KeyCode {
+    /// whether this key is on the keypad and only types something when num lock is on
+    pub fn is_keypad_number(&self) -> bool {
+        matches!(self,
+            Self::Keypad0 | Self::Keypad1 | Self::Keypad2 | Self::Keypad3 | Self::Keypad4 |
+            Self::Keypad5 | Self::Keypad6 | Self::Keypad7 | Self::Keypad8 | Self::Keypad9 |
+            Self::KeypadPeriod)
+    }
+}
+
+/// modifier keys and lock states
+#[bitmask(u16)]
+pub enum Modifiers {
+    None            = Self(0),
+    LeftShift       = Self(1 << 0),
+    RightShift      = Self(1 << 1),
+    LeftControl     = Self(1 << 2),
+    RightControl    = Self(1 << 3),
+    LeftAlt         = Self(1 << 4),
+    RightAlt        = Self(1 << 5),
+    CapsLock        = Self(1 << 6),
+    NumLock         = Self(1 << 7),
+    ScrollLock      = Self(1 << 8),
+}
+
+impl Modifiers {
+    /// whether either shift key is held
+    pub fn shift(&self) -> bool {
+        *self & (Self::LeftShift | Self::RightShift) != 0
+    }
+
+    /// whether either control key is held
+    pub fn control(&self) -> bool {
+        *self & (Self::LeftControl | Self::RightControl) != 0
+    }
+
+    /// whether either alt key is held
+    pub fn alt(&self) -> bool {
+        *self & (Self::LeftAlt | Self::RightAlt) != 0
+    }
+
+    /// gets the modifier a key controls, if it's a modifier key
+    pub fn from_key(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::LeftShift => Some(Self::LeftShift),
+            KeyCode::RightShift => Some(Self::RightShift),
+            KeyCode::LeftControl => Some(Self::LeftControl),
+            KeyCode::RightControl => Some(Self::RightControl),
+            KeyCode::LeftAlt => Some(Self::LeftAlt),
+            KeyCode::RightAlt => Some(Self::RightAlt),
+            _ => None,
+        }
+    }
+
+    /// gets the lock state a key toggles, if it's a lock key
+    pub fn lock_from_key(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::CapsLock => Some(Self::CapsLock),
+            KeyCode::NumLock => Some(Self::NumLock),
+            KeyCode::ScrollLock => Some(Self::ScrollLock),
+            _ => None,
+        }
+    }
+}
+
+/// a key being pressed or released
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// the key that was pressed or released
+    pub key: KeyCode,
+
+    /// whether the key was pressed (or repeated) rather than released
+    pub pressed: bool,
+
+    /// modifiers held when the event happened
+    pub modifiers: Modifiers,
+
+    /// character the key types according to the current keymap, if any
+    pub ascii: Option<u8>,
+}
+
+/// maps keys to the characters they type
+pub struct Keymap {
+    /// name of this keymap
+    pub name: &'static str,
+
+    /// list of keys along with the character they type normally and with shift held
+    pub keys: &'static [(KeyCode, u8, u8)],
+}
+
+impl Keymap {
+    /// gets the character a key types with the given modifiers, if any
+    pub fn translate(&self, key: KeyCode, modifiers: Modifiers) -> Option<u8> {
+        if key.is_keypad_number() && modifiers & Modifiers::NumLock == 0 {
+            return None;
+        }
+
+        let (_, normal, shifted) = self.keys.iter().find(|(k, _, _)| *k == key)?;
+
+        // caps lock only applies to letters, and shift undoes it
+        let shift = if normal.is_ascii_alphabetic() { modifiers.shift() != (modifiers & Modifiers::CapsLock != 0) } else { modifiers.shift() };
+        let c = if shift { *shifted } else { *normal };
+
+        if modifiers.control() {
+            match c {
+                b'@'..=b'_' | b'a'..=b'z' => Some(c & 0x1f),
+                b'?' => Some(0x7f),
+                _ => Some(c),
+            }
+        } else {
+            Some(c)
+        }
+    }
+}
+
+/// standard US QWERTY keymap
+pub static US_KEYMAP: Keymap = Keymap {
+    name: "us",
+    keys: &[
+        (KeyCode::Escape, 0x1b, 0x1b),
+        (KeyCode::Key1, b'1', b'!'),
+        (KeyCode::Key2, b'2', b'@'),
+        (KeyCode::Key3, b'3', b'#'),
+        (KeyCode::Key4, b'4', b'$'),
+        (KeyCode::Key5, b'5', b'%'),
+        (KeyCode::Key6, b'6', b'^'),
+        (KeyCode::Key7, b'7', b'&'),
+        (KeyCode::Key8, b'8', b'*'),
+        (KeyCode::Key9, b'9', b'('),
+        (KeyCode::Key0, b'0', b')'),
+        (KeyCode::Minus, b'-', b'_'),
+        (KeyCode::Equals, b'=', b'+'),
+        (KeyCode::Backspace, 0x7f, 0x7f),
+        (KeyCode::Tab, b'\t', b'\t'),
+        (KeyCode::Q, b'q', b'Q'),
+        (KeyCode::W, b'w', b'W'),
+        (KeyCode::E, b'e', b'E'),
+        (KeyCode::R, b'r', b'R'),
+        (KeyCode::T, b't', b'T'),
+        (KeyCode::Y, b'y', b'Y'),
+        (KeyCode::U, b'u', b'U'),
+        (KeyCode::I, b'i', b'I'),
+        (KeyCode::O, b'o', b'O'),
+        (KeyCode::P, b'p', b'P'),
+        (KeyCode::LeftBracket, b'[', b'{'),
+        (KeyCode::RightBracket, b']', b'}'),
+        (KeyCode::Enter, b'\n', b'\n'),
+        (KeyCode::A, b'a', b'A'),
+        (KeyCode::S, b's', b'S'),
+        (KeyCode::D, b'd', b'D'),
+        (KeyCode::F, b'f', b'F'),
+        (KeyCode::G, b'g', b'G'),
+        (KeyCode::H, b'h', b'H'),
+        (KeyCode::J, b'j', b'J'),
+        (KeyCode::K, b'k', b'K'),
+        (KeyCode::L, b'l', b'L'),
+        (KeyCode::Semicolon, b';', b':'),
+        (KeyCode::Quote, b'\'', b'"'),
+        (KeyCode::Backtick, b'`', b'~'),
+        (KeyCode::Backslash, b'\\', b'|'),
+        (KeyCode::Z, b'z', b'Z'),
+        (KeyCode::X, b'x', b'X'),
+        (KeyCode::C, b'c', b'C'),
+        (KeyCode::V, b'v', b'V'),
+        (KeyCode::B, b'b', b'B'),
+        (KeyCode::N, b'n', b'N'),
+        (KeyCode::M, b'm', b'M'),
+        (KeyCode::Comma, b',', b'<'),
+        (KeyCode::Period, b'.', b'>'),
+        (KeyCode::Slash, b'/', b'?'),
+        (KeyCode::KeypadMultiply, b'*', b'*'),
+        (KeyCode::Space, b' ', b' '),
+        (KeyCode::Keypad7, b'7', b'7'),
+        (KeyCode::Keypad8, b'8', b'8'),
+        (KeyCode::Keypad9, b'9', b'9'),
+        (KeyCode::KeypadMinus, b'-', b'-'),
+        (KeyCode::Keypad4, b'4', b'4'),
+        (KeyCode::Keypad5, b'5', b'5'),
+        (KeyCode::Keypad6, b'6', b'6'),
+        (KeyCode::KeypadPlus, b'+', b'+'),
+        (KeyCode::Keypad1, b'1', b'1'),
+        (KeyCode::Keypad2, b'2', b'2'),
+        (KeyCode::Keypad3, b'3', b'3'),
+        (KeyCode::Keypad0, b'0', b'0'),
+        (KeyCode::KeypadPeriod, b'.', b'.'),
+        (KeyCode::NonUsBackslash, b'\\', b'|'),
+        (KeyCode::KeypadEnter, b'\n', b'\n'),
+        (KeyCode::KeypadDivide, b'/', b'/'),
+    ],
+};
+
+/// how many lines Shift+PageUp/PageDown scroll the console by
+const SCROLLBACK_PAGE_LINES: usize = 12;
+
+/// the keymap used to translate keys into characters
+static mut KEYMAP: &Keymap = &US_KEYMAP;
+
+/// current state of the modifier keys
+static mut MODIFIERS: Modifiers = Modifiers::None;
+
+/// key events waiting to be read
+static mut INPUT_QUEUE: RingBuffer<KeyEvent, 256> = RingBuffer::new(KeyEvent {
+    key: KeyCode::Escape,
+    pressed: false,
+    modifiers: Modifiers::None,
+    ascii: None,
+});
+
+/// function that gets key events instead of the input queue, if set
+static mut EVENT_HANDLER: Option<fn(KeyEvent)> = None;
+
+/// sets a function to handle key events as they come in, instead of queueing them up
+/// this is called from interrupt handlers, so it shouldn't block
+pub fn set_event_handler(handler: Option<fn(KeyEvent)>) {
+    unsafe { EVENT_HANDLER = handler; }
+}
+
+/// sets the keymap used to translate keys into characters
+pub fn set_keymap(keymap: &'static Keymap) {
+    debug!("using keymap {}", keymap.name);
+    unsafe { KEYMAP = keymap; }
+}
+
+/// gets the keymap used to translate keys into characters
+pub fn get_keymap() -> &'static Keymap {
+    unsafe { KEYMAP }
+}
+
+/// gets the current state of the modifier keys
+pub fn get_modifiers() -> Modifiers {
+    unsafe { MODIFIERS }
+}
+
+/// gets the index of the virtual console a function key switches to
+fn console_switch_index(key: KeyCode) -> Option<usize> {
+    match key {
+        KeyCode::F1 => Some(0),
+        KeyCode::F2 => Some(1),
+        KeyCode::F3 => Some(2),
+        KeyCode::F4 => Some(3),
+        KeyCode::F5 => Some(4),
+        KeyCode::F6 => Some(5),
+        _ => None,
+    }
+}
+
+/// called by keyboard drivers when a key is pressed or released, usually from an interrupt handler
+/// returns the lock states if they changed, so the driver can update the keyboard's LEDs
+pub fn handle_key(key: KeyCode, pressed: bool) -> Option<Modifiers> {
+    let mut locks_changed = false;
+
+    unsafe {
+        if let Some(modifier) = Modifiers::from_key(key) {
+            if pressed {
+                MODIFIERS |= modifier;
+            } else {
+                MODIFIERS &= !modifier;
+            }
+        } else if let Some(lock) = Modifiers::lock_from_key(key) {
+            if pressed {
+                MODIFIERS ^= lock;
+                locks_changed = true;
+            }
+        }
+    }
+
+    let modifiers = get_modifiers();
+
+    // shift+page up/down scroll through the console's scrollback
+    if pressed && modifiers.shift() && (key == KeyCode::PageUp || key == KeyCode::PageDown) {
+        if let Some(console) = get_active_console() {
+            if key == KeyCode::PageUp {
+                console.scroll_view_back(SCROLLBACK_PAGE_LINES);
+            } else {
+                console.scroll_view_forward(SCROLLBACK_PAGE_LINES);
+            }
+        }
+    } else if let (true, Some(index)) = (pressed && modifiers.alt(), console_switch_index(key)) {
+        // alt+f1 through f6 switch virtual consoles
+        if let Err(err) = switch_console(index) {
+            debug!("couldn't switch to console {}: {}", index, err);
+        }
+    } else {
+        let event = KeyEvent {
+            key,
+            pressed,
+            modifiers,
+            ascii: if pressed { get_keymap().translate(key, modifiers) } else { None },
+        };
+
+        match unsafe { EVENT_HANDLER } {
+            Some(handler) => handler(event),
+            // if nothing's reading input, just drop new events
+            None => { let _ = unsafe { INPUT_QUEUE.push(event) }; },
+        }
+    }
+
+    if locks_changed {
+        Some(modifiers & (Modifiers::CapsLock | Modifiers::NumLock | Modifiers::ScrollLock))
+    } else {
+        None
+    }
+}
+
+/// takes the oldest key event out of the input queue
+pub fn read_key_event() -> Option<KeyEvent> {
+    without_interrupts(|| unsafe { INPUT_QUEUE.pop() })
+}
@@ -10,59 +10,486 @@
  */
 use core::sync::atomic;
 use core::fmt;
-use crate::console::get_console;
+use num_enum::TryFromPrimitive;
+use alloc::boxed::Box;
+use crate::{
+    arch::without_interrupts,
+    cmdline,
+    console::get_console,
+    fs::{
+        devfs::add_device,
+        kernfs::ReadOnlyFile,
+    },
+    platform::irq::{TICKS, TIMER_RATE},
+    util::ring::RingBuffer,
+};
+
+/// how important a message is, lower is more important
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    /// name of the level as it shows up in messages
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// parses a level from its name, or from a linux style number (7 is debug)
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => match cmdline::parse_int(name)? {
+                0..=3 => Some(Level::Error),
+                4 => Some(Level::Warn),
+                5 | 6 => Some(Level::Info),
+                7 => Some(Level::Debug),
+                _ => Some(Level::Trace),
+            },
+        }
+    }
+}
+
+/// somewhere log messages are written to
+pub struct Sink {
+    pub name: &'static str,
+
+    /// least important messages that get written here
+    pub level: Level,
+
+    /// writes part of a message
+    write: fn(&str),
+}
+
+/// writes to the serial port
+fn write_serial(s: &str) {
+    unsafe {
+        crate::platform::debug::puts(s);
+    }
+}
+
+/// writes to the console
+fn write_console(s: &str) {
+    if let Some(console) = get_console() {
+        console.puts(s);
+    }
+}
+
+/// where log messages go, by default debug messages only go to serial so they don't flood the screen
+static mut SINKS: [Sink; 2] = [
+    Sink {
+        name: "serial",
+        level: Level::Trace,
+        write: write_serial,
+    },
+    Sink {
+        name: "console",
+        level: Level::Info,
+        write: write_console,
+    },
+];
+
+/// gets a sink by its name
+pub fn get_sink(name: &str) -> Option<&'static mut Sink> {
+    unsafe { SINKS.iter_mut().find(|sink| sink.name == name) }
+}
+
+/// least important messages that get logged from modules without a filter
+static mut LOG_LEVEL: Level = if cfg!(debug_messages) { Level::Debug } else { Level::Info };
+
+/// maximum amount of per module filters
+pub const MAX_FILTERS: usize = 16;
+
+/// per module log levels, as module path prefixes without the crate name (like `fs` or `arch::i586::paging`)
+static mut FILTERS: [Option<(&'static str, Level)>; MAX_FILTERS] = [None; MAX_FILTERS];
+
+/// sets the least important messages that get logged from modules without a filter
+pub fn set_log_level(level: Level) {
+    unsafe { LOG_LEVEL = level; }
+}
+
+/// sets the log level for a module and everything in it
+pub fn set_module_level(module: &'static str, level: Level) -> Result<(), &'static str> {
+    unsafe {
+        let slot = FILTERS.iter_mut()
+            .find(|filter| matches!(filter, Some((other, _)) if *other == module))
+            .or_else(|| FILTERS.iter_mut().find(|filter| filter.is_none()))
+            .ok_or("too many log filters")?;
+
+        *slot = Some((module, level));
+    }
+
+    Ok(())
+}
+
+/// checks whether a filter's module contains the given module
+fn filter_matches(filter: &str, module: &str) -> bool {
+    match module.strip_prefix(filter) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// gets the least important messages that get logged from a module, using the most specific filter that matches
+pub fn module_level(module: &str) -> Level {
+    // module paths start with the crate name, which filters leave out
+    let module = module.split_once("::").map_or("", |(_, rest)| rest);
+
+    unsafe {
+        FILTERS.iter()
+            .flatten()
+            .filter(|(filter, _)| filter_matches(filter, module))
+            .max_by_key(|(filter, _)| filter.len())
+            .map_or(LOG_LEVEL, |(_, level)| *level)
+    }
+}
+
+/// checks whether a message from the given module at the given level would get logged
+pub fn enabled(level: Level, module: &str) -> bool {
+    level <= module_level(module)
+}
+
+/// size of the buffer holding recent log messages
+pub const LOG_BUFFER_SIZE: usize = 16384;
+
+/// recent log messages, the oldest get thrown away when it fills up
+static mut LOG_BUFFER: RingBuffer<u8, LOG_BUFFER_SIZE> = RingBuffer::new(0);
+
+/// adds text to the log buffer
+fn write_buffer(s: &str) {
+    unsafe {
+        for &b in s.as_bytes() {
+            if LOG_BUFFER.is_full() {
+                LOG_BUFFER.pop();
+            }
+            let _ = LOG_BUFFER.push(b);
+        }
+    }
+}
+
+/// copies recent log messages into a buffer, starting at the given offset into the log buffer
+/// returns how many bytes were copied
+pub fn read_log(bytes: &mut [u8], offset: usize) -> usize {
+    let mut len = 0;
+
+    unsafe {
+        while len < bytes.len() {
+            match LOG_BUFFER.get(offset + len) {
+                Some(b) => bytes[len] = b,
+                None => break,
+            }
+            len += 1;
+        }
+    }
+
+    len
+}
+
+/// writes part of a message to the log buffer and every sink that wants it
+fn emit(level: Level, s: &str) {
+    write_buffer(s);
+
+    for sink in unsafe { SINKS.iter() } {
+        if level <= sink.level {
+            (sink.write)(s);
+        }
+    }
+}
+
+/// ends a message, the log buffer doesn't need carriage returns
+fn emit_newline(level: Level) {
+    write_buffer("\n");
+
+    for sink in unsafe { SINKS.iter() } {
+        if level <= sink.level {
+            (sink.write)("\r\n");
+        }
+    }
+}
+
+/// size of the buffer holding messages that were logged while something else was logging
+pub const PENDING_BUFFER_SIZE: usize = 8192;
+
+/// longest message that can be held back while something else is logging, anything longer is written out straight away
+pub const MAX_DEFERRED_MESSAGE: usize = 256;
+
+/// messages logged while the lock was held (from interrupt handlers or while panicking), waiting to be written out by whoever holds it
+/// each message is its level, then its text, then a 0
+static mut PENDING: RingBuffer<u8, PENDING_BUFFER_SIZE> = RingBuffer::new(0);
+
+/// adds a whole message to the pending buffer, returning false if there isn't room for all of it.
+/// messages are only added all at once, so ones logged by different interrupt handlers can't get mixed up
+fn push_pending(level: Level, text: &[u8]) -> bool {
+    without_interrupts(|| unsafe {
+        // room for the level and the terminator too
+        if PENDING.capacity() - PENDING.len() < text.len() + 2 {
+            return false;
+        }
+
+        let _ = PENDING.push(level as u8);
+        for &b in text {
+            let _ = PENDING.push(b);
+        }
+        let _ = PENDING.push(0);
+
+        true
+    })
+}
+
+/// takes a byte out of the pending buffer
+fn pop_pending() -> Option<u8> {
+    without_interrupts(|| unsafe { PENDING.pop() })
+}
+
+/// writes out everything in the pending buffer, this must only be called while holding the lock
+fn flush_pending() {
+    while let Some(level) = pop_pending() {
+        let level = Level::try_from(level).unwrap_or(Level::Error);
+
+        let mut chunk = [0; 64];
+        let mut len = 0;
+
+        loop {
+            let end = match pop_pending() {
+                Some(0) | None => true,
+                Some(b) => {
+                    chunk[len] = b;
+                    len += 1;
+                    false
+                },
+            };
+
+            if end || len == chunk.len() {
+                // don't split up characters that span chunks
+                let valid = match core::str::from_utf8(&chunk[..len]) {
+                    Ok(_) => len,
+                    Err(err) => err.valid_up_to(),
+                };
+
+                emit(level, unsafe { core::str::from_utf8_unchecked(&chunk[..valid]) });
+
+                chunk.copy_within(valid..len, 0);
+                len -= valid;
+            }
+
+            if end {
+                break;
+            }
+        }
+
+        emit_newline(level);
+    }
+}
+
+/// writes the time since boot, level and module name that start every message
+fn write_prefix(out: &mut impl fmt::Write, level: Level, module: &str) -> fmt::Result {
+    let ticks = unsafe { TICKS };
+    let rate = unsafe { TIMER_RATE } as u64;
+    write!(out, "[{:5}.{:03}] {} [{}] ", ticks / rate, (ticks % rate) * 1000 / rate, level.name(), module)
+}
+
+/// where a writer's output goes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Output {
+    /// straight to the log buffer and sinks, we hold the lock
+    Direct,
+
+    /// into our own buffer, which goes into the pending buffer once the message is done.
+    /// someone else holds the lock and will write it out when they're done
+    Deferred,
+
+    /// straight to the log buffer and sinks even though someone else holds the lock, since the message didn't fit in a buffer.
+    /// it ends up in the middle of their message, but it isn't lost
+    Interleaved,
+}
 
 /// A formatter object
-pub struct Writer(bool);
+pub struct Writer {
+    /// where output goes
+    output: Output,
+
+    /// level of the message being written
+    level: Level,
+
+    /// the message so far, if it's being deferred
+    buffer: [u8; MAX_DEFERRED_MESSAGE],
+
+    /// how much of the buffer is used
+    len: usize,
+}
 
 /// A primitive lock for the logging output
 ///
 /// This is not really a lock. Since there is no threading at the moment, all
-/// it does is prevent writing when a collision would occur.
+/// it does is detect when a message is logged in the middle of another one, so that it can be put aside until the first is done.
 static LOGGING_LOCK: atomic::AtomicBool = atomic::AtomicBool::new(false);
 
+/// set once we've panicked, after which everything is written directly since whatever held the lock isn't coming back
+static PANICKING: atomic::AtomicBool = atomic::AtomicBool::new(false);
+
+/// whether a message was being written directly when we panicked, so we know to end its line
+static LINE_OPEN: atomic::AtomicBool = atomic::AtomicBool::new(false);
+
+/// releases the lock, writing out anything that was logged while it was held
+fn release_lock() {
+    loop {
+        flush_pending();
+        LOGGING_LOCK.store(false, atomic::Ordering::Release);
+
+        // something could have been logged between flushing and releasing, so it's up to us to get it out if nobody else takes the lock
+        if without_interrupts(|| unsafe { PENDING.is_empty() }) || LOGGING_LOCK.swap(true, atomic::Ordering::Acquire) {
+            break;
+        }
+    }
+}
+
+/// takes over logging when we panic, writing out everything that's pending so crash reports are complete
+pub fn panic_flush() {
+    PANICKING.store(true, atomic::Ordering::SeqCst);
+
+    // whatever was being written when we panicked isn't getting finished
+    if LINE_OPEN.swap(false, atomic::Ordering::SeqCst) {
+        emit_newline(Level::Error);
+    }
+
+    flush_pending();
+}
+
 impl Writer {
     /// Obtain a logger for the specified module
-    pub fn get(module: &str) -> Writer {
-        // This "acquires" the lock (actually just disables output if paralel writes are attempted
-        let mut ret = Writer(! LOGGING_LOCK.swap(true, atomic::Ordering::Acquire));
-        
-        // Print the module name before returning (prefixes all messages)
-        {
-            use core::fmt::Write;
-            let _ = write!(&mut ret, "[{}] ", module);
-        }
-        
+    pub fn get(level: Level, module: &str) -> Writer {
+        let output =
+            if PANICKING.load(atomic::Ordering::SeqCst) || !LOGGING_LOCK.swap(true, atomic::Ordering::Acquire) {
+                LINE_OPEN.store(true, atomic::Ordering::SeqCst);
+                Output::Direct
+            } else {
+                Output::Deferred
+            };
+
+        let mut ret = Writer { output, level, buffer: [0; MAX_DEFERRED_MESSAGE], len: 0 };
+
+        // Print the time since boot, level and module name before returning (prefixes all messages)
+        let _ = write_prefix(&mut ret, level, module);
+
         ret
     }
+
+    /// gives up on holding the message back and writes out what we have so far, on its own line
+    fn interleave(&mut self) {
+        self.output = Output::Interleaved;
+
+        emit_newline(self.level);
+        // only whole strings are ever added to the buffer, so it's always valid
+        emit(self.level, core::str::from_utf8(&self.buffer[..self.len]).unwrap_or(""));
+    }
 }
 
 impl core::ops::Drop for Writer {
     fn drop(&mut self) {
-        // Write a terminating newline before releasing the lock
-        {
-            use core::fmt::Write;
-            let _ = write!(self, "\r\n");
-        }
-        // On drop, "release" the lock
-        if self.0 {
-            LOGGING_LOCK.store(false, atomic::Ordering::Release);
+        match self.output {
+            Output::Direct => {
+                emit_newline(self.level);
+                LINE_OPEN.store(false, atomic::Ordering::SeqCst);
+
+                if !PANICKING.load(atomic::Ordering::SeqCst) {
+                    release_lock();
+                }
+            },
+            Output::Deferred => {
+                if !push_pending(self.level, &self.buffer[..self.len]) {
+                    self.interleave();
+                    emit_newline(self.level);
+                }
+            },
+            Output::Interleaved => emit_newline(self.level),
         }
     }
 }
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        // If the lock is owned by this instance, then we can safely write to the output
-        if self.0 {
-            unsafe {
-                crate::platform::debug::puts(s);
-            }
+        match self.output {
+            Output::Direct | Output::Interleaved => emit(self.level, s),
+            Output::Deferred if self.len + s.len() <= self.buffer.len() => {
+                self.buffer[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+                self.len += s.len();
+            },
+            Output::Deferred => {
+                self.interleave();
+                emit(self.level, s);
+            },
+        }
+        Ok(())
+    }
+}
+
+/// sets log levels from the command line
+pub fn init() {
+    // debug_messages asks for debug messages before the command line is read, so only let an explicit loglevel= turn them back off
+    if !cfg!(debug_messages) || cmdline::is_set("loglevel") {
+        if let Some(level) = cmdline::get_str("loglevel").and_then(Level::parse) {
+            set_log_level(level);
+        }
+    }
+
+    if let Some(level) = cmdline::get_str("console_loglevel").and_then(Level::parse) {
+        if let Some(sink) = get_sink("console") {
+            sink.level = level;
+        }
+    }
 
-            if let Some(console) = get_console() {
-                console.puts(s);
+    // filters look like log_filter=fs:debug,arch::i586::paging:trace
+    if let Some(filters) = cmdline::get_str("log_filter") {
+        for filter in filters.split(',').filter(|filter| !filter.is_empty()) {
+            let result =
+                match filter.rsplit_once(':').and_then(|(module, level)| Some((module, Level::parse(level)?))) {
+                    Some((module, level)) => set_module_level(module, level),
+                    None => Err("bad log filter"),
+                };
+
+            if let Err(err) = result {
+                warn!("{}: {}", err, filter);
             }
         }
-        Ok(())
+    }
+}
+
+/// device file to read recent log messages from
+pub struct KmsgFile;
+
+impl ReadOnlyFile for KmsgFile {
+    fn name(&self) -> &str {
+        "kmsg"
+    }
+
+    fn size(&self) -> usize {
+        unsafe { LOG_BUFFER.len() }
+    }
+
+    fn read_contents(&self, bytes: &mut [u8], offset: usize) -> usize {
+        read_log(bytes, offset)
+    }
+}
+
+/// adds /dev/kmsg, this needs the filesystem
+pub fn init_file() {
+    if let Err(err) = add_device(Box::new(KmsgFile)) {
+        error!("couldn't add /dev/kmsg: {}", err);
     }
 }
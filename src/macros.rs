@@ -9,26 +9,47 @@
  * its use, and the author takes no liability.
  */
 
-/// A very primitive logging macro
+/// logs a message at the given level
 ///
 /// Obtaines a logger instance (locking the log channel) with the current module name passed
-/// then passes the standard format! arguments to it
-macro_rules! log {
-    ( $($arg:tt)* ) => ({
-        // Import the Writer trait (required by write!)
-        use core::fmt::Write;
-        let _ = write!(&mut crate::logging::Writer::get(module_path!()), $($arg)*);
-    })
-}
-
-/// works exactly the same as log!, however requres debug_messages to be set at compile time
-macro_rules! debug {
-    ( $($arg:tt)* ) => ({
-        #[cfg(debug_messages)]
-        {
+/// then passes the standard format! arguments to it, if messages at this level are enabled for the module
+macro_rules! log_at {
+    ( $level:expr, $($arg:tt)* ) => ({
+        let level = $level;
+        if crate::logging::enabled(level, module_path!()) {
             // Import the Writer trait (required by write!)
             use core::fmt::Write;
-            let _ = write!(&mut crate::logging::Writer::get(module_path!()), $($arg)*);
+            let _ = write!(&mut crate::logging::Writer::get(level, module_path!()), $($arg)*);
         }
     })
 }
+
+/// logs something that went wrong
+macro_rules! error {
+    ( $($arg:tt)* ) => (log_at!(crate::logging::Level::Error, $($arg)*))
+}
+
+/// logs something that might be a problem
+macro_rules! warn {
+    ( $($arg:tt)* ) => (log_at!(crate::logging::Level::Warn, $($arg)*))
+}
+
+/// logs something interesting
+macro_rules! info {
+    ( $($arg:tt)* ) => (log_at!(crate::logging::Level::Info, $($arg)*))
+}
+
+/// A very primitive logging macro, the same as info!
+macro_rules! log {
+    ( $($arg:tt)* ) => (info!($($arg)*))
+}
+
+/// logs details that are useful when things break, these only go to serial by default and only show up with loglevel=debug
+macro_rules! debug {
+    ( $($arg:tt)* ) => (log_at!(crate::logging::Level::Debug, $($arg)*))
+}
+
+/// logs way too much detail, only shows up with loglevel=trace or a log_filter for the module
+macro_rules! trace {
+    ( $($arg:tt)* ) => (log_at!(crate::logging::Level::Trace, $($arg)*))
+}
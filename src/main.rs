@@ -37,14 +37,23 @@ pub mod unwind;
 
 mod logging;
 
+pub mod cmdline;
+
+pub mod gdb;
+pub mod monitor;
+
 pub mod console;
+pub mod input;
+pub mod tty;
 
 pub mod mm;
 
 pub mod util;
 
 pub mod tasks;
+pub mod deferred;
 pub mod syscalls;
+pub mod signals;
 
 pub mod fs;
 
@@ -72,16 +81,34 @@ extern "C" {
 #[no_mangle]
 pub extern fn kmain() -> ! {
     // initialize kernel
+    cmdline::init(); // save the command line before anything can overwrite it
+
+    logging::init(); // set log level from the command line
+
     arch::init(); // platform specific initialization
 
+    gdb::init(); // start the gdb stub if asked, before serial ports get ttys
+
     mm::init(); // init memory management/heap/etc
 
     console::init(); // init console
 
     fs::init(); // init filesystems
 
+    logging::init_file(); // add /dev/kmsg to read the log from
+
+    tty::init(); // init ttys, this needs the console and filesystems
+
+    platform::init_devices(); // add platform specific device files, like serial ports
+
+    tty::init_console(); // pick the tty for /dev/console, this needs all the ttys
+
     log!("{} v{}", NAME, VERSION);
 
+    cmdline::check(); // complain about bad parameters now that they'll show up on the console
+
+    gdb::wait(); // give gdb a chance to attach before anything runs
+
     #[cfg(test)]
     {
         test_main();
@@ -93,7 +120,7 @@ pub extern fn kmain() -> ! {
 }
 
 use core::arch::asm;
-use tasks::{IN_TASK, Task, add_task, get_current_task_mut};
+use tasks::{IN_TASK, Task, add_task, get_current_task_mut, spawn_idle_task};
 use syscalls::Syscalls;
 use arch::{LINKED_BASE, PAGE_SIZE};
 
@@ -108,13 +135,38 @@ pub fn switch_to_user_mode(ptr: *const u32) -> ! {
 
     task.state.alloc_page((LINKED_BASE - PAGE_SIZE) as u32, false, true, false);
 
+    // there's no way to load programs yet, so the built in test program is always what runs first
+    if cmdline::is_set("init") {
+        log!("can't run {}, running built in init instead", cmdline::get_str("init").unwrap_or(""));
+    }
+
+    // give the task stdin, stdout and stderr on the console, and put it in the foreground
+    debug!("opening console");
+
+    for _i in 0..3 {
+        match fs::ops::open("dev/console") {
+            Ok(file) => task.files.borrow_mut().push(Some(file)),
+            Err(err) => log!("couldn't open console: {}", err),
+        }
+    }
+
+    if let Some(tty) = unsafe { tty::CONSOLE_TTY }.and_then(tty::get_tty) {
+        tty.foreground_group = Some(task.pgid);
+    }
+
     debug!("adding task");
 
     add_task(task);
 
+    // make sure there's always something to run
+    spawn_idle_task();
+
+    // start the thread that runs work interrupts leave behind
+    deferred::init();
+
     debug!("switching page tables");
 
-    get_current_task_mut().expect("no tasks?").state.pages.switch_to();
+    get_current_task_mut().expect("no tasks?").state.pages.borrow().switch_to();
 
     debug!("entering user mode @ {:#x}", ptr as u32);
 
@@ -128,20 +180,20 @@ pub fn switch_to_user_mode(ptr: *const u32) -> ! {
 #[inline(always)]
 unsafe fn syscall_is_computer_on() -> bool {
     let result: u32;
-    asm!("int 0x80", in("eax") Syscalls::IsComputerOn as u32, out("ebx") result);
+    asm!("int 0x80", inlateout("eax") Syscalls::IsComputerOn as u32 => result);
 
     result > 0
 }
 
 #[inline(always)]
 unsafe fn syscall_test_log(string: &[u8]) {
-    asm!("int 0x80", in("eax") Syscalls::TestLog as u32, in("ebx") &string[0] as *const _);
+    asm!("int 0x80", inlateout("eax") Syscalls::TestLog as u32 => _, in("ebx") &string[0] as *const _);
 }
 
 #[inline(always)]
 unsafe fn syscall_fork() -> u32 {
     let result: u32;
-    asm!("int 0x80", in("eax") Syscalls::Fork as u32, out("ebx") result);
+    asm!("int 0x80", inlateout("eax") Syscalls::Fork as u32 => result);
 
     result
 }
@@ -156,7 +208,7 @@ unsafe fn syscall_exit() {
 #[inline(always)]
 unsafe fn syscall_get_pid() -> u32 {
     let result: u32;
-    asm!("int 0x80", in("eax") Syscalls::GetPID as u32, out("ebx") result);
+    asm!("int 0x80", inlateout("eax") Syscalls::GetPID as u32 => result);
 
     result
 }
@@ -1,5 +1,5 @@
 use crate::arch::{
-    KHEAP_START, PAGE_SIZE, INV_PAGE_SIZE, halt,
+    KHEAP_START, PAGE_SIZE, INV_PAGE_SIZE, halt, without_interrupts,
     paging::alloc_page,
 };
 use core::{
@@ -37,6 +37,17 @@ static ALLOCATOR: CustomAlloc = CustomAlloc(atomic::AtomicBool::new(false));
 
 unsafe impl GlobalAlloc for CustomAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // kernel threads can be preempted, and we don't want the heap to stay locked while another task runs
+        without_interrupts(|| self.alloc_inner(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.dealloc_inner(ptr, layout))
+    }
+}
+
+impl CustomAlloc {
+    unsafe fn alloc_inner(&self, layout: Layout) -> *mut u8 {
         if let Some(heap) = KERNEL_HEAP.as_mut() {
             // check if we have the lock
             if !self.0.swap(true, atomic::Ordering::Acquire) { // we do
@@ -75,7 +86,7 @@ unsafe impl GlobalAlloc for CustomAlloc {
                         self.0.store(false, atomic::Ordering::Release);
 
                         // try again
-                        self.alloc(layout)
+                        self.alloc_inner(layout)
                     },
                 };
     
@@ -95,7 +106,7 @@ unsafe impl GlobalAlloc for CustomAlloc {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout) {
         if let Some(heap) = KERNEL_HEAP.as_mut() {
             // check if we have the lock
             if ((ptr as usize) < KHEAP_START) || ((ptr as usize) >= KHEAP_START + KHEAP_MAX_SIZE) {
@@ -0,0 +1,565 @@
+//! interactive kernel monitor
+//! if monitor= is given on the command line, panics and kernel faults drop into a little shell on that serial port
+//! (or the console) instead of going straight to halting, so whoever's watching can poke around first
+
+use core::fmt::{self, Write};
+use crate::{
+    arch::{
+        PAGE_SIZE,
+        backtrace::{Frame, backtrace, frame_pointer, kernel_symbols},
+        control_registers,
+        ints::SyscallRegisters,
+        paging::{LARGE_PAGE_SIZE, PageTableFlags, is_mapped, with_current_directory},
+        without_interrupts,
+    },
+    cmdline::parse_int,
+    console::get_console,
+    deferred,
+    fs::{
+        tree::Directory,
+        vfs::ROOT_DIR,
+    },
+    input::{Modifiers, get_keymap},
+    mm::KERNEL_HEAP,
+    platform::{
+        apic,
+        irq::{IRQ_COUNTS, SPURIOUS_COUNTS, Controller, controller, irq_handler_names, is_irq_masked},
+        keyboard::poll_key,
+        pic::NUM_IRQS,
+        power_off, reboot,
+        serial::get_port,
+    },
+    tasks::{CURRENT_TASK, TASKS},
+};
+
+/// longest command line we take
+const MAX_LINE: usize = 80;
+
+/// how many bytes a line of a memory dump shows
+const DUMP_WIDTH: usize = 16;
+
+/// how many bytes mem shows if it isn't told
+pub const DEFAULT_DUMP_SIZE: usize = 64;
+
+/// page flags that change just from using a page, ignored when grouping pages together
+const USAGE_FLAGS: u16 = 0x60; // accessed and dirty
+
+/// whether the monitor is running, so a fault in the monitor doesn't start another one
+static mut ACTIVE: bool = false;
+
+/// where the monitor takes input and sends output
+#[derive(Debug, Copy, Clone)]
+pub enum Channel {
+    /// a serial port, by number
+    Serial(usize),
+
+    /// the console and the keyboard
+    Console,
+}
+
+impl Channel {
+    /// gets the channel for the given monitor= value
+    fn from_name(name: &str) -> Option<Self> {
+        if name == "console" {
+            return Some(Self::Console);
+        }
+
+        let num = name.strip_prefix("ttyS")?.parse::<usize>().ok()?;
+        get_port(num).map(|_| Self::Serial(num))
+    }
+}
+
+/// pages mapped one after another to contiguous physical memory with the same flags
+struct PageRun {
+    start: u32,
+    end: u32,
+    phys: u32,
+    flags: u16,
+}
+
+impl PageRun {
+    /// whether a page at the given address would continue this run
+    fn follows(&self, virt: u32, phys: u32, flags: u16) -> bool {
+        virt == self.end && phys == self.phys.wrapping_add(self.end.wrapping_sub(self.start)) && flags == self.flags
+    }
+}
+
+impl fmt::Display for PageRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x}-{:#010x} -> {:#010x} {}", self.start, self.end.wrapping_sub(1), self.phys, PageTableFlags::from(self.flags))
+    }
+}
+
+/// a command typed into the monitor
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// an empty line
+    Nothing,
+    Help,
+    Registers,
+    Backtrace,
+
+    /// mapped pages, or the page an address is in
+    Pages(Option<usize>),
+
+    Tasks,
+    Heap,
+    Irqs,
+    Vfs,
+
+    /// dump memory, from an address for a length
+    Memory(usize, usize),
+
+    Continue,
+    Reboot,
+    PowerOff,
+}
+
+/// why a command line couldn't be parsed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError<'a> {
+    /// there's no command with this name
+    Unknown(&'a str),
+
+    /// the command's arguments were wrong, and what to tell whoever typed it
+    BadArguments(&'static str),
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(command) => write!(f, "unknown command {:?}, type help for a list of commands", command),
+            Self::BadArguments(message) => f.write_str(message),
+        }
+    }
+}
+
+/// parses a command line, numbers are decimal or 0x hex
+pub fn parse_command(line: &str) -> Result<Command, ParseError<'_>> {
+    let mut args = line.split_whitespace();
+    let command = args.next().unwrap_or("");
+    let number = |arg: Option<&str>| arg.and_then(parse_int);
+
+    Ok(match command {
+        "" => Command::Nothing,
+        "help" | "?" => Command::Help,
+        "regs" => Command::Registers,
+        "bt" => Command::Backtrace,
+        "pages" => {
+            let addr = args.next();
+            match (addr, number(addr)) {
+                (Some(_), None) => return Err(ParseError::BadArguments("bad address")),
+                (_, addr) => Command::Pages(addr),
+            }
+        },
+        "tasks" => Command::Tasks,
+        "heap" => Command::Heap,
+        "irqs" => Command::Irqs,
+        "vfs" => Command::Vfs,
+        "mem" => match number(args.next()) {
+            Some(addr) => Command::Memory(addr, number(args.next()).unwrap_or(DEFAULT_DUMP_SIZE)),
+            None => return Err(ParseError::BadArguments("usage: mem addr [len]")),
+        },
+        "continue" | "c" => Command::Continue,
+        "reboot" => Command::Reboot,
+        "poweroff" => Command::PowerOff,
+        _ => return Err(ParseError::Unknown(command)),
+    })
+}
+
+/// state of a running monitor
+pub struct Monitor<'a> {
+    channel: Channel,
+
+    /// modifier keys held down, when reading from the keyboard
+    modifiers: Modifiers,
+
+    /// registers of the code that faulted or panicked
+    regs: Option<&'a SyscallRegisters>,
+}
+
+impl Write for Monitor<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.channel {
+            Channel::Serial(num) => {
+                if let Some(port) = get_port(num) {
+                    for byte in s.bytes() {
+                        if byte == b'\n' {
+                            port.write_polled(b'\r');
+                        }
+                        port.write_polled(byte);
+                    }
+                }
+            },
+            Channel::Console => {
+                if let Some(console) = get_console() {
+                    console.puts(s);
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new(channel: Channel, regs: Option<&'a SyscallRegisters>) -> Self {
+        Self {
+            channel,
+            modifiers: Modifiers::None,
+            regs,
+        }
+    }
+
+    /// waits for a character
+    fn read_char(&mut self) -> u8 {
+        loop {
+            match self.channel {
+                Channel::Serial(num) => {
+                    if let Some(byte) = get_port(num).and_then(|port| port.poll_read()) {
+                        return byte;
+                    }
+                },
+                Channel::Console => {
+                    if let Some((key, pressed)) = poll_key() {
+                        if let Some(modifier) = Modifiers::from_key(key) {
+                            if pressed {
+                                self.modifiers |= modifier;
+                            } else {
+                                self.modifiers &= !modifier;
+                            }
+                        } else if let (true, Some(c)) = (pressed, get_keymap().translate(key, self.modifiers)) {
+                            return c;
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// reads a line into the buffer, echoing it back and handling backspace
+    fn read_line<'b>(&mut self, buf: &'b mut [u8; MAX_LINE]) -> &'b str {
+        let mut len = 0;
+
+        loop {
+            match self.read_char() {
+                b'\r' | b'\n' => break,
+                b'\x08' | b'\x7f' => {
+                    if len > 0 {
+                        len -= 1;
+                        let _ = self.write_str("\x08 \x08");
+                    }
+                },
+                c @ 0x20..=0x7e => {
+                    if len < MAX_LINE {
+                        buf[len] = c;
+                        len += 1;
+                        let _ = self.write_char(c as char);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let _ = self.write_str("\n");
+
+        core::str::from_utf8(&buf[..len]).unwrap_or("")
+    }
+
+    fn help(&mut self) -> fmt::Result {
+        writeln!(self, "commands:")?;
+        writeln!(self, "  regs              show registers")?;
+        writeln!(self, "  bt                show a backtrace from where the kernel stopped")?;
+        writeln!(self, "  pages [addr]      show mapped pages, or the page an address is in")?;
+        writeln!(self, "  tasks             list tasks")?;
+        writeln!(self, "  heap              show kernel heap usage")?;
+        writeln!(self, "  irqs              show interrupt counts and handlers")?;
+        writeln!(self, "  vfs               show the filesystem tree")?;
+        writeln!(self, "  mem addr [len]    dump memory, numbers are decimal or 0x hex")?;
+        writeln!(self, "  continue          leave the monitor, faults retry the instruction and panics halt")?;
+        writeln!(self, "  reboot            restart the computer")?;
+        writeln!(self, "  poweroff          turn the computer off")
+    }
+
+    fn registers(&mut self) -> fmt::Result {
+        if let Some(regs) = self.regs {
+            let regs = *regs;
+            writeln!(self, "eax {:#010x} ebx {:#010x} ecx {:#010x} edx {:#010x}", { regs.eax }, { regs.ebx }, { regs.ecx }, { regs.edx })?;
+            writeln!(self, "esi {:#010x} edi {:#010x} ebp {:#010x} esp {:#010x}", { regs.esi }, { regs.edi }, { regs.ebp }, { regs.useresp })?;
+            writeln!(self, "eip {:#010x} eflags {:#010x}", { regs.eip }, { regs.eflags })?;
+            writeln!(self, "cs {:#06x} ds {:#06x} ss {:#06x}", { regs.cs }, { regs.ds }, { regs.ss })?;
+        }
+
+        for (name, value) in control_registers() {
+            writeln!(self, "{} {:#010x}", name, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn backtrace(&mut self) -> fmt::Result {
+        let mut result = Ok(());
+
+        // start where the kernel stopped rather than in the monitor, with the instruction it stopped at as the first frame
+        let (ebp, first) =
+            match self.regs {
+                Some(regs) => {
+                    let eip = regs.eip as usize;
+                    writeln!(self, "{}", Frame { index: 0, addr: eip, symbol: kernel_symbols().lookup(eip) })?;
+                    (regs.ebp as usize, 1)
+                },
+                None => (frame_pointer(), 0),
+            };
+
+        unsafe {
+            backtrace(ebp, |mut frame| {
+                if result.is_ok() {
+                    frame.index += first;
+                    result = writeln!(self, "{}", frame);
+                }
+            });
+        }
+
+        result
+    }
+
+    fn pages(&mut self, addr: Option<usize>) -> fmt::Result {
+        if let Some(addr) = addr {
+            let entry = with_current_directory(|dir| dir.get_entry(addr as u32)).flatten();
+
+            return match entry {
+                Some(entry) if !entry.is_unused() => writeln!(self, "{}", entry),
+                _ => writeln!(self, "{:#x} isn't mapped", addr),
+            };
+        }
+
+        // group runs of pages that are mapped next to each other with the same flags, otherwise this would be thousands of lines
+        let mut run: Option<PageRun> = None;
+        let mut result = Ok(());
+
+        with_current_directory(|dir| {
+            for table_idx in 0..1024 {
+                // large pages don't have a table, but get_entry fills in what their entries would be
+                if dir.tables[table_idx].is_null() && !dir.is_large_page((table_idx * LARGE_PAGE_SIZE) as u32) {
+                    continue;
+                }
+
+                for entry_idx in 0..1024 {
+                    let virt = ((table_idx * 1024 + entry_idx) * PAGE_SIZE) as u32;
+
+                    let entry =
+                        match dir.get_entry(virt) {
+                            Some(entry) if !entry.is_unused() => entry,
+                            _ => continue,
+                        };
+
+                    let phys = entry.get_address();
+                    let flags = entry.get_flags() & !USAGE_FLAGS;
+
+                    match run.as_mut() {
+                        Some(run) if run.follows(virt, phys, flags) => run.end = virt.wrapping_add(PAGE_SIZE as u32),
+                        _ => {
+                            if let Some(run) = run.take() {
+                                result = result.and_then(|_| writeln!(self, "{}", run));
+                            }
+
+                            run = Some(PageRun { start: virt, end: virt.wrapping_add(PAGE_SIZE as u32), phys, flags });
+                        },
+                    }
+                }
+            }
+        });
+
+        if let Some(run) = run {
+            result = result.and_then(|_| writeln!(self, "{}", run));
+        }
+
+        result
+    }
+
+    fn tasks(&mut self) -> fmt::Result {
+        writeln!(self, "   id  tgid  pgid  eip         state")?;
+
+        for (index, task) in unsafe { TASKS.iter().enumerate() } {
+            let current = if index == unsafe { CURRENT_TASK } { '*' } else { ' ' };
+            let eip = task.state.registers.eip;
+
+            write!(self, "{}{:4}  {:4}  {:4}  {:#010x}  ", current, task.id, task.tgid, task.pgid, eip)?;
+
+            match task.blocked_on {
+                Some(reason) => writeln!(self, "blocked on {:?}", reason)?,
+                None if task.state.is_kernel_thread() => writeln!(self, "runnable (kernel thread)")?,
+                None => writeln!(self, "runnable")?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn heap(&mut self) -> fmt::Result {
+        match unsafe { KERNEL_HEAP.as_ref() } {
+            Some(heap) => {
+                writeln!(self, "heap @ {:#x}, {} bytes", heap.bottom(), heap.size())?;
+                writeln!(self, "{} bytes used, {} free", heap.used(), heap.free())
+            },
+            None => writeln!(self, "heap isn't set up"),
+        }
+    }
+
+    fn irqs(&mut self) -> fmt::Result {
+        match controller() {
+            Controller::Pic => writeln!(self, "using the 8259 PIC")?,
+            Controller::Apic => writeln!(self, "using the APIC, {} spurious interrupts", unsafe { apic::SPURIOUS_COUNT })?,
+        }
+
+        writeln!(self, "irq  count       spurious  handlers")?;
+
+        for irq in 0..NUM_IRQS {
+            let (count, spurious) = unsafe { (IRQ_COUNTS[irq], SPURIOUS_COUNTS[irq]) };
+            let masked = if is_irq_masked(irq as u8) { " (masked)" } else { "" };
+
+            write!(self, "{:3}  {:10}  {:8} ", irq, count, spurious)?;
+
+            let mut result = Ok(());
+            irq_handler_names(irq as u8, |name| result = result.and_then(|_| write!(self, " {}", name)));
+            result?;
+
+            writeln!(self, "{}", masked)?;
+        }
+
+        writeln!(self, "deferred work: {} pending, {} run, {} dropped", deferred::pending(), unsafe { deferred::COMPLETED }, unsafe { deferred::DROPPED })
+    }
+
+    fn tree(&mut self, dir: &dyn Directory, depth: usize) -> fmt::Result {
+        for dir in dir.get_directories() {
+            writeln!(self, "{:indent$}{}/", "", dir.get_name(), indent = depth * 2)?;
+            self.tree(dir.as_ref(), depth + 1)?;
+        }
+
+        for file in dir.get_files() {
+            writeln!(self, "{:indent$}{} ({} bytes)", "", file.get_name(), file.get_size(), indent = depth * 2)?;
+        }
+
+        Ok(())
+    }
+
+    fn vfs(&mut self) -> fmt::Result {
+        match unsafe { ROOT_DIR.as_ref() } {
+            Some(root) => {
+                writeln!(self, "/")?;
+                self.tree(root.as_ref(), 1)
+            },
+            None => writeln!(self, "filesystem isn't set up"),
+        }
+    }
+
+    fn memory(&mut self, addr: usize, len: usize) -> fmt::Result {
+        for line in (0..len).step_by(DUMP_WIDTH) {
+            let start = addr.wrapping_add(line);
+            let width = DUMP_WIDTH.min(len - line);
+
+            write!(self, "{:08x}: ", start)?;
+
+            let mut bytes = [None; DUMP_WIDTH];
+            for (i, byte) in bytes.iter_mut().enumerate().take(width) {
+                let addr = start.wrapping_add(i);
+
+                if is_mapped(addr) {
+                    *byte = Some(unsafe { core::ptr::read_volatile(addr as *const u8) });
+                }
+            }
+
+            for byte in bytes.iter().take(width) {
+                match byte {
+                    Some(byte) => write!(self, "{:02x} ", byte)?,
+                    None => write!(self, "?? ")?,
+                }
+            }
+
+            write!(self, "{:width$}|", "", width = (DUMP_WIDTH - width) * 3)?;
+
+            for byte in bytes.iter().take(width) {
+                match byte {
+                    Some(c @ 0x20..=0x7e) => self.write_char(*c as char)?,
+                    _ => self.write_char('.')?,
+                }
+            }
+
+            writeln!(self, "|")?;
+        }
+
+        Ok(())
+    }
+
+    /// runs commands until told to leave, returns true if we should keep going rather than halting
+    fn run(&mut self, reason: &str) -> bool {
+        let _ = writeln!(self, "\n=== kernel monitor: {}", reason);
+        let _ = writeln!(self, "type help for a list of commands");
+
+        let mut buf = [0; MAX_LINE];
+
+        loop {
+            let _ = self.write_str("mon> ");
+
+            let line = self.read_line(&mut buf);
+
+            if self.execute(line) {
+                return true;
+            }
+        }
+    }
+
+    /// runs a command line, returns true if it says to leave the monitor
+    pub fn execute(&mut self, line: &str) -> bool {
+        let result =
+            match parse_command(line) {
+                Ok(Command::Nothing) => Ok(()),
+                Ok(Command::Help) => self.help(),
+                Ok(Command::Registers) => self.registers(),
+                Ok(Command::Backtrace) => self.backtrace(),
+                Ok(Command::Pages(addr)) => self.pages(addr),
+                Ok(Command::Tasks) => self.tasks(),
+                Ok(Command::Heap) => self.heap(),
+                Ok(Command::Irqs) => self.irqs(),
+                Ok(Command::Vfs) => self.vfs(),
+                Ok(Command::Memory(addr, len)) => self.memory(addr, len),
+                Ok(Command::Continue) => return true,
+                Ok(Command::Reboot) => reboot(),
+                Ok(Command::PowerOff) => power_off(),
+                Err(err) => writeln!(self, "{}", err),
+            };
+
+        if result.is_err() {
+            let _ = writeln!(self, "couldn't write output");
+        }
+
+        false
+    }
+}
+
+/// drops into the monitor if monitor= was given on the command line.
+/// returns true if whoever's at the monitor wants to carry on instead of halting
+pub fn enter(reason: &str, regs: Option<&SyscallRegisters>) -> bool {
+    let channel =
+        match crate::cmdline::get_str("monitor").and_then(Channel::from_name) {
+            Some(channel) => channel,
+            None => return false,
+        };
+
+    unsafe {
+        if ACTIVE {
+            return false;
+        }
+
+        ACTIVE = true;
+    }
+
+    let mut monitor = Monitor::new(channel, regs);
+
+    // we're polling the keyboard and serial ports, interrupt handlers would steal the input
+    let resume = without_interrupts(|| monitor.run(reason));
+
+    unsafe { ACTIVE = false; }
+
+    resume
+}
@@ -0,0 +1,338 @@
+//! ACPI tables, power off and reset
+//! the firmware leaves a pointer to its root table somewhere in the BIOS area, and the root table points to all the others.
+//! we only read the tables here, there's no AML interpreter
+
+use core::{
+    ops::Range,
+    ptr::write_volatile,
+};
+use super::io::{outb, inw, outw};
+use crate::arch::{LINKED_BASE, paging::map_physical};
+
+/// signature the root system description pointer starts with
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// size of the ACPI 1.0 root system description pointer, which is all we need out of it
+const RSDP_SIZE: usize = 20;
+
+/// where the segment of the extended BIOS data area is kept
+const EBDA_POINTER: usize = 0x40e;
+
+/// where the extended BIOS data area can be, anything else is garbage
+const EBDA_RANGE: Range<usize> = 0x80000..0xa0000;
+
+/// how much of the extended BIOS data area the root pointer can be in
+const EBDA_SEARCH_SIZE: usize = 0x400;
+
+/// BIOS read only memory area, the other place the root pointer can be
+const BIOS_AREA: Range<usize> = 0xe0000..0x100000;
+
+/// size of the header every table starts with
+pub const HEADER_SIZE: usize = 36;
+
+/// biggest table we'll map, anything bigger is almost certainly garbage
+const MAX_TABLE_SIZE: usize = 0x100000;
+
+/// how many tables we keep track of
+const MAX_TABLES: usize = 32;
+
+/// FADT fields, as offsets from the start of the table
+const FADT_DSDT: usize = 40;
+const FADT_SMI_COMMAND: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_PM1A_CONTROL: usize = 64;
+const FADT_PM1B_CONTROL: usize = 68;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REGISTER: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+
+/// FADT flag saying the reset register can be used
+const FADT_RESET_SUPPORTED: u32 = 1 << 10;
+
+/// address spaces of a generic address structure, like the reset register
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+const ADDRESS_SPACE_IO: u8 = 1;
+
+/// PM1 control register bits
+const PM1_SCI_ENABLE: u16 = 1 << 0;
+const PM1_SLEEP_TYPE_SHIFT: u16 = 10;
+const PM1_SLEEP_TYPE_MASK: u16 = 0b111 << PM1_SLEEP_TYPE_SHIFT;
+const PM1_SLEEP_ENABLE: u16 = 1 << 13;
+
+/// AML opcodes needed to find the sleep type for S5
+const AML_NAME: u8 = 0x08;
+const AML_PACKAGE: u8 = 0x12;
+const AML_ZERO: u8 = 0x00;
+const AML_ONE: u8 = 0x01;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+
+/// how many times we check on the hardware before giving up on it
+const TIMEOUT: usize = 1000000;
+
+/// an ACPI table, mapped into kernel memory
+#[derive(Copy, Clone)]
+pub struct Table {
+    data: &'static [u8],
+}
+
+impl Table {
+    /// the four character name of the table, like APIC or FACP
+    pub fn signature(&self) -> &'static [u8] {
+        &self.data[0..4]
+    }
+
+    /// who made the table
+    pub fn oem_id(&self) -> &'static [u8] {
+        &self.data[10..16]
+    }
+
+    /// the table's contents, past the header
+    pub fn body(&self) -> &'static [u8] {
+        &self.data[HEADER_SIZE..]
+    }
+
+    /// the whole table, header and all
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
+/// tables the root table points to
+static mut TABLES: [Option<Table>; MAX_TABLES] = [None; MAX_TABLES];
+
+/// reads a little endian 16 bit number out of a table
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+/// reads a little endian 32 bit number out of a table
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// checks that the bytes of a table add up to zero, like they're supposed to
+fn checksum_valid(data: &[u8]) -> bool {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// gets some memory in the first 4mb, which is always mapped
+unsafe fn low_memory(range: Range<usize>) -> &'static [u8] {
+    core::slice::from_raw_parts((LINKED_BASE + range.start) as *const u8, range.len())
+}
+
+/// looks for the root system description pointer on the 16 byte boundaries in a range of low memory
+unsafe fn search_rsdp(range: Range<usize>) -> Option<&'static [u8]> {
+    let area = low_memory(range);
+
+    (0..area.len()).step_by(16)
+        .map(|offset| &area[offset..])
+        .find(|rest| rest.len() >= RSDP_SIZE && rest.starts_with(RSDP_SIGNATURE) && checksum_valid(&rest[..RSDP_SIZE]))
+        .map(|rest| &rest[..RSDP_SIZE])
+}
+
+/// finds the root system description pointer, which is either in the first 1kb of the extended BIOS data area or in the BIOS area
+unsafe fn find_rsdp() -> Option<&'static [u8]> {
+    let ebda = (read_u16(low_memory(EBDA_POINTER..EBDA_POINTER + 2), 0)? as usize) << 4;
+
+    if EBDA_RANGE.contains(&ebda) {
+        if let Some(rsdp) = search_rsdp(ebda..ebda + EBDA_SEARCH_SIZE) {
+            return Some(rsdp);
+        }
+    }
+
+    search_rsdp(BIOS_AREA)
+}
+
+/// maps the table at the given physical address, if it looks alright
+unsafe fn map_table(phys: usize) -> Option<Table> {
+    let header = core::slice::from_raw_parts(map_physical(phys, HEADER_SIZE) as *const u8, HEADER_SIZE);
+    let len = read_u32(header, 4)? as usize;
+
+    if !(HEADER_SIZE..=MAX_TABLE_SIZE).contains(&len) {
+        debug!("table at {:#x} has a bad length ({:#x})", phys, len);
+        return None;
+    }
+
+    let data = core::slice::from_raw_parts(map_physical(phys, len) as *const u8, len);
+
+    if !checksum_valid(data) {
+        debug!("table at {:#x} has a bad checksum", phys);
+        return None;
+    }
+
+    Some(Table { data })
+}
+
+/// finds a table by its signature
+pub fn find_table(signature: &[u8; 4]) -> Option<Table> {
+    unsafe { TABLES.iter().flatten().find(|table| table.signature() == signature).copied() }
+}
+
+/// finds and maps the firmware's tables
+pub unsafe fn init() {
+    let rsdp =
+        match find_rsdp() {
+            Some(rsdp) => rsdp,
+            None => {
+                log!("no ACPI tables");
+                return;
+            },
+        };
+
+    // XSDT addresses are 64 bits, which doesn't help us, so the RSDT it is
+    let rsdt =
+        match read_u32(rsdp, 16).and_then(|phys| map_table(phys as usize)) {
+            Some(rsdt) if rsdt.signature() == b"RSDT" => rsdt,
+            _ => {
+                log!("ACPI root table is broken");
+                return;
+            },
+        };
+
+    let mut num_tables = 0;
+
+    for entry in rsdt.body().chunks_exact(4) {
+        let phys = u32::from_le_bytes(entry.try_into().unwrap()) as usize;
+
+        if num_tables >= MAX_TABLES {
+            debug!("too many tables, ignoring the rest");
+            break;
+        }
+
+        if let Some(table) = map_table(phys) {
+            debug!("{} @ {:#x}", core::str::from_utf8(table.signature()).unwrap_or("????"), phys);
+
+            TABLES[num_tables] = Some(table);
+            num_tables += 1;
+        }
+    }
+
+    // the DSDT isn't in the root table, the FADT points to it instead
+    let dsdt = find_table(b"FACP").and_then(|fadt| read_u32(fadt.data(), FADT_DSDT)).filter(|&phys| phys != 0);
+
+    if let Some(table) = dsdt.and_then(|phys| map_table(phys as usize)) {
+        if num_tables < MAX_TABLES {
+            TABLES[num_tables] = Some(table);
+            num_tables += 1;
+        }
+    }
+
+    log!("{} ACPI tables from {}", num_tables, core::str::from_utf8(rsdt.oem_id()).unwrap_or("?").trim_end());
+}
+
+/// reads an integer constant out of some AML, returning it and what comes after it
+fn parse_aml_byte(aml: &[u8]) -> Option<(u8, &[u8])> {
+    match *aml.first()? {
+        AML_ZERO => Some((0, &aml[1..])),
+        AML_ONE => Some((1, &aml[1..])),
+        AML_BYTE_PREFIX => Some((*aml.get(1)?, aml.get(2..)?)),
+        _ => None,
+    }
+}
+
+/// finds the sleep type values for S5 (soft off) in the DSDT without running any AML, by looking for the definition of
+/// the \_S5 package and taking the first two numbers in it. this works on every firmware we care about, QEMU included
+pub fn parse_s5(dsdt: &[u8]) -> Option<(u8, u8)> {
+    (0..dsdt.len().saturating_sub(3)).filter(|&i| &dsdt[i..i + 4] == b"_S5_").find_map(|i| {
+        // it has to be a definition, not just a reference, so it should come right after a name op, maybe with a root prefix
+        let before = &dsdt[..i];
+        if !before.ends_with(&[AML_NAME]) && !before.ends_with(&[AML_NAME, b'\\']) {
+            return None;
+        }
+
+        let rest = &dsdt[i + 4..];
+        if *rest.first()? != AML_PACKAGE {
+            return None;
+        }
+
+        // the top 2 bits of the package length's first byte say how many more bytes it has, then there's the element count
+        let length_bytes = (*rest.get(1)? >> 6) as usize + 1;
+        let rest = rest.get(1 + length_bytes + 1..)?;
+
+        let (sleep_type_a, rest) = parse_aml_byte(rest)?;
+        let (sleep_type_b, _) = parse_aml_byte(rest)?;
+
+        Some((sleep_type_a, sleep_type_b))
+    })
+}
+
+/// switches the hardware into ACPI mode if the firmware hasn't already, so the PM1 control registers do something
+unsafe fn enable_acpi(fadt: &[u8], pm1a_control: u16) {
+    if inw(pm1a_control) & PM1_SCI_ENABLE != 0 {
+        return;
+    }
+
+    let smi_command = read_u32(fadt, FADT_SMI_COMMAND).unwrap_or(0);
+    let acpi_enable = fadt.get(FADT_ACPI_ENABLE).copied().unwrap_or(0);
+
+    if smi_command == 0 || acpi_enable == 0 {
+        return;
+    }
+
+    outb(smi_command as u16, acpi_enable);
+
+    for _i in 0..TIMEOUT {
+        if inw(pm1a_control) & PM1_SCI_ENABLE != 0 {
+            break;
+        }
+    }
+}
+
+/// puts the machine into S5 (soft off). only returns if that didn't work
+pub fn power_off() -> Result<(), &'static str> {
+    let fadt = find_table(b"FACP").ok_or("no FADT")?.data();
+    let dsdt = find_table(b"DSDT").ok_or("no DSDT")?;
+    let (sleep_type_a, sleep_type_b) = parse_s5(dsdt.body()).ok_or("no \\_S5 in DSDT")?;
+
+    let pm1a_control = read_u32(fadt, FADT_PM1A_CONTROL).filter(|&port| port != 0).ok_or("no PM1a control register")? as u16;
+    let pm1b_control = read_u32(fadt, FADT_PM1B_CONTROL).unwrap_or(0) as u16;
+
+    unsafe {
+        enable_acpi(fadt, pm1a_control);
+
+        let sleep = |port: u16, sleep_type: u8| {
+            let value = inw(port) & !PM1_SLEEP_TYPE_MASK;
+            outw(port, value | ((sleep_type as u16) << PM1_SLEEP_TYPE_SHIFT) | PM1_SLEEP_ENABLE);
+        };
+
+        sleep(pm1a_control, sleep_type_a);
+
+        if pm1b_control != 0 {
+            sleep(pm1b_control, sleep_type_b);
+        }
+
+        for _i in 0..TIMEOUT {
+            core::hint::spin_loop();
+        }
+    }
+
+    Err("machine is still on")
+}
+
+/// resets the machine with the FADT's reset register, if it has one. only returns if that didn't work
+pub fn reset() -> Result<(), &'static str> {
+    let fadt = find_table(b"FACP").ok_or("no FADT")?.data();
+
+    let flags = read_u32(fadt, FADT_FLAGS).unwrap_or(0);
+    let value = fadt.get(FADT_RESET_VALUE).copied();
+
+    let (space, addr, value) =
+        match (fadt.get(FADT_RESET_REGISTER), read_u32(fadt, FADT_RESET_REGISTER + 4), value) {
+            (Some(&space), Some(addr), Some(value)) if flags & FADT_RESET_SUPPORTED != 0 => (space, addr, value),
+            _ => return Err("no reset register"),
+        };
+
+    unsafe {
+        match space {
+            ADDRESS_SPACE_IO => outb(addr as u16, value),
+            ADDRESS_SPACE_MEMORY => write_volatile(map_physical(addr as usize, 1) as *mut u8, value),
+            _ => return Err("reset register is somewhere we can't get to"),
+        }
+
+        for _i in 0..TIMEOUT {
+            core::hint::spin_loop();
+        }
+    }
+
+    Err("machine is still running")
+}
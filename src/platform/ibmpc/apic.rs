@@ -0,0 +1,446 @@
+//! local APIC and I/O APIC
+//! newer PCs route interrupts through an I/O APIC instead of the 8259s, which sends them on to the local APIC in the cpu.
+//! the MADT says where the I/O APICs are and which of their pins the ISA IRQs ended up on.
+//! the local APIC also has a timer, which we use as the tick source instead of the PIT when we can
+
+use core::ptr::{read_volatile, write_volatile};
+use super::{
+    acpi::{self, read_u16, read_u32},
+    io::{inb, outb},
+    irq::PIT_FREQUENCY,
+    pic::{self, NUM_IRQS, MASTER_OFFSET},
+};
+use crate::arch::{
+    PAGE_SIZE,
+    cpu::{has_apic, read_msr, write_msr},
+    ints::{IDT, IDTEntry, IDTFlags, ExceptionStackFrame},
+    paging::map_physical,
+};
+
+/// MSR holding the local APIC's physical address and enable flag
+const IA32_APIC_BASE: u32 = 0x1b;
+
+/// globally enables the local APIC
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// local APIC registers
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SPURIOUS: usize = 0xf0;
+const LAPIC_IN_SERVICE: usize = 0x100;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_LVT_LINT1: usize = 0x360;
+const LAPIC_LVT_ERROR: usize = 0x370;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+/// software enable flag in the spurious interrupt register
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// local vector table flags
+const LVT_NMI: u32 = 0b100 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+/// timer divide configuration value for dividing the bus clock by 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+/// vector the local APIC sends when an interrupt goes away before it's delivered. it has to end in 0xf on older cpus
+const SPURIOUS_VECTOR: usize = 0xff;
+
+/// I/O APIC registers, everything goes through a select register and a window register
+const IOAPIC_SELECT: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+/// how much of an I/O APIC's memory we need mapped
+const IOAPIC_SIZE: usize = 0x20;
+
+/// redirection entry flags
+const REDIRECT_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECT_LEVEL: u32 = 1 << 15;
+const REDIRECT_MASKED: u32 = 1 << 16;
+
+/// how many I/O APICs we keep track of
+pub const MAX_IO_APICS: usize = 4;
+
+/// MADT flag saying there's a pair of 8259s too, which need to be masked
+const MADT_PCAT_COMPAT: u32 = 1 << 0;
+
+/// MADT entry types
+const MADT_IO_APIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+
+/// PIT channel 2 ports, which is wired to the speaker and can be polled, unlike channel 0
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_PORT_B: u16 = 0x61;
+
+/// PIT command: channel 2, low then high byte, interrupt on terminal count
+const PIT_CHANNEL_2_ONESHOT: u8 = 0b10_11_000_0;
+
+/// port B flags
+const PORT_B_GATE_2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT_2: u8 = 1 << 5;
+
+/// how long the local APIC timer is measured against the PIT for
+const CALIBRATION_MS: u32 = 10;
+
+/// an I/O APIC the firmware told us about
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+
+    /// physical address of its registers
+    pub addr: u32,
+
+    /// first global system interrupt it handles
+    pub gsi_base: u32,
+}
+
+/// where an ISA IRQ ends up on the I/O APICs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IsaRoute {
+    /// global system interrupt the IRQ comes in on
+    pub gsi: u32,
+
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+impl IsaRoute {
+    /// ISA IRQs are edge triggered and active high, and unless the firmware says otherwise they go to the same numbered pin
+    const fn identity(irq: u8) -> Self {
+        Self { gsi: irq as u32, active_low: false, level_triggered: false }
+    }
+}
+
+/// what we need out of the MADT
+#[derive(Debug, PartialEq, Eq)]
+pub struct Madt {
+    /// whether there's 8259s as well
+    pub has_pics: bool,
+
+    pub io_apics: [Option<IoApicInfo>; MAX_IO_APICS],
+
+    /// where each ISA IRQ goes, or nothing if another IRQ was moved onto its pin
+    pub isa_routes: [Option<IsaRoute>; NUM_IRQS],
+}
+
+/// parses the body of the MADT
+pub fn parse_madt(body: &[u8]) -> Option<Madt> {
+    let flags = read_u32(body, 4)?;
+
+    let mut madt = Madt {
+        has_pics: flags & MADT_PCAT_COMPAT != 0,
+        io_apics: [None; MAX_IO_APICS],
+        isa_routes: [None; NUM_IRQS],
+    };
+
+    for (irq, route) in madt.isa_routes.iter_mut().enumerate() {
+        *route = Some(IsaRoute::identity(irq as u8));
+    }
+
+    let mut num_io_apics = 0;
+    let mut offset = 8;
+
+    while offset + 2 <= body.len() {
+        let kind = body[offset];
+        let len = body[offset + 1] as usize;
+
+        if len < 2 || offset + len > body.len() {
+            return None;
+        }
+
+        let entry = &body[offset..offset + len];
+
+        match kind {
+            MADT_IO_APIC if num_io_apics < MAX_IO_APICS => {
+                madt.io_apics[num_io_apics] = Some(IoApicInfo {
+                    id: *entry.get(2)?,
+                    addr: read_u32(entry, 4)?,
+                    gsi_base: read_u32(entry, 8)?,
+                });
+                num_io_apics += 1;
+            },
+            MADT_SOURCE_OVERRIDE => {
+                let (bus, source, gsi, flags) = (*entry.get(2)?, *entry.get(3)?, read_u32(entry, 4)?, read_u16(entry, 8)?);
+
+                // bus 0 is ISA, and that's the only bus there's ever overrides for
+                if bus == 0 && (source as usize) < NUM_IRQS {
+                    // IRQs that were on the pin before don't get to keep it
+                    for route in madt.isa_routes.iter_mut() {
+                        if route.map_or(false, |route| route.gsi == gsi) {
+                            *route = None;
+                        }
+                    }
+
+                    // polarity and trigger mode are 2 bits each, 0 means the bus default and 3 means the opposite of it
+                    madt.isa_routes[source as usize] = Some(IsaRoute {
+                        gsi,
+                        active_low: flags & 0b11 == 0b11,
+                        level_triggered: (flags >> 2) & 0b11 == 0b11,
+                    });
+                }
+            },
+            _ => (),
+        }
+
+        offset += len;
+    }
+
+    Some(madt)
+}
+
+/// an I/O APIC we're using
+#[derive(Copy, Clone)]
+struct IoApic {
+    /// where its registers are mapped
+    addr: usize,
+
+    /// first global system interrupt it handles
+    gsi_base: u32,
+
+    /// how many pins it has
+    num_pins: u32,
+}
+
+impl IoApic {
+    unsafe fn read(&self, reg: u32) -> u32 {
+        write_volatile((self.addr + IOAPIC_SELECT) as *mut u32, reg);
+        read_volatile((self.addr + IOAPIC_WINDOW) as *const u32)
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        write_volatile((self.addr + IOAPIC_SELECT) as *mut u32, reg);
+        write_volatile((self.addr + IOAPIC_WINDOW) as *mut u32, value);
+    }
+
+    /// whether a global system interrupt comes in on this I/O APIC
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < self.num_pins
+    }
+
+    /// reads the low half of a pin's redirection entry, which has everything but the destination
+    unsafe fn read_redirection(&self, gsi: u32) -> u32 {
+        self.read(IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2)
+    }
+
+    /// sets where a pin's interrupts go and how, destination first so it's never unmasked with a stale one
+    unsafe fn write_redirection(&self, gsi: u32, low: u32, destination: u8) {
+        let reg = IOAPIC_REDIRECTION + (gsi - self.gsi_base) * 2;
+
+        self.write(reg, REDIRECT_MASKED);
+        self.write(reg + 1, (destination as u32) << 24);
+        self.write(reg, low);
+    }
+}
+
+/// where the local APIC's registers are mapped, or 0 if we aren't using it
+static mut LAPIC: usize = 0;
+
+/// I/O APICs we're using
+static mut IO_APICS: [Option<IoApic>; MAX_IO_APICS] = [None; MAX_IO_APICS];
+
+/// where each ISA IRQ goes
+static mut ISA_ROUTES: [Option<IsaRoute>; NUM_IRQS] = [None; NUM_IRQS];
+
+/// how many spurious interrupts the local APIC has sent
+pub static mut SPURIOUS_COUNT: u64 = 0;
+
+unsafe fn lapic_read(reg: usize) -> u32 {
+    read_volatile((LAPIC + reg) as *const u32)
+}
+
+unsafe fn lapic_write(reg: usize, value: u32) {
+    write_volatile((LAPIC + reg) as *mut u32, value);
+}
+
+/// finds the I/O APIC and global system interrupt an ISA IRQ comes in on
+unsafe fn find_pin(irq: u8) -> Option<(IoApic, u32)> {
+    let gsi = ISA_ROUTES.get(irq as usize).copied().flatten()?.gsi;
+    let io_apic = IO_APICS.iter().flatten().find(|io_apic| io_apic.handles(gsi))?;
+
+    Some((*io_apic, gsi))
+}
+
+/// sets or clears the mask flag on an ISA IRQ's pin
+unsafe fn set_masked(irq: u8, masked: bool) {
+    if let Some((io_apic, gsi)) = find_pin(irq) {
+        let reg = IOAPIC_REDIRECTION + (gsi - io_apic.gsi_base) * 2;
+        let low = io_apic.read(reg);
+
+        io_apic.write(reg, if masked { low | REDIRECT_MASKED } else { low & !REDIRECT_MASKED });
+    }
+}
+
+/// stops an ISA IRQ from interrupting
+pub fn mask(irq: u8) {
+    unsafe { set_masked(irq, true); }
+}
+
+/// lets an ISA IRQ interrupt
+pub fn unmask(irq: u8) {
+    unsafe { set_masked(irq, false); }
+}
+
+/// checks whether an ISA IRQ is masked. IRQs that aren't wired to anything count as masked
+pub fn is_masked(irq: u8) -> bool {
+    unsafe {
+        match find_pin(irq) {
+            Some((io_apic, gsi)) => io_apic.read_redirection(gsi) & REDIRECT_MASKED != 0,
+            None => true,
+        }
+    }
+}
+
+/// tells the local APIC we're done with the interrupt it sent last
+pub fn end_of_interrupt() {
+    unsafe { lapic_write(LAPIC_EOI, 0); }
+}
+
+/// checks whether the local APIC delivered the given vector and is waiting for it to be acknowledged.
+/// anything else on that vector came from somewhere else, like the 8259s, and mustn't be acknowledged here
+pub fn is_in_service(vector: u8) -> bool {
+    // 8 registers of 32 bits each, 16 bytes apart
+    let reg = LAPIC_IN_SERVICE + (vector as usize / 32) * 0x10;
+    unsafe { lapic_read(reg) & (1 << (vector % 32)) != 0 }
+}
+
+/// counts spurious interrupts, which mustn't be acknowledged
+unsafe extern "x86-interrupt" fn spurious_handler(_frame: ExceptionStackFrame) {
+    SPURIOUS_COUNT += 1;
+}
+
+/// finds the APICs and routes ISA IRQs through them, all masked. the 8259s are masked too, since nothing should come from them any more.
+/// if anything needed is missing nothing's changed, and the reason is returned so we can say why we're sticking with the PICs
+pub unsafe fn init() -> Result<(), &'static str> {
+    if !has_apic() {
+        return Err("cpu has no APIC");
+    }
+
+    let madt = acpi::find_table(b"APIC").ok_or("no MADT in ACPI tables")?;
+    let madt = parse_madt(madt.body()).ok_or("MADT is broken")?;
+
+    if madt.io_apics.iter().all(|io_apic| io_apic.is_none()) {
+        return Err("no I/O APIC");
+    }
+
+    if madt.has_pics {
+        pic::disable();
+    }
+
+    // the firmware could have turned the local APIC off, and the MSR is the authority on where it is
+    let base = read_msr(IA32_APIC_BASE);
+    write_msr(IA32_APIC_BASE, base | APIC_BASE_ENABLE);
+    LAPIC = map_physical((base & 0xffff_f000) as usize, PAGE_SIZE);
+
+    for (info, io_apic) in madt.io_apics.iter().flatten().zip(IO_APICS.iter_mut()) {
+        let addr = map_physical(info.addr as usize, IOAPIC_SIZE);
+        let mut new = IoApic { addr, gsi_base: info.gsi_base, num_pins: 0 };
+        new.num_pins = ((new.read(IOAPIC_VERSION) >> 16) & 0xff) + 1;
+
+        // nothing comes in until it's routed
+        for gsi in new.gsi_base..new.gsi_base + new.num_pins {
+            new.write_redirection(gsi, REDIRECT_MASKED, 0);
+        }
+
+        debug!("I/O APIC {} @ {:#x}: {} pins from GSI {}", info.id, info.addr, new.num_pins, new.gsi_base);
+
+        *io_apic = Some(new);
+    }
+
+    ISA_ROUTES = madt.isa_routes;
+
+    let id = (lapic_read(LAPIC_ID) >> 24) as u8;
+
+    // ISA IRQs get the same vectors they'd have had through the PICs, so the handlers stay put
+    for irq in 0..NUM_IRQS as u8 {
+        if let (Some((io_apic, gsi)), Some(route)) = (find_pin(irq), ISA_ROUTES[irq as usize]) {
+            let mut low = (MASTER_OFFSET + irq) as u32 | REDIRECT_MASKED;
+
+            if route.active_low {
+                low |= REDIRECT_ACTIVE_LOW;
+            }
+            if route.level_triggered {
+                low |= REDIRECT_LEVEL;
+            }
+
+            io_apic.write_redirection(gsi, low, id);
+
+            if gsi != irq as u32 {
+                debug!("IRQ {} is on GSI {}", irq, gsi);
+            }
+        }
+    }
+
+    IDT[SPURIOUS_VECTOR] = IDTEntry::new(spurious_handler as *const (), IDTFlags::External);
+
+    // accept everything, the 8259s' old line into LINT0 isn't needed and LINT1 carries NMIs
+    lapic_write(LAPIC_TASK_PRIORITY, 0);
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT1, LVT_NMI);
+    lapic_write(LAPIC_LVT_ERROR, LVT_MASKED);
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+    lapic_write(LAPIC_SPURIOUS, LAPIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+
+    log!("using APIC: local APIC {} @ {:#x}, {} I/O APIC(s)", id, base & 0xffff_f000, IO_APICS.iter().flatten().count());
+
+    Ok(())
+}
+
+/// measures how fast the local APIC timer counts down with the divider at 16, by timing it against PIT channel 2
+unsafe fn calibrate_timer() -> u32 {
+    let port_b = inb(PIT_PORT_B) & !PORT_B_SPEAKER;
+    let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+    // hold the count with the gate low while it's loaded
+    outb(PIT_PORT_B, port_b & !PORT_B_GATE_2);
+    outb(PIT_COMMAND, PIT_CHANNEL_2_ONESHOT);
+    outb(PIT_CHANNEL_2, (count & 0xff) as u8);
+    outb(PIT_CHANNEL_2, ((count >> 8) & 0xff) as u8);
+
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+
+    // start both, then wait for the PIT's output to go high. if the local APIC timer runs out first something's wrong
+    outb(PIT_PORT_B, port_b | PORT_B_GATE_2);
+    lapic_write(LAPIC_TIMER_INITIAL, u32::MAX);
+
+    while inb(PIT_PORT_B) & PORT_B_OUT_2 == 0 && lapic_read(LAPIC_TIMER_CURRENT) != 0 {
+        core::hint::spin_loop();
+    }
+
+    let elapsed = u32::MAX - lapic_read(LAPIC_TIMER_CURRENT);
+
+    lapic_write(LAPIC_TIMER_INITIAL, 0);
+    outb(PIT_PORT_B, port_b);
+
+    elapsed.saturating_mul(1000 / CALIBRATION_MS)
+}
+
+/// starts the local APIC timer at the given rate in Hz, sending the timer's usual vector
+pub unsafe fn init_timer(rate: u32) -> Result<(), &'static str> {
+    if LAPIC == 0 {
+        return Err("local APIC isn't in use");
+    }
+
+    let frequency = calibrate_timer();
+
+    if frequency == u32::MAX || frequency / rate == 0 {
+        return Err("couldn't calibrate local APIC timer");
+    }
+
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    lapic_write(LAPIC_LVT_TIMER, LVT_TIMER_PERIODIC | (MASTER_OFFSET + super::irq::TIMER_IRQ) as u32);
+    lapic_write(LAPIC_TIMER_INITIAL, frequency / rate);
+
+    log!("local APIC timer counts at {} Hz, running at {} Hz", frequency, rate);
+
+    Ok(())
+}
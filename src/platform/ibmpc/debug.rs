@@ -27,6 +27,9 @@ pub unsafe fn puts(s: &str) {
 ///
 /// This method is unsafe because it does port accesses without synchronisation
 pub unsafe fn putb(b: u8) {
+    // Send anything the serial driver has queued up first so output stays in order
+    super::serial::flush(0);
+
     // Wait for the serial port's fifo to not be empty
     while (inb(0x3F8 + 5) & 0x20) == 0 {
         // Do nothing
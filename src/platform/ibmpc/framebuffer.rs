@@ -0,0 +1,285 @@
+//! text console drawn on a linear framebuffer set up by the bootloader, for when there's no VGA text mode
+
+use alloc::{
+    boxed::Box,
+    vec,
+    vec::Vec,
+};
+use crate::{
+    arch::{
+        FRAMEBUFFER_START, PAGE_SIZE,
+        multiboot::{self, FramebufferInfo, FRAMEBUFFER_TYPE_RGB},
+        paging::map_physical_region,
+    },
+    console::{ColorCode, RawTextConsole},
+    util::psf::Font,
+};
+
+/// font text is drawn with, X11's public domain misc-fixed 8x13 converted to PSF2 with the code page 437 layout VGA text mode uses
+pub static FONT_DATA: &[u8] = include_bytes!("../../fonts/fixed8x13.psf");
+
+/// RGB values of the text colors, the same as the standard VGA palette
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0xaa),
+    (0x00, 0xaa, 0x00),
+    (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00),
+    (0xaa, 0x00, 0xaa),
+    (0xaa, 0x55, 0x00),
+    (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55),
+    (0x55, 0x55, 0xff),
+    (0x55, 0xff, 0x55),
+    (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55),
+    (0xff, 0x55, 0xff),
+    (0xff, 0xff, 0x55),
+    (0xff, 0xff, 0xff),
+];
+
+/// how many scanlines at the bottom of a character the cursor takes up
+const CURSOR_HEIGHT: usize = 2;
+
+/// a framebuffer we can draw text on
+pub struct Framebuffer {
+    /// the framebuffer's memory
+    buffer: &'static mut [u8],
+
+    /// what the bootloader told us about it
+    info: FramebufferInfo,
+
+    /// bytes in each pixel
+    bytes_per_pixel: usize,
+
+    /// font characters are drawn with
+    font: Font<'static>,
+
+    /// pixel values of the text colors
+    colors: [u32; 16],
+}
+
+impl Framebuffer {
+    /// turns an RGB color into a pixel value
+    fn pack_color(info: &FramebufferInfo, (r, g, b): (u8, u8, u8)) -> u32 {
+        let field = |value: u8, (position, size): (u8, u8)| ((value as u32) >> (8 - size.min(8))) << position;
+
+        field(r, info.red) | field(g, info.green) | field(b, info.blue)
+    }
+
+    /// width of the screen in characters
+    pub fn columns(&self) -> u16 {
+        (self.info.width as usize / self.font.width) as u16
+    }
+
+    /// height of the screen in characters
+    pub fn rows(&self) -> u16 {
+        (self.info.height as usize / self.font.height) as u16
+    }
+
+    /// sets a pixel to the given pixel value
+    fn put_pixel(&mut self, x: usize, y: usize, value: u32) {
+        let offset = y * self.info.pitch as usize + x * self.bytes_per_pixel;
+        self.buffer[offset..offset + self.bytes_per_pixel].copy_from_slice(&value.to_le_bytes()[..self.bytes_per_pixel]);
+    }
+
+    /// draws a character at the given position (in characters), with an underline for the cursor if requested
+    fn draw_char(&mut self, x: u16, y: u16, color: ColorCode, c: u8, cursor: bool) {
+        let font = self.font;
+        let glyph = font.glyph(c as usize);
+        let foreground = self.colors[color.foreground as usize & 0xf];
+        let background = self.colors[color.background as usize & 0xf];
+
+        let left = x as usize * font.width;
+        let top = y as usize * font.height;
+
+        for row in 0..font.height {
+            let in_cursor = cursor && row >= font.height.saturating_sub(CURSOR_HEIGHT);
+
+            for col in 0..font.width {
+                let set = in_cursor || font.pixel(glyph, col, row);
+                self.put_pixel(left + col, top + row, if set { foreground } else { background });
+            }
+        }
+    }
+
+    /// fills a rectangle (in characters, x1 and y1 exclusive) with the background of the given color
+    fn fill(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, color: ColorCode) {
+        let background = self.colors[color.background as usize & 0xf];
+        let (width, height) = (self.font.width, self.font.height);
+
+        for y in y0 as usize * height..y1 as usize * height {
+            for x in x0 as usize * width..x1 as usize * width {
+                self.put_pixel(x, y, background);
+            }
+        }
+    }
+
+    /// copies rows of characters from y0 to y1
+    fn copy_rows(&mut self, y0: u16, y1: u16, height: u16) {
+        let row_size = self.info.pitch as usize * self.font.height;
+        let start = y0 as usize * row_size;
+
+        self.buffer.copy_within(start..start + height as usize * row_size, y1 as usize * row_size);
+    }
+}
+
+/// the framebuffer, if we're using one
+static mut FRAMEBUFFER: Option<Framebuffer> = None;
+
+/// gets the framebuffer, if we're using one
+pub fn get_framebuffer() -> Option<&'static mut Framebuffer> {
+    unsafe { FRAMEBUFFER.as_mut() }
+}
+
+/// raw console that draws on the framebuffer
+/// the framebuffer can't be read back as characters, so we keep a copy of them to redraw the cursor with
+pub struct FramebufferConsole {
+    /// characters on the screen
+    cells: Vec<(u8, ColorCode)>,
+
+    /// width of the screen in characters
+    width: u16,
+
+    /// where the cursor is
+    cursor: (u16, u16),
+
+    /// whether the cursor is shown
+    cursor_visible: bool,
+}
+
+impl FramebufferConsole {
+    /// redraws a character from our copy of the screen
+    fn draw_cell(&mut self, x: u16, y: u16) {
+        let index = y as usize * self.width as usize + x as usize;
+
+        if let (Some(&(c, color)), Some(framebuffer)) = (self.cells.get(index), get_framebuffer()) {
+            let cursor = self.cursor_visible && self.cursor == (x, y);
+            framebuffer.draw_char(x, y, color, c, cursor);
+        }
+    }
+
+    /// hides the cursor while running the given function, so it doesn't get copied or drawn over
+    fn without_cursor(&mut self, op: impl FnOnce(&mut Self)) {
+        let visible = self.cursor_visible;
+
+        self.set_cursor_visible(false);
+        op(self);
+        self.set_cursor_visible(visible);
+    }
+}
+
+impl RawTextConsole for FramebufferConsole {
+    fn write_char(&mut self, x: u16, y: u16, color: ColorCode, c: u8) {
+        if let Some(cell) = self.cells.get_mut(y as usize * self.width as usize + x as usize) {
+            *cell = (c, color);
+        }
+        self.draw_cell(x, y);
+    }
+
+    fn clear(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, color: ColorCode) {
+        self.without_cursor(|console| {
+            for y in y0..y1 {
+                let start = y as usize * console.width as usize;
+                console.cells[start + x0 as usize..start + x1 as usize].fill((b' ', color));
+            }
+
+            if let Some(framebuffer) = get_framebuffer() {
+                framebuffer.fill(x0, y0, x1, y1, color);
+            }
+        });
+    }
+
+    fn copy(&mut self, y0: u16, y1: u16, height: u16) {
+        self.without_cursor(|console| {
+            let width = console.width as usize;
+            console.cells.copy_within(y0 as usize * width..(y0 + height) as usize * width, y1 as usize * width);
+
+            if let Some(framebuffer) = get_framebuffer() {
+                framebuffer.copy_rows(y0, y1, height);
+            }
+        });
+    }
+
+    fn set_cursor_pos(&mut self, x: u16, y: u16) {
+        let (old_x, old_y) = self.cursor;
+
+        self.cursor = (x, y);
+        self.draw_cell(old_x, old_y);
+        self.draw_cell(x, y);
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        if visible != self.cursor_visible {
+            self.cursor_visible = visible;
+            self.draw_cell(self.cursor.0, self.cursor.1);
+        }
+    }
+}
+
+/// creates a raw console on the framebuffer, if we're using one
+pub fn create_console() -> Option<Box<dyn RawTextConsole + Sync>> {
+    let framebuffer = get_framebuffer()?;
+    let (width, height) = (framebuffer.columns(), framebuffer.rows());
+
+    Some(Box::new(FramebufferConsole {
+        cells: vec![(b' ', ColorCode::default()); width as usize * height as usize],
+        width,
+        cursor: (0, 0),
+        cursor_visible: false,
+    }))
+}
+
+/// sets up the framebuffer if the bootloader gave us one we can use, returns whether it did
+pub fn init() -> bool {
+    let info =
+        match multiboot::get_framebuffer() {
+            Some(info) if info.kind == FRAMEBUFFER_TYPE_RGB => info,
+            _ => return false,
+        };
+
+    if !matches!(info.bpp, 16 | 24 | 32) {
+        log!("framebuffer: unsupported depth {}", info.bpp);
+        return false;
+    }
+
+    if info.addr > u32::MAX as u64 {
+        log!("framebuffer: {:#x} is above 4gb", info.addr);
+        return false;
+    }
+
+    let font =
+        match Font::parse(FONT_DATA) {
+            Ok(font) => font,
+            Err(err) => {
+                log!("framebuffer: couldn't load font: {}", err);
+                return false;
+            },
+        };
+
+    // the framebuffer probably isn't page aligned, so map from the page it starts in
+    let phys = info.addr as usize;
+    let offset = phys % PAGE_SIZE;
+    let size = info.pitch as usize * info.height as usize;
+
+    map_physical_region(FRAMEBUFFER_START, phys - offset, (offset + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1));
+
+    let mut colors = [0; 16];
+    for (color, rgb) in colors.iter_mut().zip(PALETTE.iter()) {
+        *color = Framebuffer::pack_color(&info, *rgb);
+    }
+
+    unsafe {
+        FRAMEBUFFER = Some(Framebuffer {
+            buffer: core::slice::from_raw_parts_mut((FRAMEBUFFER_START + offset) as *mut u8, size),
+            info,
+            bytes_per_pixel: info.bpp as usize / 8,
+            font,
+            colors,
+        });
+    }
+
+    log!("framebuffer: {}x{}x{} at {:#x}, {}x{} characters", info.width, info.height, info.bpp, info.addr, info.width as usize / font.width, info.height as usize / font.height);
+
+    true
+}
@@ -1,84 +1,282 @@
 //! IRQs
+//! drivers claim interrupt lines with register_irq, and the handlers on a line are called one after another since
+//! lines can be shared. the timer is special, it gets its own low level handler so it can switch tasks.
+//! interrupts come through the APIC if there is one, or the 8259 PICs otherwise
 
-use super::io::outb;
+use super::{
+    apic,
+    io::outb,
+    pic::{self, NUM_IRQS, MASTER_OFFSET},
+};
 use crate::{
     arch::{
-        LINKED_BASE,
-        ints::{IDT, IDTEntry, IDTFlags, ExceptionStackFrame, SyscallRegisters},
-        paging::PAGE_DIR,
+        ints::{IDT, IDTEntry, IDTFlags, SyscallRegisters},
+        tasks::return_to_task,
+        without_interrupts,
     },
-    tasks::{IN_TASK, CURRENT_TERMINATED, get_current_task_mut, switch_tasks},
+    tasks::{IN_TASK, NEED_RESCHED},
 };
 
-/// interrupt stub handler for unhandled interrupts
-unsafe extern "x86-interrupt" fn stub_handler(_frame: ExceptionStackFrame) {
-    log!("unknown interrupt");
+/// line the PIT is wired up to
+pub const TIMER_IRQ: u8 = 0;
+
+/// how fast the PIT counts, in Hz
+pub const PIT_FREQUENCY: u32 = 1193180;
+
+/// how many drivers can share one line
+const MAX_SHARED_HANDLERS: usize = 4;
 
-    // reset master interrupt controller
-    outb(0x20, 0x20);
+/// a driver's interrupt handler, called with the line that interrupted and interrupts off.
+/// the interrupt is acknowledged after every handler on the line has run, so handlers shouldn't do that themselves.
+/// anything that doesn't have to happen right away should be handed to crate::deferred::defer
+pub type IrqHandler = fn(u8);
+
+/// a handler registered on a line
+#[derive(Copy, Clone)]
+struct Registration {
+    /// name of the driver, for debugging
+    name: &'static str,
+
+    handler: IrqHandler,
 }
 
-unsafe extern "x86-interrupt" fn stub_handler_2(_frame: ExceptionStackFrame) {
-    log!("unknown interrupt");
+/// handlers registered for each line
+static mut HANDLERS: [[Option<Registration>; MAX_SHARED_HANDLERS]; NUM_IRQS] = [[None; MAX_SHARED_HANDLERS]; NUM_IRQS];
+
+/// how many interrupts have come in on each line
+pub static mut IRQ_COUNTS: [u64; NUM_IRQS] = [0; NUM_IRQS];
 
-    // reset slave interrupt controller
-    outb(0xa0, 0x20);
-    outb(0x20, 0x20);
+/// how many spurious interrupts each line has gotten, these only ever happen on IRQ 7 and 15
+pub static mut SPURIOUS_COUNTS: [u64; NUM_IRQS] = [0; NUM_IRQS];
+
+/// interrupt controllers IRQs can come through
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Controller {
+    /// the pair of 8259s every PC has
+    Pic,
+
+    /// the I/O APIC, with interrupts acknowledged through the local APIC
+    Apic,
 }
 
-/// timer interrupt handler, currently just switches tasks
-#[no_mangle]
-pub unsafe extern "C" fn timer_handler(mut regs: SyscallRegisters) {
-    // TODO: task priority, task execution timers
+/// which interrupt controller is in use
+static mut CONTROLLER: Controller = Controller::Pic;
 
-    // we don't want to preempt the kernel- all sorts of bad things could happen
-    if !IN_TASK {
-        outb(0x20, 0x20);
-        return;
+/// gets which interrupt controller is in use
+pub fn controller() -> Controller {
+    unsafe { CONTROLLER }
+}
+
+fn mask_line(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::mask(irq),
+        Controller::Apic => apic::mask(irq),
+    }
+}
+
+fn unmask_line(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::unmask(irq),
+        Controller::Apic => apic::unmask(irq),
+    }
+}
+
+fn end_of_interrupt(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::end_of_interrupt(irq),
+        Controller::Apic => apic::end_of_interrupt(),
+    }
+}
+
+/// checks whether an IRQ line is masked
+pub fn is_irq_masked(irq: u8) -> bool {
+    match controller() {
+        Controller::Pic => pic::is_masked(irq),
+        Controller::Apic => apic::is_masked(irq),
     }
+}
+
+/// registers a handler for an IRQ line and unmasks it
+pub fn register_irq(irq: u8, name: &'static str, handler: IrqHandler) -> Result<(), &'static str> {
+    if irq as usize >= NUM_IRQS {
+        return Err("no such IRQ line");
+    }
+
+    if irq == TIMER_IRQ || irq == pic::CASCADE_IRQ {
+        return Err("IRQ line is reserved");
+    }
+
+    without_interrupts(|| unsafe {
+        let slot = HANDLERS[irq as usize].iter_mut().find(|slot| slot.is_none()).ok_or("too many handlers on IRQ line")?;
+        *slot = Some(Registration { name, handler });
+
+        unmask_line(irq);
 
-    // has the current task been terminated?
-    if CURRENT_TERMINATED {
-        // it no longer exists, so all we need to do is clear the flag
-        CURRENT_TERMINATED = false;
-    } else {
-        // save state of current task
-        get_current_task_mut().expect("no tasks?").state.save(&regs);
+        Ok(())
+    })
+}
+
+/// removes a handler from an IRQ line, masking the line if nothing else is using it
+pub fn unregister_irq(irq: u8, handler: IrqHandler) -> Result<(), &'static str> {
+    if irq as usize >= NUM_IRQS {
+        return Err("no such IRQ line");
     }
 
-    // switch to next task
-    switch_tasks();
+    without_interrupts(|| unsafe {
+        let handlers = &mut HANDLERS[irq as usize];
 
-    // load state of new current task
-    let current = get_current_task_mut().expect("no tasks?");
+        let slot = handlers.iter_mut().find(|slot| slot.map_or(false, |registered| registered.handler as usize == handler as usize)).ok_or("handler isn't registered")?;
+        *slot = None;
 
-    current.state.load(&mut regs);
+        if handlers.iter().all(|slot| slot.is_none()) {
+            mask_line(irq);
+        }
 
-    // get reference to global page directory
-    let dir = PAGE_DIR.as_mut().expect("paging not initialized");
+        Ok(())
+    })
+}
 
-    // has the kernel page directory been updated?
-    if current.state.page_updates != dir.page_updates {
-        // get page directory index of the start of the kernel's address space
-        let idx = LINKED_BASE >> 22;
+/// stops an IRQ line from interrupting, without getting rid of its handlers
+pub fn mask_irq(irq: u8) {
+    if (irq as usize) < NUM_IRQS {
+        without_interrupts(|| mask_line(irq));
+    }
+}
 
-        // copy from the kernel's page directory to the task's
-        current.state.copy_pages_from(dir, idx, 1024);
+/// lets an IRQ line interrupt again
+pub fn unmask_irq(irq: u8) {
+    if (irq as usize) < NUM_IRQS {
+        without_interrupts(|| unmask_line(irq));
+    }
+}
+
+/// calls the closure with the name of every driver registered on the given line
+pub fn irq_handler_names(irq: u8, mut func: impl FnMut(&'static str)) {
+    match irq {
+        TIMER_IRQ => func("timer"),
+        pic::CASCADE_IRQ if controller() == Controller::Pic => func("cascade"),
+        _ => (),
+    }
 
-        // the task's page directory is now up to date (at least for our purposes)
-        current.state.page_updates = dir.page_updates;
+    if let Some(handlers) = unsafe { HANDLERS.get(irq as usize) } {
+        for registration in handlers.iter().flatten() {
+            func(registration.name);
+        }
     }
+}
+
+/// runs every handler on a line, acknowledges the interrupt, then runs whatever work the handlers deferred
+unsafe fn dispatch(irq: u8, regs: &mut SyscallRegisters) {
+    let spurious =
+        match controller() {
+            Controller::Pic => pic::is_spurious(irq),
 
-    // switch to task's page directory
-    get_current_task_mut().expect("no tasks?").state.pages.switch_to();
+            // the local APIC has its own vector for its spurious interrupts, but the 8259s can still send their lowest priority lines
+            // with everything masked. those land on the same vectors as the I/O APIC's IRQ 7 and 15, and since they never went through
+            // the local APIC it doesn't have them in service, so they mustn't be handled or acknowledged there
+            Controller::Apic => (irq == pic::SPURIOUS_MASTER || irq == pic::SPURIOUS_SLAVE) && !apic::is_in_service(MASTER_OFFSET + irq),
+        };
+
+    if spurious {
+        SPURIOUS_COUNTS[irq as usize] += 1;
+        pic::spurious_interrupt(irq);
+        return;
+    }
+
+    IRQ_COUNTS[irq as usize] += 1;
+
+    let mut handled = false;
+
+    for registration in HANDLERS[irq as usize].iter().flatten() {
+        (registration.handler)(irq);
+        handled = true;
+    }
+
+    if !handled {
+        debug!("unhandled IRQ {}", irq);
+    }
+
+    end_of_interrupt(irq);
+
+    // gdb asked to stop, show it what we interrupted rather than this handler
+    if crate::gdb::break_in_pending() {
+        crate::gdb::handle_trap(regs);
+    }
+
+    crate::deferred::run_pending();
+}
+
+/// interrupt handler for every line but the timer's, passes it on to dispatch
+#[no_mangle]
+pub unsafe extern "C" fn irq_handler(irq: u32, mut regs: SyscallRegisters) {
+    dispatch(irq as u8, &mut regs);
+}
+
+extern "C" {
+    fn irq0_wrapper() -> !;
+    fn irq1_wrapper() -> !;
+    fn irq2_wrapper() -> !;
+    fn irq3_wrapper() -> !;
+    fn irq4_wrapper() -> !;
+    fn irq5_wrapper() -> !;
+    fn irq6_wrapper() -> !;
+    fn irq7_wrapper() -> !;
+    fn irq8_wrapper() -> !;
+    fn irq9_wrapper() -> !;
+    fn irq10_wrapper() -> !;
+    fn irq11_wrapper() -> !;
+    fn irq12_wrapper() -> !;
+    fn irq13_wrapper() -> !;
+    fn irq14_wrapper() -> !;
+    fn irq15_wrapper() -> !;
+}
+
+/// wrappers around irq_handler to save and restore state, one for each line
+const IRQ_WRAPPERS: [unsafe extern "C" fn() -> !; NUM_IRQS] = [
+    irq0_wrapper, irq1_wrapper, irq2_wrapper, irq3_wrapper,
+    irq4_wrapper, irq5_wrapper, irq6_wrapper, irq7_wrapper,
+    irq8_wrapper, irq9_wrapper, irq10_wrapper, irq11_wrapper,
+    irq12_wrapper, irq13_wrapper, irq14_wrapper, irq15_wrapper,
+];
+
+/// how many times per second the timer fires, unless timer_hz is given on the command line
+pub const DEFAULT_TIMER_RATE: u32 = 100;
+
+/// range of rates the PIT can run at, its 16 bit divisor can't go any lower than 19 Hz
+const TIMER_RATE_RANGE: core::ops::RangeInclusive<u32> = 19..=10000;
+
+/// how many times per second the timer fires
+pub static mut TIMER_RATE: u32 = DEFAULT_TIMER_RATE;
+
+/// how many times the timer has fired since it was initialized
+pub static mut TICKS: u64 = 0;
+
+/// timer interrupt handler, currently just switches tasks
+#[no_mangle]
+pub unsafe extern "C" fn timer_handler(mut regs: SyscallRegisters) {
+    // TODO: task priority, task execution timers
+
+    TICKS += 1;
+    IRQ_COUNTS[TIMER_IRQ as usize] += 1;
 
     // reset interrupt controller
-    outb(0x20, 0x20);
+    end_of_interrupt(TIMER_IRQ);
+
+    crate::deferred::run_pending();
+
+    // we don't want to preempt the kernel- all sorts of bad things could happen
+    if !IN_TASK {
+        return;
+    }
+
+    // switch to the next task
+    NEED_RESCHED = true;
+    return_to_task(&mut regs);
 }
 
 /// initializes PIT at specified frequency in Hz
 pub fn init_timer(rate: u32) {
-    let divisor = 1193180 / rate;
+    let divisor = PIT_FREQUENCY / rate;
 
     let l = (divisor & 0xff) as u8;
     let h = ((divisor >> 8) & 0xff) as u8;
@@ -97,30 +295,47 @@ extern "C" {
 
 // init IRQs
 pub unsafe fn init() {
-    // set up interrupt controller
-    outb(0x20, 0x11);
-    outb(0xa0, 0x11);
-    outb(0x21, 0x20);
-    outb(0xa1, 0x28);
-    outb(0x21, 0x04);
-    outb(0xa1, 0x02);
-    outb(0x21, 0x01);
-    outb(0xa1, 0x01);
-    outb(0x21, 0x0);
-    outb(0xa1, 0x0);
+    // set up the PICs even if we end up using the APIC, so anything they send lands on IRQ vectors instead of exceptions.
+    // every line starts out masked
+    pic::init();
 
-    // initialize timer at 100 Hz
-    init_timer(100);
+    match crate::cmdline::get_int("timer_hz").map(|rate| rate as u32) {
+        Some(rate) if TIMER_RATE_RANGE.contains(&rate) => TIMER_RATE = rate,
+        Some(rate) => log!("timer rate {} Hz out of range, using {} Hz", rate, DEFAULT_TIMER_RATE),
+        None => (),
+    }
 
-    // set up interrupt stubs
-    for i in 33..40 {
-        IDT[i] = IDTEntry::new(stub_handler as *const (), IDTFlags::External);
+    // set up interrupt handlers
+    for (irq, wrapper) in IRQ_WRAPPERS.iter().enumerate() {
+        IDT[MASTER_OFFSET as usize + irq] = IDTEntry::new(*wrapper as *const (), IDTFlags::External);
     }
 
-    for i in 40..48 {
-        IDT[i] = IDTEntry::new(stub_handler_2 as *const (), IDTFlags::External);
+    // set up interrupt handler for the timer, both the PIT and the local APIC timer send this vector
+    IDT[MASTER_OFFSET as usize + TIMER_IRQ as usize] = IDTEntry::new(timer_handler_wrapper as *const (), IDTFlags::External);
+
+    let apic = if crate::cmdline::get_bool("noapic").unwrap_or(false) { Err("disabled with noapic") } else { apic::init() };
+
+    match apic {
+        Ok(()) => {
+            CONTROLLER = Controller::Apic;
+
+            // the PIT still works through the I/O APIC if the local APIC timer doesn't
+            if let Err(err) = apic::init_timer(TIMER_RATE) {
+                log!("{}, using the PIT", err);
+                init_timer(TIMER_RATE);
+                apic::unmask(TIMER_IRQ);
+            }
+        },
+        Err(err) => {
+            log!("using the 8259 PIC: {}", err);
+            init_timer(TIMER_RATE);
+            pic::unmask(TIMER_IRQ);
+        },
     }
 
-    // set up interrupt handler for PIT
-    IDT[32] = IDTEntry::new(timer_handler_wrapper as *const (), IDTFlags::External);
+    // set up keyboard
+    super::keyboard::init();
+
+    // set up serial ports
+    super::serial::init();
 }
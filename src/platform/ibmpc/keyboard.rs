@@ -0,0 +1,259 @@
+//! PS/2 keyboard driver
+//! the i8042 controller translates whatever the keyboard sends into scancode set 1, so that's all we decode
+
+use super::io::{inb, outb};
+use super::irq::register_irq;
+use crate::{
+    arch::without_interrupts,
+    deferred::defer,
+    input::{KeyCode, Modifiers, handle_key},
+};
+
+/// IRQ line the keyboard interrupts on
+const KEYBOARD_IRQ: u8 = 1;
+
+/// i8042 data port
+const DATA_PORT: u16 = 0x60;
+
+/// i8042 status register when read, command register when written
+const STATUS_PORT: u16 = 0x64;
+
+/// status flag set when there's data waiting to be read from the data port
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+
+/// status flag set when the controller hasn't read what we last wrote yet
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+/// controller commands
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_PORT_2: u8 = 0xa7;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_DISABLE_PORT_1: u8 = 0xad;
+const COMMAND_ENABLE_PORT_1: u8 = 0xae;
+const COMMAND_RESET_CPU: u8 = 0xfe;
+
+/// response to a successful controller self test
+const SELF_TEST_PASSED: u8 = 0x55;
+
+/// controller configuration byte flags
+const CONFIG_PORT_1_INTERRUPT: u8 = 1 << 0;
+const CONFIG_PORT_2_INTERRUPT: u8 = 1 << 1;
+const CONFIG_PORT_1_CLOCK_DISABLE: u8 = 1 << 4;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// keyboard command to set the lock LEDs, followed by a byte with the LED state
+const KEYBOARD_SET_LEDS: u8 = 0xed;
+
+/// keyboard acknowledged a command
+const KEYBOARD_ACK: u8 = 0xfa;
+
+/// keyboard wants the last command sent again
+const KEYBOARD_RESEND: u8 = 0xfe;
+
+/// LED bits for KEYBOARD_SET_LEDS
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+/// how many times we poll the controller before giving up on it
+const TIMEOUT: usize = 100000;
+
+/// turns a stream of scancode set 1 bytes into key presses and releases
+pub struct ScancodeDecoder {
+    /// whether the last byte was the 0xe0 extended key prefix
+    extended: bool,
+
+    /// bytes left to skip in the pause key's sequence
+    pause_remaining: u8,
+}
+
+impl ScancodeDecoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            pause_remaining: 0,
+        }
+    }
+
+    /// decodes a byte, returning the key and whether it was pressed once a full scancode has been received
+    pub fn decode(&mut self, byte: u8) -> Option<(KeyCode, bool)> {
+        // pause sends e1 1d 45 e1 9d c5 when pressed and nothing when released
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+
+            return if self.pause_remaining == 0 { Some((KeyCode::Pause, true)) } else { None };
+        }
+
+        match byte {
+            0xe0 => {
+                self.extended = true;
+                return None;
+            },
+            0xe1 => {
+                self.pause_remaining = 5;
+                return None;
+            },
+            0x00 | 0xff => return None, // key detection error or buffer overrun
+            _ => (),
+        }
+
+        let extended = core::mem::replace(&mut self.extended, false);
+        let pressed = byte & 0x80 == 0;
+        let code = byte & 0x7f;
+
+        // print screen and some other extended keys send fake shift presses around themselves, we don't care about those
+        if extended && (code == KeyCode::LeftShift as u8 || code == KeyCode::RightShift as u8) {
+            return None;
+        }
+
+        KeyCode::try_from(if extended { code | 0x80 } else { code }).ok().map(|key| (key, pressed))
+    }
+}
+
+impl Default for ScancodeDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// decoder state for the keyboard
+static mut DECODER: ScancodeDecoder = ScancodeDecoder::new();
+
+/// LED state waiting to be sent once the keyboard acknowledges KEYBOARD_SET_LEDS
+static mut PENDING_LEDS: Option<u8> = None;
+
+/// waits until the controller is ready to accept a byte
+fn wait_for_input() -> bool {
+    (0..TIMEOUT).any(|_| unsafe { inb(STATUS_PORT) } & STATUS_INPUT_FULL == 0)
+}
+
+/// sends a command to the controller
+fn write_command(command: u8) {
+    if wait_for_input() {
+        unsafe { outb(STATUS_PORT, command); }
+    }
+}
+
+/// sends a byte to the controller's data port, which goes to the keyboard unless the controller expects a parameter
+fn write_data(data: u8) {
+    if wait_for_input() {
+        unsafe { outb(DATA_PORT, data); }
+    }
+}
+
+/// waits for a byte from the controller's data port
+fn read_data() -> Option<u8> {
+    if (0..TIMEOUT).any(|_| unsafe { inb(STATUS_PORT) } & STATUS_OUTPUT_FULL != 0) {
+        Some(unsafe { inb(DATA_PORT) })
+    } else {
+        None
+    }
+}
+
+/// reads a key straight from the controller if one is waiting, for code that runs with interrupts off
+pub fn poll_key() -> Option<(KeyCode, bool)> {
+    unsafe {
+        if inb(STATUS_PORT) & STATUS_OUTPUT_FULL == 0 {
+            return None;
+        }
+
+        DECODER.decode(inb(DATA_PORT))
+    }
+}
+
+/// pulses the cpu's reset line, which the controller is wired up to on PCs
+pub fn reset_cpu() {
+    write_command(COMMAND_RESET_CPU);
+}
+
+/// updates the keyboard's lock LEDs
+fn set_leds(locks: Modifiers) {
+    let mut leds = 0;
+
+    if locks & Modifiers::ScrollLock != 0 {
+        leds |= LED_SCROLL_LOCK;
+    }
+    if locks & Modifiers::NumLock != 0 {
+        leds |= LED_NUM_LOCK;
+    }
+    if locks & Modifiers::CapsLock != 0 {
+        leds |= LED_CAPS_LOCK;
+    }
+
+    // the LED state is sent once the keyboard acknowledges the command
+    unsafe { PENDING_LEDS = Some(leds); }
+    write_data(KEYBOARD_SET_LEDS);
+}
+
+/// keyboard interrupt handler (IRQ 1)
+fn keyboard_handler(_irq: u8) {
+    let byte = unsafe { inb(DATA_PORT) };
+
+    match byte {
+        KEYBOARD_ACK => {
+            if let Some(leds) = unsafe { PENDING_LEDS.take() } {
+                write_data(leds);
+            }
+        },
+        KEYBOARD_RESEND => {
+            if unsafe { PENDING_LEDS.is_some() } {
+                write_data(KEYBOARD_SET_LEDS);
+            }
+        },
+        _ => {
+            let _ = defer("keyboard", handle_scancode, byte as usize);
+        },
+    }
+}
+
+/// decodes a byte from the keyboard and passes on the key, this can end up anywhere from the console to a tty so it's deferred
+fn handle_scancode(byte: usize) {
+    if let Some((key, pressed)) = unsafe { DECODER.decode(byte as u8) } {
+        if let Some(locks) = handle_key(key, pressed) {
+            without_interrupts(|| set_leds(locks));
+        }
+    }
+}
+
+/// initializes the i8042 controller and installs the keyboard interrupt handler
+pub unsafe fn init() {
+    // make sure nothing gets in our way while we set things up
+    write_command(COMMAND_DISABLE_PORT_1);
+    write_command(COMMAND_DISABLE_PORT_2);
+
+    // throw away anything left over from the bios
+    for _i in 0..TIMEOUT {
+        if inb(STATUS_PORT) & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        inb(DATA_PORT);
+    }
+
+    write_command(COMMAND_READ_CONFIG);
+    let config =
+        match read_data() {
+            Some(config) => config,
+            None => {
+                log!("no PS/2 controller found");
+                return;
+            },
+        };
+
+    write_command(COMMAND_SELF_TEST);
+    if read_data() != Some(SELF_TEST_PASSED) {
+        log!("PS/2 controller failed self test");
+        return;
+    }
+
+    // the self test can reset the controller, so write the config after it
+    let config = (config | CONFIG_PORT_1_INTERRUPT | CONFIG_TRANSLATION) & !(CONFIG_PORT_2_INTERRUPT | CONFIG_PORT_1_CLOCK_DISABLE);
+    write_command(COMMAND_WRITE_CONFIG);
+    write_data(config);
+
+    if let Err(err) = register_irq(KEYBOARD_IRQ, "keyboard", keyboard_handler) {
+        log!("couldn't register keyboard interrupt handler: {}", err);
+    }
+
+    write_command(COMMAND_ENABLE_PORT_1);
+}
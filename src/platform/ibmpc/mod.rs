@@ -1,15 +1,135 @@
+pub mod acpi;
+pub mod apic;
 pub mod debug;
 pub mod io;
 pub mod vga;
+pub mod framebuffer;
 pub mod irq;
+pub mod pic;
+pub mod keyboard;
+pub mod serial;
 
-use crate::console::{TextConsole, SimpleConsole};
+use alloc::vec::Vec;
+use crate::{
+    cmdline,
+    console::{SimpleConsole, resize_consoles},
+    util::psf::Font,
+};
+
+/// text mode the screen is in at boot, unless vga= asks for another one
+pub const DEFAULT_TEXT_MODE: vga::TextMode = vga::TextMode::Text80x25;
+
+/// gets the font asked for with font= for the given text mode, or None to use the BIOS's font
+fn text_mode_font(mode: vga::TextMode) -> Result<Option<Vec<u8>>, &'static str> {
+    match cmdline::get_str("font").unwrap_or("bios") {
+        "bios" => Ok(None),
+        "fixed" => vga::font_from_psf(&Font::parse(framebuffer::FONT_DATA)?, mode.char_height()).map(Some),
+        _ => Err("unknown font"),
+    }
+}
+
+/// sets up the display hardware consoles are drawn on
+/// if the bootloader set up a framebuffer we draw on that, otherwise we use VGA text mode
+pub fn init_display() {
+    if framebuffer::init() {
+        return;
+    }
+
+    let name = cmdline::get_str("vga").unwrap_or("");
+    let mode = vga::TextMode::from_name(name).unwrap_or_else(|| {
+        log!("unknown text mode {:?}", name);
+        DEFAULT_TEXT_MODE
+    });
+
+    let font =
+        match text_mode_font(mode) {
+            Ok(font) => font,
+            Err(err) => {
+                log!("couldn't load font: {}", err);
+                None
+            },
+        };
+
+    // the BIOS already left us in 80x25 with its own font
+    if mode == vga::get_mode() && font.is_none() {
+        return;
+    }
+
+    if let Err(err) = vga::set_mode(mode, font.as_deref()) {
+        debug!("couldn't set text mode: {}", err);
+    }
+}
 
 pub fn create_console() -> SimpleConsole {
+    if let (Some(raw), Some(framebuffer)) = (framebuffer::create_console(), framebuffer::get_framebuffer()) {
+        return SimpleConsole::new(raw, framebuffer.columns(), framebuffer.rows());
+    }
+
     let raw = vga::create_console();
-    let mut console = SimpleConsole::new(raw, 80, 25);
+    let mode = vga::get_mode();
+
+    SimpleConsole::new(raw, mode.width(), mode.height())
+}
+
+/// switches to a different text mode and resizes the consoles to match
+pub fn set_text_mode(mode: vga::TextMode, font: Option<&[u8]>) -> Result<(), &'static str> {
+    if framebuffer::get_framebuffer().is_some() {
+        return Err("not using VGA text mode");
+    }
+
+    vga::set_mode(mode, font)?;
+    resize_consoles(mode.width(), mode.height());
+
+    Ok(())
+}
 
-    console.clear();
+/// gets devices ready for the machine to go away, so nothing waiting to be sent gets lost
+fn shutdown_devices() {
+    for num in 0..serial::NUM_PORTS {
+        serial::flush(num);
+    }
+}
+
+/// restarts the computer
+pub fn reboot() -> ! {
+    log!("rebooting");
+
+    shutdown_devices();
+
+    if let Err(err) = acpi::reset() {
+        debug!("couldn't reset with ACPI: {}", err);
+    }
+
+    keyboard::reset_cpu();
+
+    // give the controller a moment, then fall back to something that always works
+    for _i in 0..1000000 {
+        core::hint::spin_loop();
+    }
+
+    crate::arch::triple_fault();
+}
+
+/// finds the firmware's tables and sets up interrupts, once paging is up
+pub unsafe fn init() {
+    acpi::init();
+    irq::init();
+}
+
+/// turns the computer off, or halts it if that doesn't work
+pub fn power_off() -> ! {
+    log!("powering off");
+
+    shutdown_devices();
+
+    if let Err(err) = acpi::power_off() {
+        log!("couldn't power off: {}", err);
+    }
+
+    crate::arch::halt();
+}
 
-    console
+/// registers ttys and device files for the devices found on this platform
+pub fn init_devices() {
+    serial::init_devices();
 }
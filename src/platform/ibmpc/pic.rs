@@ -0,0 +1,140 @@
+//! 8259 programmable interrupt controller
+//! PCs have two of these chained together, the slave's output goes into line 2 of the master, giving 15 usable IRQ lines
+
+use super::io::{inb, outb};
+
+/// I/O ports of the master PIC
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+
+/// I/O ports of the slave PIC
+const SLAVE_COMMAND: u16 = 0xa0;
+const SLAVE_DATA: u16 = 0xa1;
+
+/// initialization command words
+const ICW1_ICW4: u8 = 1 << 0;       // ICW4 will be sent
+const ICW1_INIT: u8 = 1 << 4;       // start initialization
+const ICW4_8086: u8 = 1 << 0;       // 8086 mode, as opposed to 8080 mode
+
+/// operation command words
+const OCW2_EOI: u8 = 0x20;          // non-specific end of interrupt
+const OCW3_READ_IRR: u8 = 0x0a;     // next read of the command port gives the interrupt request register
+const OCW3_READ_ISR: u8 = 0x0b;     // next read of the command port gives the in-service register
+
+/// line on the master the slave is wired up to
+pub const CASCADE_IRQ: u8 = 2;
+
+/// how many IRQ lines there are between both PICs
+pub const NUM_IRQS: usize = 16;
+
+/// the lowest priority line on each PIC, which is what the PIC reports when an interrupt goes away before it's acknowledged
+pub const SPURIOUS_MASTER: u8 = 7;
+pub const SPURIOUS_SLAVE: u8 = 15;
+
+/// interrupt vector the master's IRQs start at, just past the cpu's exceptions
+pub const MASTER_OFFSET: u8 = 0x20;
+
+/// interrupt vector the slave's IRQs start at
+pub const SLAVE_OFFSET: u8 = MASTER_OFFSET + 8;
+
+/// lines that are masked, one bit per line. the masks are kept here so we don't have to read them back from the PICs
+static mut MASK: u16 = 0xffff;
+
+/// writes the current mask to both PICs
+unsafe fn write_mask() {
+    outb(MASTER_DATA, (MASK & 0xff) as u8);
+    outb(SLAVE_DATA, (MASK >> 8) as u8);
+}
+
+/// remaps both PICs' interrupts to start at MASTER_OFFSET and masks every line but the cascade
+pub unsafe fn init() {
+    outb(MASTER_COMMAND, ICW1_INIT | ICW1_ICW4);
+    outb(SLAVE_COMMAND, ICW1_INIT | ICW1_ICW4);
+
+    // ICW2: where each PIC's vectors start
+    outb(MASTER_DATA, MASTER_OFFSET);
+    outb(SLAVE_DATA, SLAVE_OFFSET);
+
+    // ICW3: the master gets a bitmask of lines with slaves on them, the slave gets the line it's on
+    outb(MASTER_DATA, 1 << CASCADE_IRQ);
+    outb(SLAVE_DATA, CASCADE_IRQ);
+
+    // ICW4
+    outb(MASTER_DATA, ICW4_8086);
+    outb(SLAVE_DATA, ICW4_8086);
+
+    // nothing gets through until a driver asks for it, except for the slave's interrupts
+    MASK = !(1 << CASCADE_IRQ);
+    write_mask();
+}
+
+/// masks every line, for when interrupts come through the APIC instead
+pub unsafe fn disable() {
+    MASK = 0xffff;
+    write_mask();
+}
+
+/// stops the given line from interrupting
+pub fn mask(irq: u8) {
+    unsafe {
+        MASK |= 1 << irq;
+        write_mask();
+    }
+}
+
+/// lets the given line interrupt
+pub fn unmask(irq: u8) {
+    unsafe {
+        MASK &= !(1 << irq);
+        write_mask();
+    }
+}
+
+/// checks whether the given line is masked
+pub fn is_masked(irq: u8) -> bool {
+    unsafe { MASK & (1 << irq) != 0 }
+}
+
+/// reads one of the PICs' status registers, slave in the high byte
+unsafe fn read_register(ocw3: u8) -> u16 {
+    outb(MASTER_COMMAND, ocw3);
+    outb(SLAVE_COMMAND, ocw3);
+
+    ((inb(SLAVE_COMMAND) as u16) << 8) | inb(MASTER_COMMAND) as u16
+}
+
+/// gets which lines are waiting to be serviced
+pub fn get_irr() -> u16 {
+    unsafe { read_register(OCW3_READ_IRR) }
+}
+
+/// gets which lines are being serviced right now
+pub fn get_isr() -> u16 {
+    unsafe { read_register(OCW3_READ_ISR) }
+}
+
+/// checks whether an interrupt on the given line is spurious, in which case it mustn't be acknowledged like a real one.
+/// if the interrupt goes away before the cpu acknowledges it, the PIC sends its lowest priority line instead,
+/// but doesn't mark it as in service
+pub fn is_spurious(irq: u8) -> bool {
+    (irq == SPURIOUS_MASTER || irq == SPURIOUS_SLAVE) && get_isr() & (1 << irq) == 0
+}
+
+/// tells the PICs we're done with an interrupt, so they can send more from this line and lower priority ones
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(SLAVE_COMMAND, OCW2_EOI);
+        }
+
+        outb(MASTER_COMMAND, OCW2_EOI);
+    }
+}
+
+/// handles a spurious interrupt. the master still needs to hear about spurious interrupts from the slave,
+/// since as far as it knows the cascade line really did interrupt
+pub fn spurious_interrupt(irq: u8) {
+    if irq >= 8 {
+        unsafe { outb(MASTER_COMMAND, OCW2_EOI); }
+    }
+}
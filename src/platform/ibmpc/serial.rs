@@ -0,0 +1,461 @@
+//! 16550 UART driver
+//! received bytes are buffered by the interrupt handler and fed to the port's tty as deferred work, and output is queued up and sent
+//! from the transmit interrupt so writers don't have to wait on the line
+
+use super::{
+    io::{inb, outb},
+    irq::register_irq,
+};
+use alloc::{
+    boxed::Box,
+    format,
+};
+use crate::{
+    arch::without_interrupts,
+    deferred::defer,
+    fs::devfs::add_device,
+    tty::{
+        ControlFlags, Termios, TtyDriver, TtyFile,
+        B9600, B19200, B38400, B57600, B115200,
+        get_tty, register_tty, tty_input,
+    },
+    util::ring::RingBuffer,
+};
+
+/// base I/O ports of COM1 through COM4
+const PORT_BASES: [u16; NUM_PORTS] = [0x3f8, 0x2f8, 0x3e8, 0x2e8];
+
+/// IRQs of COM1 through COM4, COM3 and COM4 share theirs with COM1 and COM2
+const PORT_IRQS: [u8; NUM_PORTS] = [4, 3, 4, 3];
+
+/// amount of serial ports we support
+pub const NUM_PORTS: usize = 4;
+
+/// baud rate ports are set up with
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// clock rate of the UART divided by 16, this is the highest baud rate it supports
+const MAX_BAUD: u32 = 115200;
+
+/// size of the transmit buffer
+const TX_BUFFER_SIZE: usize = 1024;
+
+/// size of the receive buffer, bytes sit here until the tty gets them
+const RX_BUFFER_SIZE: usize = 256;
+
+/// amount of bytes we can write at once when the transmit FIFO is empty
+const FIFO_SIZE: usize = 16;
+
+/// register offsets from the base port
+const REG_DATA: u16 = 0;                // receive/transmit buffer, divisor low byte when DLAB is set
+const REG_INTERRUPT_ENABLE: u16 = 1;    // divisor high byte when DLAB is set
+const REG_INTERRUPT_ID: u16 = 2;        // FIFO control when written
+const REG_LINE_CONTROL: u16 = 3;
+const REG_MODEM_CONTROL: u16 = 4;
+const REG_LINE_STATUS: u16 = 5;
+const REG_MODEM_STATUS: u16 = 6;
+const REG_SCRATCH: u16 = 7;
+
+/// interrupt enable register flags
+const IER_RECEIVED: u8 = 1 << 0;
+const IER_TRANSMIT_EMPTY: u8 = 1 << 1;
+
+/// interrupt identification register values
+const IIR_NO_INTERRUPT: u8 = 1 << 0;
+const IIR_MASK: u8 = 0x0e;
+const IIR_MODEM_STATUS: u8 = 0x00;
+const IIR_TRANSMIT_EMPTY: u8 = 0x02;
+const IIR_RECEIVED: u8 = 0x04;
+const IIR_LINE_STATUS: u8 = 0x06;
+const IIR_TIMEOUT: u8 = 0x0c;
+
+/// FIFO control: enable, clear both FIFOs, interrupt once 14 bytes have been received
+const FCR_ENABLE_14: u8 = 0xc7;
+
+/// line control register flags
+const LCR_8N1: u8 = 0x03;
+const LCR_DIVISOR_LATCH: u8 = 1 << 7;
+
+/// modem control register flags
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT2: u8 = 1 << 3; // gates the UART's interrupt line on PCs
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+/// line status register flags
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// a serial port
+pub struct SerialPort {
+    /// base I/O port
+    base: u16,
+
+    /// IRQ line the port interrupts on
+    pub irq: u8,
+
+    /// current baud rate
+    pub baud: u32,
+
+    /// output waiting to be sent
+    tx: RingBuffer<u8, TX_BUFFER_SIZE>,
+
+    /// input waiting to be passed on to the tty
+    rx: RingBuffer<u8, RX_BUFFER_SIZE>,
+
+    /// whether passing input on to the tty has been queued up
+    input_queued: bool,
+
+    /// index of the tty attached to this port, if one has been registered
+    pub tty: Option<usize>,
+
+    /// whether the GDB stub owns this port, in which case it doesn't get a tty
+    pub debugger: bool,
+}
+
+impl SerialPort {
+    /// checks whether there's a working UART at the given port, using its loopback mode
+    fn probe(base: u16) -> bool {
+        unsafe {
+            outb(base + REG_SCRATCH, 0x5a);
+            if inb(base + REG_SCRATCH) != 0x5a {
+                return false;
+            }
+
+            outb(base + REG_INTERRUPT_ENABLE, 0);
+            outb(base + REG_MODEM_CONTROL, MCR_LOOPBACK | MCR_RTS | MCR_DTR);
+            outb(base + REG_DATA, 0xae);
+            let works = inb(base + REG_DATA) == 0xae;
+            outb(base + REG_MODEM_CONTROL, 0);
+
+            works
+        }
+    }
+
+    /// sets up the port at the given baud rate with 8N1 framing and FIFOs enabled
+    fn new(base: u16, irq: u8, baud: u32) -> Self {
+        let mut port = Self {
+            base, irq, baud,
+            tx: RingBuffer::new(0),
+            rx: RingBuffer::new(0),
+            input_queued: false,
+            tty: None,
+            debugger: false,
+        };
+
+        unsafe {
+            outb(base + REG_INTERRUPT_ENABLE, 0);
+            port.set_baud(baud);
+            outb(base + REG_INTERRUPT_ID, FCR_ENABLE_14);
+            outb(base + REG_MODEM_CONTROL, MCR_DTR | MCR_RTS | MCR_OUT2);
+
+            // throw away anything left over
+            while inb(base + REG_LINE_STATUS) & LSR_DATA_READY != 0 {
+                inb(base + REG_DATA);
+            }
+
+            outb(base + REG_INTERRUPT_ENABLE, IER_RECEIVED);
+        }
+
+        port
+    }
+
+    /// changes the baud rate of the port
+    pub fn set_baud(&mut self, baud: u32) {
+        // the divisor is 16 bits, so really slow baud rates get the slowest one we can do
+        let divisor = (MAX_BAUD / baud.max(1)).clamp(1, 0xffff) as u16;
+
+        unsafe {
+            outb(self.base + REG_LINE_CONTROL, LCR_DIVISOR_LATCH);
+            outb(self.base + REG_DATA, (divisor & 0xff) as u8);
+            outb(self.base + REG_INTERRUPT_ENABLE, (divisor >> 8) as u8);
+            outb(self.base + REG_LINE_CONTROL, LCR_8N1);
+        }
+
+        self.baud = MAX_BAUD / divisor as u32;
+    }
+
+    /// puts the port in or out of loopback mode, where everything it sends is received right back
+    pub fn set_loopback(&mut self, enabled: bool) {
+        let mcr = if enabled { MCR_LOOPBACK | MCR_RTS | MCR_DTR } else { MCR_DTR | MCR_RTS | MCR_OUT2 };
+        unsafe { outb(self.base + REG_MODEM_CONTROL, mcr); }
+    }
+
+    /// whether the transmit holding register can take another byte
+    fn can_transmit(&self) -> bool {
+        unsafe { inb(self.base + REG_LINE_STATUS) & LSR_TRANSMIT_EMPTY != 0 }
+    }
+
+    /// enables or disables the transmit empty interrupt
+    fn set_transmit_interrupt(&self, enabled: bool) {
+        let ier = if enabled { IER_RECEIVED | IER_TRANSMIT_EMPTY } else { IER_RECEIVED };
+        unsafe { outb(self.base + REG_INTERRUPT_ENABLE, ier); }
+    }
+
+    /// moves as much queued output as the FIFO can hold into it
+    fn transmit(&mut self) {
+        if !self.can_transmit() {
+            return;
+        }
+
+        for _i in 0..FIFO_SIZE {
+            match self.tx.pop() {
+                Some(byte) => unsafe { outb(self.base + REG_DATA, byte) },
+                None => break,
+            }
+        }
+
+        // we only care about the FIFO emptying if there's more to send
+        self.set_transmit_interrupt(!self.tx.is_empty());
+    }
+
+    /// queues up bytes to be sent
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            // if the buffer is full, wait for room
+            while self.tx.push(byte).is_err() {
+                self.flush_one();
+            }
+        }
+
+        self.transmit();
+    }
+
+    /// sends one byte from the transmit buffer, waiting until the line is ready
+    fn flush_one(&mut self) {
+        if let Some(byte) = self.tx.pop() {
+            while !self.can_transmit() {}
+            unsafe { outb(self.base + REG_DATA, byte); }
+        }
+    }
+
+    /// sends everything in the transmit buffer right away, waiting on the line
+    /// used when interrupts might not be coming, like when panicking
+    pub fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            self.flush_one();
+        }
+    }
+
+    /// takes everything the port has received and queues it up to be passed on to its tty once the interrupt is done
+    pub fn receive(&mut self, num: usize) {
+        while unsafe { inb(self.base + REG_LINE_STATUS) } & LSR_DATA_READY != 0 {
+            let byte = unsafe { inb(self.base + REG_DATA) };
+
+            if self.debugger {
+                // gdb sends ctrl+c when it wants the kernel to stop
+                if byte == crate::gdb::INTERRUPT {
+                    crate::gdb::break_in();
+                }
+            } else if self.tty.is_some() && self.rx.push(byte).is_err() {
+                debug!("ttyS{} input overrun", num);
+            }
+        }
+
+        if !self.input_queued && !self.rx.is_empty() {
+            self.input_queued = defer("serial", flush_input, num).is_ok();
+        }
+    }
+
+    /// reads a byte if one has come in, without waiting on interrupts
+    pub fn poll_read(&mut self) -> Option<u8> {
+        unsafe {
+            if inb(self.base + REG_LINE_STATUS) & LSR_DATA_READY != 0 {
+                Some(inb(self.base + REG_DATA))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// sends a byte right away, waiting on the line instead of the transmit interrupt
+    pub fn write_polled(&mut self, byte: u8) {
+        self.flush();
+
+        while !self.can_transmit() {}
+        unsafe { outb(self.base + REG_DATA, byte); }
+    }
+
+    /// handles an interrupt from this port, returns false if it wasn't this port that interrupted
+    fn handle_interrupt(&mut self, num: usize) -> bool {
+        let mut handled = false;
+
+        loop {
+            let id = unsafe { inb(self.base + REG_INTERRUPT_ID) };
+
+            if id & IIR_NO_INTERRUPT != 0 {
+                return handled;
+            }
+
+            handled = true;
+
+            match id & IIR_MASK {
+                IIR_RECEIVED | IIR_TIMEOUT => self.receive(num),
+                IIR_TRANSMIT_EMPTY => self.transmit(),
+                IIR_LINE_STATUS => unsafe { inb(self.base + REG_LINE_STATUS); },
+                IIR_MODEM_STATUS => unsafe { inb(self.base + REG_MODEM_STATUS); },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// all the serial ports that were found
+static mut SERIAL_PORTS: [Option<SerialPort>; NUM_PORTS] = [None, None, None, None];
+
+/// gets a serial port by its number (0 for COM1)
+pub fn get_port(num: usize) -> Option<&'static mut SerialPort> {
+    unsafe { SERIAL_PORTS.get_mut(num)?.as_mut() }
+}
+
+/// writes bytes to a serial port
+pub fn write(num: usize, bytes: &[u8]) {
+    if let Some(port) = get_port(num) {
+        without_interrupts(|| port.write(bytes));
+    }
+}
+
+/// sends everything queued up for a serial port right away
+pub fn flush(num: usize) {
+    if let Some(port) = get_port(num) {
+        without_interrupts(|| port.flush());
+    }
+}
+
+/// handles an interrupt for every port on the given IRQ line
+fn handle_irq(irq: u8) {
+    for (num, port) in unsafe { SERIAL_PORTS.iter_mut().enumerate() } {
+        if let Some(port) = port {
+            if port.irq == irq {
+                port.handle_interrupt(num);
+            }
+        }
+    }
+}
+
+/// passes what came in on a port on to its tty
+fn flush_input(num: usize) {
+    let port =
+        match get_port(num) {
+            Some(port) => port,
+            None => return,
+        };
+
+    let mut buf = [0; RX_BUFFER_SIZE];
+
+    let (tty, len) = without_interrupts(|| {
+        port.input_queued = false;
+
+        let mut len = 0;
+        while let Some(byte) = port.rx.pop() {
+            buf[len] = byte;
+            len += 1;
+        }
+
+        (port.tty, len)
+    });
+
+    if let Some(tty) = tty {
+        tty_input(tty, &buf[..len]);
+    }
+}
+
+/// termios baud rate values and the baud rates they stand for
+const BAUD_RATES: [(u32, u32); 5] = [
+    (B9600, 9600),
+    (B19200, 19200),
+    (B38400, 38400),
+    (B57600, 57600),
+    (B115200, 115200),
+];
+
+/// converts a termios baud rate to an actual baud rate
+pub fn termios_baud(termios: &Termios) -> Option<u32> {
+    let flags = termios.control_flags & ControlFlags::BaudMask;
+
+    BAUD_RATES.iter().find(|(value, _)| flags == ControlFlags::from(*value)).map(|(_, baud)| *baud)
+}
+
+/// converts a baud rate to a termios baud rate
+pub fn baud_termios(baud: u32) -> ControlFlags {
+    let value = BAUD_RATES.iter().find(|(_, rate)| *rate == baud).map_or(B115200, |(value, _)| *value);
+
+    ControlFlags::from(value)
+}
+
+/// tty driver for a serial port
+pub struct SerialTtyDriver {
+    /// number of the port (0 for COM1)
+    port: usize,
+}
+
+impl TtyDriver for SerialTtyDriver {
+    fn write(&mut self, bytes: &[u8]) {
+        write(self.port, bytes);
+    }
+
+    fn set_termios(&mut self, termios: &Termios) {
+        if let (Some(port), Some(baud)) = (get_port(self.port), termios_baud(termios)) {
+            without_interrupts(|| {
+                // let anything that was written at the old baud rate go out first
+                port.flush();
+                port.set_baud(baud);
+            });
+        }
+    }
+}
+
+/// finds and sets up serial ports and installs their interrupt handlers
+pub unsafe fn init() {
+    for (num, (&base, &irq)) in PORT_BASES.iter().zip(PORT_IRQS.iter()).enumerate() {
+        if SerialPort::probe(base) {
+            SERIAL_PORTS[num] = Some(SerialPort::new(base, irq, DEFAULT_BAUD));
+        }
+    }
+
+    // COM1 and COM3 share a line, as do COM2 and COM4, so only register each line once
+    for (num, &irq) in PORT_IRQS.iter().enumerate() {
+        let needed = SERIAL_PORTS.iter().flatten().any(|port| port.irq == irq);
+
+        if needed && !PORT_IRQS[..num].contains(&irq) {
+            if let Err(err) = register_irq(irq, "serial", handle_irq) {
+                log!("couldn't register serial interrupt handler for IRQ {}: {}", irq, err);
+            }
+        }
+    }
+}
+
+/// registers ttys and device files for every serial port that was found
+pub fn init_devices() {
+    for num in 0..NUM_PORTS {
+        let baud =
+            match get_port(num) {
+                Some(port) if port.debugger => {
+                    log!("COM{} is being used by the debugger", num + 1);
+                    continue;
+                },
+                Some(port) => port.baud,
+                None => continue,
+            };
+
+        let name = format!("ttyS{}", num);
+        let index = register_tty(&name, Box::new(SerialTtyDriver { port: num }));
+
+        // make the tty's settings match how the port is actually set up
+        if let Some(tty) = get_tty(index) {
+            tty.termios.control_flags = (tty.termios.control_flags & !ControlFlags::BaudMask) | baud_termios(baud);
+        }
+
+        without_interrupts(|| {
+            if let Some(port) = get_port(num) {
+                port.tty = Some(index);
+            }
+        });
+
+        if let Err(err) = add_device(Box::new(TtyFile::new(&name, index))) {
+            log!("couldn't add /dev/{}: {}", name, err);
+        }
+
+        log!("COM{} at {} baud is /dev/{}", num + 1, baud, name);
+    }
+}
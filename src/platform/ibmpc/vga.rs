@@ -1,59 +1,453 @@
 //! x86 vga text mode
 
-use crate::console::{ColorCode, RawTextConsole};
-use core::cmp::Ordering;
-use alloc::boxed::Box;
+use crate::{
+    console::{ColorCode, RawTextConsole},
+    util::psf::Font,
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use super::io::{inb, outb};
 
-const BUFFER_WIDTH: usize = 80;
-const BUFFER_HEIGHT: usize = 25;
+/// video ram in text modes, the lowest 4 mb are mapped up to 0xc0000000 (3gb), this includes video ram lmao
+const TEXT_BUFFER: usize = 0xc00b8000;
 
-/// describes the layout of video ram, makes casting a pointer to it easier
-#[repr(transparent)]
-pub struct Buffer {
-    chars: [[u16; BUFFER_WIDTH]; BUFFER_HEIGHT],
+/// video ram as seen when a font plane is mapped in
+const FONT_BUFFER: usize = 0xc00a0000;
+
+/// amount of bytes each character takes up in the font plane, regardless of how tall it actually is
+const FONT_CHAR_STRIDE: usize = 32;
+
+/// amount of characters in a font
+pub const FONT_CHARS: usize = 256;
+
+/// miscellaneous output register, written here and read at MISC_READ
+const MISC_WRITE: u16 = 0x3c2;
+const MISC_READ: u16 = 0x3cc;
+
+/// sequencer index and data registers
+const SEQ_INDEX: u16 = 0x3c4;
+const SEQ_DATA: u16 = 0x3c5;
+
+/// graphics controller index and data registers
+const GC_INDEX: u16 = 0x3ce;
+const GC_DATA: u16 = 0x3cf;
+
+/// sequencer registers
+const SEQ_RESET: u8 = 0x00;
+const SEQ_CLOCKING_MODE: u8 = 0x01;
+const SEQ_MAP_MASK: u8 = 0x02;
+const SEQ_MEMORY_MODE: u8 = 0x04;
+
+/// graphics controller registers
+const GC_READ_MAP: u8 = 0x04;
+const GC_MODE: u8 = 0x05;
+const GC_MISC: u8 = 0x06;
+
+/// CRT controller index register
+const CRTC_INDEX: u16 = 0x3d4;
+
+/// CRT controller data register
+const CRTC_DATA: u16 = 0x3d5;
+
+/// CRTC register for the first scanline of the cursor, bit 5 disables the cursor
+const CRTC_CURSOR_START: u8 = 0x0a;
+
+/// CRTC register for the last scanline of the cursor
+const CRTC_CURSOR_END: u8 = 0x0b;
+
+/// CRTC register for the high byte of the cursor location
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0e;
+
+/// CRTC register for the low byte of the cursor location
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+/// bit in the cursor start register that hides the cursor
+const CURSOR_DISABLE: u8 = 1 << 5;
+
+/// CRTC register holding the write protect bit for registers 0 through 7
+const CRTC_VERTICAL_RETRACE_END: u8 = 0x11;
+
+/// bit in the vertical retrace end register that write protects CRTC registers 0 through 7
+const CRTC_PROTECT: u8 = 1 << 7;
+
+/// reads a CRT controller register
+fn read_crtc(index: u8) -> u8 {
+    unsafe {
+        outb(CRTC_INDEX, index);
+        inb(CRTC_DATA)
+    }
+}
+
+/// writes a CRT controller register
+fn write_crtc(index: u8, value: u8) {
+    unsafe {
+        outb(CRTC_INDEX, index);
+        outb(CRTC_DATA, value);
+    }
+}
+
+/// writes a sequencer register
+fn write_seq(index: u8, value: u8) {
+    unsafe {
+        outb(SEQ_INDEX, index);
+        outb(SEQ_DATA, value);
+    }
+}
+
+/// writes a graphics controller register
+fn write_gc(index: u8, value: u8) {
+    unsafe {
+        outb(GC_INDEX, index);
+        outb(GC_DATA, value);
+    }
+}
+
+/// text modes we know how to set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode {
+    /// the standard mode the BIOS leaves us in, 720x400 with 9x16 characters
+    Text80x25,
+
+    /// 720x400 with 9x8 characters
+    Text80x50,
+
+    /// 720x480 with 8x16 characters
+    Text90x30,
+
+    /// 720x480 with 8x8 characters
+    Text90x60,
+}
+
+impl TextMode {
+    /// all the text modes
+    pub const ALL: [TextMode; 4] = [TextMode::Text80x25, TextMode::Text80x50, TextMode::Text90x30, TextMode::Text90x60];
+
+    /// width of the screen in characters
+    pub fn width(self) -> u16 {
+        match self {
+            TextMode::Text80x25 | TextMode::Text80x50 => 80,
+            TextMode::Text90x30 | TextMode::Text90x60 => 90,
+        }
+    }
+
+    /// height of the screen in characters
+    pub fn height(self) -> u16 {
+        match self {
+            TextMode::Text80x25 => 25,
+            TextMode::Text80x50 => 50,
+            TextMode::Text90x30 => 30,
+            TextMode::Text90x60 => 60,
+        }
+    }
+
+    /// height of a character in scanlines
+    pub fn char_height(self) -> u8 {
+        match self {
+            TextMode::Text80x25 | TextMode::Text90x30 => 16,
+            TextMode::Text80x50 | TextMode::Text90x60 => 8,
+        }
+    }
+
+    /// finds a text mode by its dimensions, like "80x50"
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|mode| {
+            let (width, height) = name.split_once('x').unwrap_or(("", ""));
+            width.parse::<u16>().ok() == Some(mode.width()) && height.parse::<u16>().ok() == Some(mode.height())
+        })
+    }
+
+    /// register values for this mode
+    pub fn registers(self) -> &'static ModeRegisters {
+        match self {
+            TextMode::Text80x25 => &MODE_80X25,
+            TextMode::Text80x50 => &MODE_80X50,
+            TextMode::Text90x30 => &MODE_90X30,
+            TextMode::Text90x60 => &MODE_90X60,
+        }
+    }
+}
+
+/// the registers that differ between text modes
+pub struct ModeRegisters {
+    /// miscellaneous output register, selects the dot clock and sync polarity (and so the amount of scanlines)
+    pub misc: u8,
+
+    /// sequencer clocking mode register, selects 8 or 9 dot wide characters
+    pub clocking_mode: u8,
+
+    /// CRT controller registers 0 through 0x18
+    pub crtc: [u8; 25],
+}
+
+/// 720x400, 28 MHz dot clock, 9 dot characters
+const MODE_80X25: ModeRegisters = ModeRegisters {
+    misc: 0x67,
+    clocking_mode: 0x00,
+    crtc: [
+        0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f,
+        0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00, 0x00, 0x00,
+        0x9c, 0x8e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3,
+        0xff,
+    ],
+};
+
+/// same timing as 80x25, with characters half as tall
+const MODE_80X50: ModeRegisters = ModeRegisters {
+    misc: 0x67,
+    clocking_mode: 0x00,
+    crtc: [
+        0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f,
+        0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0x9c, 0x8e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3,
+        0xff,
+    ],
+};
+
+/// 720x480, 28 MHz dot clock, 8 dot characters
+const MODE_90X30: ModeRegisters = ModeRegisters {
+    misc: 0xe7,
+    clocking_mode: 0x01,
+    crtc: [
+        0x6b, 0x59, 0x5a, 0x82, 0x60, 0x8d, 0x0b, 0x3e,
+        0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00, 0x00, 0x00,
+        0xea, 0x8c, 0xdf, 0x2d, 0x10, 0xe8, 0x05, 0xa3,
+        0xff,
+    ],
+};
+
+/// same timing as 90x30, with characters half as tall
+const MODE_90X60: ModeRegisters = ModeRegisters {
+    misc: 0xe7,
+    clocking_mode: 0x01,
+    crtc: [
+        0x6b, 0x59, 0x5a, 0x82, 0x60, 0x8d, 0x0b, 0x3e,
+        0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0xea, 0x8c, 0xdf, 0x2d, 0x08, 0xe8, 0x05, 0xa3,
+        0xff,
+    ],
+};
+
+/// the text mode we're in
+static mut MODE: TextMode = TextMode::Text80x25;
+
+/// the font the BIOS loaded, saved before we replace it so we can make other fonts from it
+static mut BIOS_FONT: Option<Box<[u8; FONT_CHARS * FONT_CHAR_STRIDE]>> = None;
+
+/// gets the text mode we're in
+pub fn get_mode() -> TextMode {
+    unsafe { MODE }
+}
+
+/// maps the font plane (plane 2) in at 0xa0000 so fonts can be read and written, and runs the given function
+fn with_font_plane<T>(op: impl FnOnce(&mut [u8]) -> T) -> T {
+    write_seq(SEQ_MAP_MASK, 0x04); // only write to plane 2
+    write_seq(SEQ_MEMORY_MODE, 0x07); // sequential addressing instead of odd/even
+    write_gc(GC_READ_MAP, 0x02); // read from plane 2
+    write_gc(GC_MODE, 0x00);
+    write_gc(GC_MISC, 0x04); // map 64k at 0xa0000, no odd/even
+
+    let result = op(unsafe { core::slice::from_raw_parts_mut(FONT_BUFFER as *mut u8, FONT_CHARS * FONT_CHAR_STRIDE) });
+
+    // back to the usual text mode setup
+    write_seq(SEQ_MAP_MASK, 0x03);
+    write_seq(SEQ_MEMORY_MODE, 0x03);
+    write_gc(GC_READ_MAP, 0x00);
+    write_gc(GC_MODE, 0x10);
+    write_gc(GC_MISC, 0x0e);
+
+    result
+}
+
+/// uploads a font, with char_height bytes per character (one per row, most significant bit on the left)
+pub fn load_font(font: &[u8], char_height: u8) -> Result<(), &'static str> {
+    let height = char_height as usize;
+
+    if height == 0 || height > FONT_CHAR_STRIDE {
+        return Err("unsupported character height");
+    }
+
+    if font.len() < height * FONT_CHARS {
+        return Err("font is missing characters");
+    }
+
+    with_font_plane(|plane| {
+        for (c, glyph) in font.chunks_exact(height).take(FONT_CHARS).enumerate() {
+            let dest = &mut plane[c * FONT_CHAR_STRIDE..(c + 1) * FONT_CHAR_STRIDE];
+            dest[..height].copy_from_slice(glyph);
+            dest[height..].fill(0);
+        }
+    });
+
+    Ok(())
+}
+
+/// saves the font the BIOS loaded if we haven't already
+fn save_bios_font() {
+    unsafe {
+        if BIOS_FONT.is_none() {
+            let mut font = Box::new([0; FONT_CHARS * FONT_CHAR_STRIDE]);
+            with_font_plane(|plane| font.copy_from_slice(plane));
+            BIOS_FONT = Some(font);
+        }
+    }
+}
+
+/// makes a font with the given character height out of the BIOS's 8x16 font
+/// 8 scanline fonts are made by merging pairs of rows, which is squashed but perfectly readable
+fn bios_font(char_height: u8) -> Option<Vec<u8>> {
+    let bios = unsafe { BIOS_FONT.as_ref()? };
+    let height = char_height as usize;
+
+    let mut font = Vec::with_capacity(FONT_CHARS * height);
+
+    for glyph in bios.chunks_exact(FONT_CHAR_STRIDE) {
+        match height {
+            16 => font.extend_from_slice(&glyph[..16]),
+            8 => font.extend((0..8).map(|row| glyph[row * 2] | glyph[row * 2 + 1])),
+            _ => return None,
+        }
+    }
+
+    Some(font)
 }
 
-/// raw VGA text console
-pub struct VGAConsole {
-    buffer: &'static mut Buffer,
+/// makes a font for text mode out of a PSF font, padding each glyph with blank rows at the bottom to fill the given character height.
+/// glyphs have to be 8 pixels wide, since that's all the VGA can draw
+pub fn font_from_psf(psf: &Font, char_height: u8) -> Result<Vec<u8>, &'static str> {
+    let height = char_height as usize;
+
+    if psf.width != 8 {
+        return Err("font isn't 8 pixels wide");
+    }
+
+    if psf.height > height {
+        return Err("font is too tall for this text mode");
+    }
+
+    let mut font = Vec::with_capacity(FONT_CHARS * height);
+
+    for c in 0..FONT_CHARS {
+        font.extend_from_slice(psf.glyph(c));
+        font.resize((c + 1) * height, 0);
+    }
+
+    Ok(font)
+}
+
+/// switches to a different text mode, loading a font for it
+/// if no font is given, one is made from the BIOS's font
+/// anything on screen is garbage afterwards, so consoles need to be resized and redrawn
+pub fn set_mode(mode: TextMode, font: Option<&[u8]>) -> Result<(), &'static str> {
+    save_bios_font();
+
+    let generated;
+    let font =
+        match font {
+            Some(font) => font,
+            None => {
+                generated = bios_font(mode.char_height()).ok_or("couldn't make font")?;
+                &generated[..]
+            },
+        };
+
+    let registers = mode.registers();
+
+    unsafe {
+        // hold the sequencer in reset while changing clocks
+        write_seq(SEQ_RESET, 0x01);
+        outb(MISC_WRITE, (inb(MISC_READ) & 0x01) | (registers.misc & !0x01)); // keep the I/O address select bit
+        write_seq(SEQ_CLOCKING_MODE, registers.clocking_mode);
+        write_seq(SEQ_RESET, 0x03);
+
+        // unlock CRTC registers 0 through 7 and write everything
+        write_crtc(CRTC_VERTICAL_RETRACE_END, read_crtc(CRTC_VERTICAL_RETRACE_END) & !CRTC_PROTECT);
+
+        for (index, &value) in registers.crtc.iter().enumerate() {
+            let value = if index as u8 == CRTC_VERTICAL_RETRACE_END { value & !CRTC_PROTECT } else { value };
+            write_crtc(index as u8, value);
+        }
+
+        write_crtc(CRTC_VERTICAL_RETRACE_END, registers.crtc[CRTC_VERTICAL_RETRACE_END as usize] | CRTC_PROTECT);
+    }
+
+    load_font(font, mode.char_height())?;
+
+    unsafe { MODE = mode; }
+
+    VGAConsole.set_cursor_shape(mode.char_height() - 2, mode.char_height() - 1);
+
+    debug!("vga: switched to {}x{} text mode", mode.width(), mode.height());
+
+    Ok(())
+}
+
+/// raw VGA text console, always the size of the current text mode
+pub struct VGAConsole;
+
+impl VGAConsole {
+    /// gets video ram and the width of the screen
+    fn buffer(&mut self) -> (&'static mut [u16], u16) {
+        let mode = get_mode();
+        let len = mode.width() as usize * mode.height() as usize;
+
+        (unsafe { core::slice::from_raw_parts_mut(TEXT_BUFFER as *mut u16, len) }, mode.width())
+    }
 }
 
 impl RawTextConsole for VGAConsole {
     fn write_char(&mut self, x: u16, y: u16, color: ColorCode, c: u8) {
-        self.buffer.chars[y as usize][x as usize] = (((color.background as u16) & 0xf) << 12) | (((color.foreground as u16) & 0xf) << 8) | (c as u16);
+        let (buffer, width) = self.buffer();
+        buffer[y as usize * width as usize + x as usize] = (((color.background as u16) & 0xf) << 12) | (((color.foreground as u16) & 0xf) << 8) | (c as u16);
     }
 
     fn clear(&mut self, x0: u16, y0: u16, x1: u16, y1: u16, color: ColorCode) {
         let color2 = (((color.background as u16) & 0xf) << 12) | (((color.foreground as u16) & 0xf) << 8);
+        let (buffer, width) = self.buffer();
         for y in y0..y1 {
-            for x in x0..x1 {
-                self.buffer.chars[y as usize][x as usize] = color2 | ((b' ') as u16);
-            }
+            let start = y as usize * width as usize;
+            buffer[start + x0 as usize..start + x1 as usize].fill(color2 | ((b' ') as u16));
         }
     }
 
     fn copy(&mut self, y0: u16, y1: u16, height: u16) {
-        match y0.cmp(&y1) {
-            Ordering::Less => { // scroll up
-                let diff = y1 - y0;
-                for y in (y0 .. y0 + height).rev() {
-                    self.buffer.chars[(y + diff) as usize] = self.buffer.chars[y as usize];
-                }
-            },
-            Ordering::Greater => { // scroll down
-                let diff = y0 - y1;
-                for y in y0 .. y0 + height {
-                    self.buffer.chars[(y - diff) as usize] = self.buffer.chars[y as usize];
-                }
-            },
-            _ => (),
+        let (buffer, width) = self.buffer();
+        let width = width as usize;
+        buffer.copy_within(y0 as usize * width..(y0 + height) as usize * width, y1 as usize * width);
+    }
+
+    fn set_cursor_pos(&mut self, x: u16, y: u16) {
+        let pos = y * get_mode().width() + x;
+
+        write_crtc(CRTC_CURSOR_LOCATION_HIGH, (pos >> 8) as u8);
+        write_crtc(CRTC_CURSOR_LOCATION_LOW, (pos & 0xff) as u8);
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        let start = read_crtc(CRTC_CURSOR_START);
+
+        if visible {
+            write_crtc(CRTC_CURSOR_START, start & !CURSOR_DISABLE);
+        } else {
+            write_crtc(CRTC_CURSOR_START, start | CURSOR_DISABLE);
         }
     }
+
+    fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        // keep the disable bit in the start register and the skew bits in the end register
+        write_crtc(CRTC_CURSOR_START, (read_crtc(CRTC_CURSOR_START) & 0xe0) | (start & 0x1f));
+        write_crtc(CRTC_CURSOR_END, (read_crtc(CRTC_CURSOR_END) & 0xe0) | (end & 0x1f));
+    }
 }
 
 /// creates a raw console
 pub fn create_console() -> Box<dyn RawTextConsole + Sync> {
-    Box::new(VGAConsole {
-        buffer: unsafe { &mut *(0xc00b8000 as *mut Buffer) }, // lowest 4 mb are mapped up to 0xc0000000 (3gb), this includes video ram lmao
-    })
+    let mut console = VGAConsole;
+
+    // underline cursor, the bottom two scanlines of a character
+    let char_height = get_mode().char_height();
+    console.set_cursor_shape(char_height - 2, char_height - 1);
+
+    Box::new(console)
 }
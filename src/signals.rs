@@ -0,0 +1,182 @@
+//! POSIX-ish signals
+//! tasks can't install their own signal handlers yet, so every signal just has its default action applied
+
+use num_enum::TryFromPrimitive;
+use crate::{
+    arch::tasks::{kill_task, kill_thread_group},
+    errno::Errno,
+    tasks::{CURRENT_TASK, TASKS, BlockReason, Task, get_current_task_mut},
+};
+
+/// signal numbers, these match the numbering used on linux
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u32)]
+pub enum Signal {
+    Hangup = 1,                 // SIGHUP
+    Interrupt = 2,              // SIGINT
+    Quit = 3,                   // SIGQUIT
+    IllegalInstruction = 4,     // SIGILL
+    Trap = 5,                   // SIGTRAP
+    Abort = 6,                  // SIGABRT
+    BusError = 7,               // SIGBUS
+    FloatingPoint = 8,          // SIGFPE
+    Kill = 9,                   // SIGKILL
+    User1 = 10,                 // SIGUSR1
+    SegmentationFault = 11,     // SIGSEGV
+    User2 = 12,                 // SIGUSR2
+    BrokenPipe = 13,            // SIGPIPE
+    Alarm = 14,                 // SIGALRM
+    Terminate = 15,             // SIGTERM
+    Child = 17,                 // SIGCHLD
+    Continue = 18,              // SIGCONT
+    Stop = 19,                  // SIGSTOP
+    TerminalStop = 20,          // SIGTSTP
+    TerminalInput = 21,         // SIGTTIN
+    TerminalOutput = 22,        // SIGTTOU
+    Urgent = 23,                // SIGURG
+    WindowChanged = 28,         // SIGWINCH
+}
+
+/// what happens to a task when it receives a signal
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignalAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+impl Signal {
+    /// gets the action taken when a task receives this signal
+    pub fn default_action(&self) -> SignalAction {
+        match self {
+            Self::Child | Self::Urgent | Self::WindowChanged => SignalAction::Ignore,
+            Self::Stop | Self::TerminalStop | Self::TerminalInput | Self::TerminalOutput => SignalAction::Stop,
+            Self::Continue => SignalAction::Continue,
+            _ => SignalAction::Terminate,
+        }
+    }
+
+    /// gets the bit representing this signal in a set of pending signals
+    pub fn mask(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+/// sends a signal to a task
+pub fn send_signal(task: &mut Task, signal: Signal) {
+    debug!("sending {:?} to task {}", signal, task.id);
+
+    match signal.default_action() {
+        SignalAction::Ignore => (),
+        SignalAction::Continue => {
+            // any stop signals that haven't been handled yet are cancelled out
+            task.pending_signals &= !(Signal::Stop.mask() | Signal::TerminalStop.mask() | Signal::TerminalInput.mask() | Signal::TerminalOutput.mask());
+
+            if task.blocked_on == Some(BlockReason::Stopped) {
+                task.unblock();
+            }
+        },
+        action => {
+            task.pending_signals |= signal.mask();
+
+            match task.blocked_on {
+                // interrupt whatever syscall the task is blocked in so it can handle the signal
+                Some(BlockReason::Futex(_)) => {
+                    task.state.set_syscall_result(-(Errno::Interrupted.number() as i32) as u32);
+                    task.unblock();
+                },
+                // tty reads are restarted once the task is woken up, so the task just needs to be woken up to handle the signal
+                Some(BlockReason::TtyRead(_)) => task.unblock(),
+                // stopped tasks only wake up to be killed
+                Some(BlockReason::Stopped) if signal == Signal::Kill => task.unblock(),
+                _ => (),
+            }
+
+            // make sure a stop doesn't get undone by an older continue
+            if action == SignalAction::Stop {
+                task.pending_signals &= !Signal::Continue.mask();
+            }
+        },
+    }
+}
+
+/// sends a signal to every task in a thread group
+pub fn send_signal_to_group(tgid: usize, signal: Signal) -> Result<(), Errno> {
+    let mut found = false;
+
+    for task in unsafe { TASKS.iter_mut() }.filter(|task| task.tgid == tgid) {
+        send_signal(task, signal);
+        found = true;
+    }
+
+    if found {
+        Ok(())
+    } else {
+        Err(Errno::NoSuchProcess)
+    }
+}
+
+/// sends a signal to every task in a process group
+pub fn send_signal_to_pgroup(pgid: usize, signal: Signal) -> Result<(), Errno> {
+    let mut found = false;
+
+    for task in unsafe { TASKS.iter_mut() }.filter(|task| task.pgid == pgid) {
+        send_signal(task, signal);
+        found = true;
+    }
+
+    if found {
+        Ok(())
+    } else {
+        Err(Errno::NoSuchProcess)
+    }
+}
+
+/// applies the default action of any signals pending for the current task
+/// returns true if the current task was killed or stopped and can't be returned to
+pub fn handle_signals() -> bool {
+    let task =
+        match get_current_task_mut() {
+            Some(task) if !task.state.is_kernel_thread() => task,
+            _ => return false,
+        };
+
+    // stopped tasks keep their signals pending until they're continued (or killed)
+    if task.blocked_on == Some(BlockReason::Stopped) && task.pending_signals & Signal::Kill.mask() == 0 {
+        return false;
+    }
+
+    while task.pending_signals != 0 {
+        let num = task.pending_signals.trailing_zeros();
+        task.pending_signals &= !(1 << num);
+
+        let signal =
+            match Signal::try_from(num) {
+                Ok(signal) => signal,
+                Err(_) => continue,
+            };
+
+        match signal.default_action() {
+            SignalAction::Terminate => {
+                log!("task {} (pid {}) killed by {:?}", task.id, task.tgid, signal);
+
+                if let Err(msg) = kill_thread_group(unsafe { CURRENT_TASK }).and_then(|_| kill_task(unsafe { CURRENT_TASK })) {
+                    panic!("couldn't kill task: {}", msg);
+                }
+
+                return true;
+            },
+            SignalAction::Stop => {
+                debug!("task {} stopped by {:?}", task.id, signal);
+
+                task.block(BlockReason::Stopped, None);
+
+                return true;
+            },
+            _ => (),
+        }
+    }
+
+    false
+}
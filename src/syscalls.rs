@@ -1,5 +1,10 @@
 //! aspects of syscalls that we want to be platform independent
 
+use crate::errno::Errno;
+
+/// result of a syscall, errors are passed back to user mode as negative errno values
+pub type SyscallResult = Result<u32, Errno>;
+
 /// list of syscalls- we want this to be the same across all platforms
 #[repr(usize)]
 pub enum Syscalls {
@@ -8,4 +13,33 @@ pub enum Syscalls {
     Fork,
     Exit,
     GetPID,
+    ThreadCreate,
+    GetTID,
+    ExitGroup,
+    Futex,
+    Kill,
+    Open,
+    Close,
+    Read,
+    Write,
+    IOCtl,
+    SetPGID,
+    GetPGID,
+    Reboot,
+    PowerOff,
+}
+
+/// the reboot and power off syscalls only go through with these as their first two arguments, same as on linux,
+/// so a stray syscall number can't take the machine down
+pub const REBOOT_MAGIC1: u32 = 0xfee1dead;
+pub const REBOOT_MAGIC2: u32 = 672274793;
+
+/// operations for the futex syscall
+#[repr(usize)]
+pub enum FutexOp {
+    /// block until woken up if the futex word contains the expected value
+    Wait = 0,
+
+    /// wake up tasks waiting on the futex
+    Wake,
 }
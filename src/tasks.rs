@@ -1,12 +1,62 @@
 //! tasks and task switching
 
-use crate::arch::tasks::TaskState;
-use alloc::vec::Vec;
+use crate::{
+    arch::{
+        tasks::TaskState,
+        without_interrupts,
+    },
+    errno::Errno,
+    fs::ops::FileTable,
+    platform::irq::TICKS,
+};
+use alloc::{
+    boxed::Box,
+    rc::Rc,
+    vec::Vec,
+};
+use core::cell::RefCell;
 
 /// structure for task, contains task state, flags, etc
 pub struct Task {
     pub state: TaskState,
+
+    /// unique id of this task (the thread id)
     pub id: usize,
+
+    /// id of the thread group this task belongs to, which is the id of the task that created the group
+    /// this is what user mode sees as the process id
+    pub tgid: usize,
+
+    /// id of the process group this task belongs to, used for job control
+    pub pgid: usize,
+
+    /// files this task has open, shared between every thread in a thread group
+    pub files: Rc<RefCell<FileTable>>,
+
+    /// what this task is waiting on, blocked tasks aren't scheduled
+    pub blocked_on: Option<BlockReason>,
+
+    /// timer tick at which this task will be unblocked if nothing else wakes it up first
+    pub wake_at: Option<u64>,
+
+    /// set of signals that have been sent to this task but haven't been handled yet
+    pub pending_signals: u32,
+}
+
+/// what a blocked task is waiting on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockReason {
+    /// waiting on a futex, keyed by the physical address of the futex word
+    Futex(usize),
+
+    /// stopped by a signal until it's continued
+    Stopped,
+
+    /// waiting for input on the tty with the given index
+    TtyRead(usize),
+
+    /// the deferred work thread, waiting for interrupts to leave it something to do
+    DeferredWork,
 }
 
 impl Task {
@@ -25,8 +75,31 @@ impl Task {
 
         Self {
             state, id,
+            tgid: id,
+            pgid: id,
+            files: Rc::new(RefCell::new(Vec::new())),
+            blocked_on: None,
+            wake_at: None,
+            pending_signals: 0,
         }
     }
+
+    /// blocks this task until it's woken up or until the timer reaches the given tick
+    pub fn block(&mut self, reason: BlockReason, wake_at: Option<u64>) {
+        self.blocked_on = Some(reason);
+        self.wake_at = wake_at;
+    }
+
+    /// unblocks this task, allowing it to be scheduled again
+    pub fn unblock(&mut self) {
+        self.blocked_on = None;
+        self.wake_at = None;
+    }
+
+    /// checks whether this task can be scheduled
+    pub fn is_runnable(&self) -> bool {
+        self.blocked_on.is_none()
+    }
 }
 
 impl Default for Task {
@@ -50,6 +123,12 @@ pub static mut CURRENT_TERMINATED: bool = false;
 /// count of all task ids, we don't want duplicates
 pub static mut TOTAL_TASKS: usize = 0;
 
+/// whether the current task should be switched away from before returning to it
+pub static mut NEED_RESCHED: bool = false;
+
+/// pid of the task that runs when no other task can, if it's been spawned
+pub static mut IDLE_TASK: Option<usize> = None;
+
 /// get a reference to the next task to switch to
 pub fn get_next_task() -> Option<&'static Task> {
     unsafe {
@@ -80,18 +159,54 @@ pub fn get_current_task_mut() -> Option<&'static mut Task> {
     }
 }
 
-/// switch to the next task, making it the current task
+/// switch to the next runnable task, making it the current task
+/// if no other task can run, switches to the idle task
 pub fn switch_tasks() {
     unsafe {
-        CURRENT_TASK = (CURRENT_TASK + 1) % TASKS.len();
+        let len = TASKS.len();
+        let mut next = None;
+
+        // look through every task after the current one (ending with the current one) for one that can run
+        for i in 1..=len {
+            let idx = (CURRENT_TASK + i) % len;
+            let task = &mut TASKS[idx];
+
+            // wake up tasks whose timeout has passed
+            if !task.is_runnable() && task.wake_at.map_or(false, |tick| tick <= TICKS) {
+                task.unblock();
+            }
+
+            if task.is_runnable() && Some(task.id) != IDLE_TASK {
+                next = Some(idx);
+                break;
+            }
+        }
+
+        CURRENT_TASK = next
+            .or_else(|| pid_to_id(IDLE_TASK?))
+            .unwrap_or((CURRENT_TASK + 1) % len);
     }
 }
 
 /// add new task
 pub fn add_task(task: Task) {
-    unsafe {
+    // kernel threads can be preempted, so make sure the task list isn't switched on while it's being modified
+    without_interrupts(|| unsafe {
         TASKS.push(task);
-    }
+    });
+}
+
+/// spawns a kernel thread that runs the provided function in kernel mode, sharing the kernel's address space
+/// returns the pid of the new thread
+pub fn spawn_kernel_thread<F: FnOnce() + Send + 'static>(func: F) -> usize {
+    let task = Task::from_state(TaskState::new_kernel_thread(Box::new(func)));
+    let pid = task.id;
+
+    debug!("spawning kernel thread with pid {}", pid);
+
+    add_task(task);
+
+    pid
 }
 
 /// remove existing task
@@ -101,12 +216,80 @@ pub fn remove_task(id: usize) {
             TASKS.remove(id);
         }
         if id == CURRENT_TASK {
-            CURRENT_TASK = (CURRENT_TASK - 1) % TASKS.len();
+            // go back one task so that the next task switch lands on the task after the removed one
+            CURRENT_TASK = if TASKS.is_empty() { 0 } else { (CURRENT_TASK + TASKS.len() - 1) % TASKS.len() };
             CURRENT_TERMINATED = true;
+        } else if id < CURRENT_TASK {
+            // everything after the removed task was shifted down
+            CURRENT_TASK -= 1;
         }
     }
 }
 
+/// spawns the idle task, which halts the cpu whenever no other task can run
+pub fn spawn_idle_task() {
+    let pid = spawn_kernel_thread(|| loop {
+        unsafe { core::arch::asm!("sti; hlt"); }
+    });
+
+    unsafe { IDLE_TASK = Some(pid); }
+}
+
+/// blocks the given task on the futex with the given key if the futex word's value is what the task expects it to be,
+/// until it's woken up or until the timer reaches the given tick. fails with TryAgain if the value didn't match
+pub fn wait_futex(task: &mut Task, key: usize, value: u32, expected: u32, wake_at: Option<u64>) -> Result<(), Errno> {
+    if value != expected {
+        return Err(Errno::TryAgain);
+    }
+
+    task.block(BlockReason::Futex(key), wake_at);
+
+    Ok(())
+}
+
+/// wakes up to the given amount of tasks waiting on the futex with the given key, returns the amount of tasks woken
+/// the given function is called on every task before it's woken up
+pub fn wake_futex<F: FnMut(&mut Task)>(key: usize, count: usize, mut func: F) -> usize {
+    let mut woken = 0;
+
+    for task in unsafe { TASKS.iter_mut() } {
+        if woken >= count {
+            break;
+        }
+
+        if task.blocked_on == Some(BlockReason::Futex(key)) {
+            func(task);
+            task.unblock();
+            woken += 1;
+        }
+    }
+
+    // give the tasks we woke up a chance to run
+    if woken > 0 {
+        unsafe { NEED_RESCHED = true; }
+    }
+
+    woken
+}
+
+/// wakes up every task blocked for the given reason, returns the amount of tasks woken
+pub fn wake_blocked(reason: BlockReason) -> usize {
+    let mut woken = 0;
+
+    for task in unsafe { TASKS.iter_mut() } {
+        if task.blocked_on == Some(reason) {
+            task.unblock();
+            woken += 1;
+        }
+    }
+
+    if woken > 0 {
+        unsafe { NEED_RESCHED = true; }
+    }
+
+    woken
+}
+
 /// get reference to existing task
 pub fn get_task(id: usize) -> Option<&'static Task> {
     unsafe {
@@ -1,16 +1,62 @@
 //! tests
 
-use core::arch::asm;
+use core::{
+    arch::asm,
+    fmt::Write,
+};
 use crate::{
-    console::{ColorCode, get_console},
+    arch::{
+        LINKED_BASE,
+        without_interrupts,
+        cpu::{CpuFeatures, CpuInfo},
+        fpu,
+        ints::SyscallRegisters,
+        paging::{PAGE_DIR, is_mapped, virt_to_phys},
+        syscalls::{dispatch, power_off, reboot},
+        tasks::{DEAD_STACK, create_thread, fork_task, kill_task, kill_thread_group},
+    },
+    cmdline::{parse_bool, parse_int, parse_params},
+    console::{Color, ColorCode, active_console, get_console, get_virtual_console, switch_console},
+    deferred::{defer, pending, run_pending},
     fs::{
+        kernfs::StaticFile,
+        ops::{open, close},
         tree::{
             File, Directory, LockType,
             get_file_from_path, get_directory_from_path,
         },
-        vfs::Permissions,
+        vfs::{Permissions, ROOT_DIR},
     },
     errno::Errno,
+    gdb::{checksum, parse_hex, parse_range},
+    input::{KeyCode, Modifiers, US_KEYMAP},
+    logging::{Level, LOG_BUFFER_SIZE, MAX_DEFERRED_MESSAGE, PENDING_BUFFER_SIZE, Writer, module_level, read_log, set_module_level},
+    monitor::{Channel, Command, DEFAULT_DUMP_SIZE, Monitor, ParseError, parse_command},
+    platform::{
+        acpi::parse_s5,
+        apic::{IoApicInfo, IsaRoute, parse_madt},
+        framebuffer::FONT_DATA,
+        irq::{register_irq, unregister_irq, is_irq_masked, TIMER_IRQ},
+        keyboard::ScancodeDecoder,
+        serial::{DEFAULT_BAUD, baud_termios, get_port, termios_baud},
+        vga::{TextMode, font_from_psf},
+    },
+    tasks::{
+        CURRENT_TASK, CURRENT_TERMINATED, NEED_RESCHED, TASKS,
+        Task,
+        add_task, get_task, pid_to_id, remove_task, spawn_kernel_thread, wait_futex, wake_futex,
+    },
+    syscalls::{REBOOT_MAGIC1, REBOOT_MAGIC2, Syscalls},
+    tty::{
+        Tty, TtyDriver, Termios, ControlFlags, LocalFlags,
+        B9600, B115200,
+        get_tty,
+    },
+    util::{
+        psf::Font,
+        ring::RingBuffer,
+        symbols::SymbolTable,
+    },
 };
 use alloc::{
     boxed::Box,
@@ -67,6 +113,531 @@ fn vga_partial() {
     }
 }
 
+/// make sure every text mode's registers describe a screen of the size the mode says it is
+#[test_case]
+fn vga_text_modes() {
+    for mode in TextMode::ALL {
+        let registers = mode.registers();
+        let crtc = &registers.crtc;
+        let char_width = if registers.clocking_mode & 1 != 0 { 8 } else { 9 };
+
+        assert!(TextMode::from_name(&alloc::format!("{}x{}", mode.width(), mode.height())) == Some(mode));
+
+        // horizontal display end, offset (in words per line) and maximum scan line
+        assert!(crtc[0x01] as u16 + 1 == mode.width());
+        assert!(crtc[0x13] as u16 * 2 == mode.width());
+        assert!(crtc[0x09] & 0x1f == mode.char_height() - 1);
+
+        // vertical display end, with bits 8 and 9 in the overflow register
+        let vertical_end = crtc[0x12] as u16 | ((crtc[0x07] as u16 >> 1) & 1) << 8 | ((crtc[0x07] as u16 >> 6) & 1) << 9;
+        assert!(vertical_end + 1 == mode.height() * mode.char_height() as u16);
+
+        // 720 pixels wide either way
+        assert!(mode.width() * char_width == 720);
+    }
+
+    assert!(TextMode::from_name("80x24").is_none());
+    assert!(TextMode::from_name("80").is_none());
+}
+
+/// make sure PSF fonts are padded out to the text mode's character height
+#[test_case]
+fn vga_psf_font() {
+    let psf = Font::parse(FONT_DATA).unwrap();
+    let font = font_from_psf(&psf, 16).unwrap();
+
+    assert!(font.len() == 256 * 16);
+    assert!(font[b'A' as usize * 16..][..psf.height] == *psf.glyph(b'A' as usize));
+    assert!(font[b'A' as usize * 16..][psf.height..16].iter().all(|row| *row == 0));
+
+    assert!(font_from_psf(&psf, 8).is_err());
+}
+
+/// make sure escape sequences are parsed and don't get printed
+#[test_case]
+fn ansi_escapes() {
+    let console = get_console().unwrap();
+    let old_color = console.get_color();
+
+    console.puts("\x1b[s\x1b[1;31;44mOwO\x1b[0m \x1b[2K\x1b[10;5H\x1b[3A\x1b[u\x1b[?25l\x1b[999;999H\x1b[J\n");
+    assert!(console.get_color() == ColorCode::default());
+
+    console.puts("\x1b[92;100m");
+    assert!(console.get_color() == ColorCode { foreground: Color::LightGreen, background: Color::DarkGray });
+
+    console.set_color(old_color);
+}
+
+/// make sure lines scrolled off the screen end up in the scrollback buffer
+#[test_case]
+fn scrollback() {
+    let console = get_console().unwrap();
+
+    console.puts("scrollback test line\n");
+    for _i in 0..100 {
+        console.puts("\n");
+    }
+
+    console.scroll_view_back(1000);
+    console.scroll_view_forward(10);
+
+    let mut contents: Vec<u8> = Vec::new();
+    console.dump(&mut |b| contents.push(b));
+    assert!(contents.windows(20).any(|w| w == b"scrollback test line"));
+
+    // output should snap the view back
+    console.puts("\n");
+}
+
+/// make sure output to a virtual console stays on that console, and switching between them works
+#[test_case]
+fn virtual_consoles() {
+    let console = get_virtual_console(1).unwrap();
+    console.puts("virtual console test\n");
+
+    let mut contents: Vec<u8> = Vec::new();
+    get_console().unwrap().dump(&mut |b| contents.push(b));
+    assert!(!contents.windows(20).any(|w| w == b"virtual console test"));
+
+    switch_console(1).unwrap();
+    assert!(active_console() == 1);
+    assert!(switch_console(100).is_err());
+    switch_console(0).unwrap();
+
+    let mut contents: Vec<u8> = Vec::new();
+    console.dump(&mut |b| contents.push(b));
+    assert!(contents.windows(20).any(|w| w == b"virtual console test"));
+}
+
+/// make sure command lines are split up properly
+#[test_case]
+fn cmdline_parsing() {
+    let params: Vec<_> = parse_params("  /boot/kernel console=ttyS0 quiet  init=\"/bin/sh -l\" loglevel=0x7").collect();
+
+    assert!(params == [
+        ("/boot/kernel", None),
+        ("console", Some("ttyS0")),
+        ("quiet", None),
+        ("init", Some("/bin/sh -l")),
+        ("loglevel", Some("0x7")),
+    ]);
+
+    assert!(parse_int("0x7") == Some(7) && parse_int("100") == Some(100) && parse_int("abc").is_none());
+    assert!(parse_bool("") == Some(true) && parse_bool("off") == Some(false) && parse_bool("maybe").is_none());
+}
+
+/// make sure log levels, filters and the log buffer work
+#[test_case]
+fn logging() {
+    assert!(Level::parse("debug") == Some(Level::Debug));
+    assert!(Level::parse("7") == Some(Level::Debug));
+    assert!(Level::parse("loud").is_none());
+
+    set_module_level("log_test", Level::Trace).unwrap();
+    set_module_level("log_test::quiet", Level::Error).unwrap();
+    assert!(module_level("ockernel::log_test::inner") == Level::Trace);
+    assert!(module_level("ockernel::log_test::quiet::inner") == Level::Error);
+    assert!(module_level("ockernel::log_testing") == module_level("ockernel"));
+
+    log!("log buffer test message");
+
+    let mut contents = vec![0; LOG_BUFFER_SIZE];
+    let len = read_log(&mut contents, 0);
+    assert!(contents[..len].windows(23).any(|w| w == b"log buffer test message"));
+}
+
+/// make sure messages logged in the middle of another message are held back and written out afterwards instead of lost
+#[test_case]
+fn nested_logging() {
+    {
+        let mut outer = Writer::get(Level::Info, module_path!());
+        let _ = write!(outer, "outer log message");
+
+        // this is what happens when an interrupt handler logs something
+        log!("nested log message");
+    }
+
+    let mut contents = vec![0; LOG_BUFFER_SIZE];
+    let len = read_log(&mut contents, 0);
+    let contents = &contents[..len];
+
+    let outer = contents.windows(17).rposition(|w| w == b"outer log message").unwrap();
+    let nested = contents.windows(18).rposition(|w| w == b"nested log message").unwrap();
+    assert!(nested > outer);
+}
+
+/// make sure held back messages that don't fit in the pending buffer or are too long to hold back are written out anyway
+#[test_case]
+fn overflowing_logging() {
+    // more messages than fit in the pending buffer, but few enough that the log buffer keeps them all
+    let count = PENDING_BUFFER_SIZE / 40;
+    let long = alloc::format!("long held back log message {:-<1$}", "", MAX_DEFERRED_MESSAGE);
+
+    {
+        let _outer = Writer::get(Level::Info, module_path!());
+
+        log!("{}", long);
+
+        for i in 0..count {
+            log!("held back log message {:04}", i);
+        }
+    }
+
+    let mut contents = vec![0; LOG_BUFFER_SIZE];
+    let len = read_log(&mut contents, 0);
+    let contents = &contents[..len];
+
+    let contains = |message: &str| contents.windows(message.len()).any(|w| w == message.as_bytes());
+
+    assert!(contains(&alloc::format!("{}\n", long)));
+    for i in 0..count {
+        assert!(contains(&alloc::format!("held back log message {:04}\n", i)));
+    }
+}
+
+/// make sure PSF fonts are parsed properly
+#[test_case]
+fn psf_font() {
+    // PSF1 header, 256 glyphs 2 pixels tall
+    let mut data = vec![0x36, 0x04, 0, 2];
+    for i in 0..256 {
+        data.push(i as u8);
+        data.push(0x80);
+    }
+
+    let font = Font::parse(&data).unwrap();
+    assert!(font.width == 8 && font.height == 2 && font.num_glyphs == 256);
+    assert!(font.glyph(b'A' as usize) == [b'A', 0x80]);
+    assert!(font.pixel(font.glyph(0), 0, 1));
+    assert!(!font.pixel(font.glyph(0), 1, 1));
+
+    assert!(Font::parse(&data[..100]).is_err());
+    assert!(Font::parse(b"not a font").is_err());
+}
+
+/// make sure gdb packets are checked and parsed properly
+#[test_case]
+fn gdb_packets() {
+    assert!(checksum(b"OK") == 0x9a);
+    assert!(checksum(b"") == 0);
+    assert!(parse_hex(b"c0100000") == Some(0xc0100000));
+    assert!(parse_hex(b"").is_none() && parse_hex(b"xyz").is_none() && parse_hex(b"123456789").is_none());
+    assert!(parse_range(b"c0101000,40") == Some((0xc0101000, 0x40)));
+    assert!(parse_range(b"c0101000").is_none());
+}
+
+/// make sure drivers can claim and release IRQ lines, and lines are only unmasked while they're claimed
+#[test_case]
+fn irq_registration() {
+    fn handler(_irq: u8) {}
+    fn other_handler(_irq: u8) {}
+
+    // nothing's on IRQ 5 on the machines we test on
+    assert!(is_irq_masked(5));
+
+    register_irq(5, "test", handler).unwrap();
+    register_irq(5, "test", other_handler).unwrap();
+    assert!(!is_irq_masked(5));
+
+    unregister_irq(5, handler).unwrap();
+    assert!(!is_irq_masked(5));
+    unregister_irq(5, other_handler).unwrap();
+    assert!(is_irq_masked(5));
+
+    assert!(unregister_irq(5, handler).is_err());
+    assert!(register_irq(TIMER_IRQ, "test", handler).is_err());
+    assert!(register_irq(16, "test", handler).is_err());
+}
+
+/// make sure deferred work runs in order and a full queue turns work away
+#[test_case]
+fn deferred_work() {
+    static mut ORDER: usize = 0;
+    static mut COUNT: usize = 0;
+
+    fn work(arg: usize) {
+        unsafe { ORDER = ORDER * 10 + arg; }
+    }
+
+    fn count(_arg: usize) {
+        unsafe { COUNT += 1; }
+    }
+
+    defer("test", work, 1).unwrap();
+    defer("test", work, 2).unwrap();
+    defer("test", work, 3).unwrap();
+
+    // the queue is drained a few items per interrupt
+    while pending() > 0 {
+        without_interrupts(|| unsafe { run_pending() });
+    }
+
+    assert!(unsafe { ORDER } == 123);
+
+    let mut queued = 0;
+    while defer("test", count, 0).is_ok() {
+        queued += 1;
+    }
+
+    assert!(queued > 0 && pending() == queued);
+
+    while pending() > 0 {
+        without_interrupts(|| unsafe { run_pending() });
+    }
+
+    assert!(unsafe { COUNT } == queued);
+}
+
+/// make sure an interrupt that comes in while work is running doesn't start on the next item
+#[test_case]
+fn deferred_work_nesting() {
+    static mut NESTED_RAN_SECOND: bool = false;
+    static mut SECOND_RAN: bool = false;
+
+    fn first(_arg: usize) {
+        // what an interrupt does on its way out
+        without_interrupts(|| unsafe { run_pending() });
+        unsafe { NESTED_RAN_SECOND = SECOND_RAN; }
+    }
+
+    fn second(_arg: usize) {
+        unsafe { SECOND_RAN = true; }
+    }
+
+    defer("test", first, 0).unwrap();
+    defer("test", second, 0).unwrap();
+
+    while pending() > 0 {
+        without_interrupts(|| unsafe { run_pending() });
+    }
+
+    assert!(unsafe { !NESTED_RAN_SECOND && SECOND_RAN });
+}
+
+/// make sure reboot and power off turn away callers that don't give the magic numbers
+#[test_case]
+fn reboot_magic() {
+    let mut regs = SyscallRegisters::default();
+
+    assert!(matches!(reboot(&mut regs, [0; 6]), Err(Errno::InvalidArgument)));
+    assert!(matches!(power_off(&mut regs, [REBOOT_MAGIC1, 0, 0, 0, 0, 0]), Err(Errno::InvalidArgument)));
+    assert!(matches!(power_off(&mut regs, [REBOOT_MAGIC2, REBOOT_MAGIC1, 0, 0, 0, 0]), Err(Errno::InvalidArgument)));
+}
+
+/// make sure the S5 sleep types are found in the DSDT, and references to \_S5 aren't mistaken for its definition
+#[test_case]
+fn s5_parsing() {
+    // Name (_S5, Package (0x04) { Zero, Zero, Zero, Zero }), like QEMU has
+    assert!(parse_s5(b"\x10\x08_S5_\x12\x06\x04\x00\x00\x00\x00") == Some((0, 0)));
+
+    // a reference first, then Name (\_S5, Package (0x02) { 0x05, One }) with a long package length
+    assert!(parse_s5(b"\x70_S5_\x00\x08\\_S5_\x12\x40\x00\x02\x0a\x05\x01") == Some((5, 1)));
+
+    assert!(parse_s5(b"\x70_S5_\x00").is_none());
+    assert!(parse_s5(b"\x08_S5_\x12\x06\x04").is_none());
+}
+
+/// make sure cpu info is laid out like linux's /proc/cpuinfo, and only lists features the cpu has
+#[test_case]
+fn cpuinfo_formatting() {
+    let mut info = CpuInfo {
+        vendor: *b"GenuineIntel",
+        brand: [0; 48],
+        family: 6,
+        model: 8,
+        stepping: 3,
+        features: CpuFeatures::Fpu | CpuFeatures::Pse | CpuFeatures::Pge | CpuFeatures::Nx,
+    };
+    info.brand[..8].copy_from_slice(b"  Tester");
+
+    let mut string = String::new();
+    write!(string, "{}", info).unwrap();
+
+    assert!(string.contains("vendor_id\t: GenuineIntel\n"));
+    assert!(string.contains("cpu family\t: 6\n"));
+    assert!(string.contains("model name\t: Tester\n"));
+    assert!(string.contains("flags\t\t: fpu pse pge nx\n"));
+
+    info.vendor = [0; 12];
+    info.features = CpuFeatures::None;
+    string.clear();
+    write!(string, "{}", info).unwrap();
+
+    assert!(string.contains("vendor_id\t: unknown\n"));
+    assert!(string.contains("flags\t\t:\n"));
+}
+
+/// make sure kernel memory can still be looked up when it's mapped with a large page
+#[test_case]
+fn large_page_lookups() {
+    let addr = LINKED_BASE + 0x1234;
+
+    assert!(virt_to_phys(addr) == Some(0x1234));
+    assert!(is_mapped(addr));
+
+    let dir = unsafe { PAGE_DIR.as_mut().unwrap() };
+
+    if dir.is_large_page(addr as u32) {
+        // there's no page table entry to change, only the directory entry
+        assert!(dir.get_page(addr as u32, true).is_none());
+        assert!(dir.get_entry(addr as u32).map_or(false, |entry| entry.get_address() == 0x1000));
+    }
+}
+
+/// make sure read only files read from where they're asked, and stop at the end
+#[test_case]
+fn static_file_reads() {
+    let mut file = StaticFile::new("test", b"hello world".to_vec());
+    let mut buf = [0; 8];
+
+    assert!(file.get_size() == 11);
+    assert!(matches!(file.read_at(&mut buf, 6), Ok(5)));
+    assert!(&buf[..5] == b"world");
+    assert!(matches!(file.read_at(&mut buf, 11), Ok(0)));
+    assert!(file.can_read_at(1, 10) && !file.can_read_at(1, 11));
+    assert!(matches!(file.write_at(b"no", 0), Err(Errno::NotSupported)));
+}
+
+/// make sure ISA IRQ overrides in the MADT move IRQs to the right pins
+#[test_case]
+fn madt_parsing() {
+    let body = [
+        0x00, 0x00, 0xe0, 0xfe, // local APIC address
+        0x01, 0x00, 0x00, 0x00, // flags: there's PICs too
+        0, 8, 0, 0, 1, 0, 0, 0, // local APIC
+        1, 12, 1, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0, // I/O APIC 1 @ 0xfec00000, from GSI 0
+        2, 10, 0, 0, 2, 0, 0, 0, 0x00, 0x00, // IRQ 0 on GSI 2
+        2, 10, 0, 9, 9, 0, 0, 0, 0x0d, 0x00, // IRQ 9 active high, level triggered
+    ];
+
+    let madt = parse_madt(&body).unwrap();
+
+    assert!(madt.has_pics);
+    assert!(madt.io_apics[0] == Some(IoApicInfo { id: 1, addr: 0xfec00000, gsi_base: 0 }));
+    assert!(madt.io_apics[1].is_none());
+
+    assert!(madt.isa_routes[0] == Some(IsaRoute { gsi: 2, active_low: false, level_triggered: false }));
+    assert!(madt.isa_routes[1] == Some(IsaRoute { gsi: 1, active_low: false, level_triggered: false }));
+    assert!(madt.isa_routes[2].is_none());
+    assert!(madt.isa_routes[9] == Some(IsaRoute { gsi: 9, active_low: false, level_triggered: true }));
+
+    // an entry running off the end
+    assert!(parse_madt(&body[..body.len() - 1]).is_none());
+}
+
+/// make sure addresses are symbolized against the right symbol
+#[test_case]
+fn symbol_lookup() {
+    let table = SymbolTable::new(b"c0101000 kmain\nc0101200 <ockernel::tty::Tty as core::fmt::Write>::write_str\nc0101400 halt\n\0garbage");
+
+    assert!(table.lookup(0xc0100fff).is_none());
+    assert!(table.lookup(0xc0101000) == Some(("kmain", 0)));
+    assert!(table.lookup(0xc0101234) == Some(("<ockernel::tty::Tty as core::fmt::Write>::write_str", 0x34)));
+    assert!(table.lookup(0xc0105000) == Some(("halt", 0x3c00)));
+    assert!(table.symbols().count() == 3);
+    assert!(SymbolTable::new(&[0; 16]).is_empty());
+}
+
+/// test ring buffer wrapping around and filling up
+#[test_case]
+fn ring_buffer() {
+    let mut ring: RingBuffer<u8, 4> = RingBuffer::new(0);
+
+    for i in 0..10 {
+        assert!(ring.push(i).is_ok());
+        assert!(ring.push(i + 1).is_ok());
+        assert!(ring.pop() == Some(i));
+        assert!(ring.pop() == Some(i + 1));
+    }
+
+    for i in 0..4 {
+        assert!(ring.push(i).is_ok());
+    }
+    assert!(ring.is_full());
+    assert!(ring.push(4) == Err(4));
+    assert!(ring.peek() == Some(0));
+    assert!(ring.len() == 4);
+}
+
+/// test decoding scancodes and translating them with a keymap
+#[test_case]
+fn keyboard_decode() {
+    let mut decoder = ScancodeDecoder::new();
+
+    assert!(decoder.decode(0x1e) == Some((KeyCode::A, true)));
+    assert!(decoder.decode(0x9e) == Some((KeyCode::A, false)));
+    assert!(decoder.decode(0xe0).is_none());
+    assert!(decoder.decode(0x48) == Some((KeyCode::Up, true)));
+    assert!(decoder.decode(0xe0).is_none());
+    assert!(decoder.decode(0xc8) == Some((KeyCode::Up, false)));
+
+    // print screen's fake shift
+    assert!(decoder.decode(0xe0).is_none());
+    assert!(decoder.decode(0x2a).is_none());
+
+    for byte in [0xe1, 0x1d, 0x45, 0xe1, 0x9d] {
+        assert!(decoder.decode(byte).is_none());
+    }
+    assert!(decoder.decode(0xc5) == Some((KeyCode::Pause, true)));
+
+    assert!(US_KEYMAP.translate(KeyCode::A, Modifiers::None) == Some(b'a'));
+    assert!(US_KEYMAP.translate(KeyCode::A, Modifiers::LeftShift) == Some(b'A'));
+    assert!(US_KEYMAP.translate(KeyCode::A, Modifiers::CapsLock) == Some(b'A'));
+    assert!(US_KEYMAP.translate(KeyCode::A, Modifiers::CapsLock | Modifiers::RightShift) == Some(b'a'));
+    assert!(US_KEYMAP.translate(KeyCode::Key1, Modifiers::CapsLock) == Some(b'1'));
+    assert!(US_KEYMAP.translate(KeyCode::C, Modifiers::LeftControl) == Some(0x03));
+    assert!(US_KEYMAP.translate(KeyCode::Keypad1, Modifiers::None).is_none());
+    assert!(US_KEYMAP.translate(KeyCode::Keypad1, Modifiers::NumLock) == Some(b'1'));
+    assert!(US_KEYMAP.translate(KeyCode::Up, Modifiers::None).is_none());
+}
+
+/// everything written to TestTtyDriver
+static mut TTY_OUTPUT: Vec<u8> = Vec::new();
+
+/// tty driver that saves its output
+pub struct TestTtyDriver;
+
+impl TtyDriver for TestTtyDriver {
+    fn write(&mut self, bytes: &[u8]) {
+        unsafe { TTY_OUTPUT.extend_from_slice(bytes); }
+    }
+}
+
+/// test line editing, echo and raw mode in the line discipline
+#[test_case]
+fn tty_line_discipline() {
+    let mut tty = Tty::new(Box::new(TestTtyDriver));
+    let mut buf = [0; 64];
+
+    // nothing is readable until the line is finished
+    for &c in b"hellp\x7fo wrld\x17world" {
+        assert!(!tty.input(c));
+    }
+    assert!(matches!(tty.read(&mut buf), Err(Errno::OperationWouldBlock)));
+
+    assert!(tty.input(b'\r'));
+    assert!(matches!(tty.read(&mut buf), Ok(12)));
+    assert!(&buf[..12] == b"hello world\n");
+
+    // echoed output, with erased characters rubbed out and newlines turned into \r\n
+    assert!(unsafe { TTY_OUTPUT.ends_with(b"world\r\n") });
+    assert!(unsafe { TTY_OUTPUT.windows(3).any(|w| w == b"\x08 \x08") });
+
+    // kill the line, then end of file on an empty line
+    for &c in b"junk\x15" {
+        tty.input(c);
+    }
+    assert!(tty.input(0x04));
+    assert!(matches!(tty.read(&mut buf), Ok(0)));
+
+    // raw mode makes everything readable immediately
+    tty.termios.local_flags &= !(LocalFlags::Canonical | LocalFlags::Echo);
+    assert!(tty.input(b'x'));
+    assert!(tty.input(0x7f));
+    assert!(matches!(tty.read(&mut buf), Ok(2)));
+    assert!(&buf[..2] == b"x\x7f");
+}
+
 /// test global allocator and vec
 #[test_case]
 fn vec() {
@@ -293,3 +864,343 @@ fn file_read() {
 
     assert!(string == "this is testfile6");
 }
+
+/// make sure kernel threads are reaped once they exit, and their stacks are freed once nothing runs on them
+#[test_case]
+fn kernel_thread_reaped() {
+    let pids = [spawn_kernel_thread(|| {}), spawn_kernel_thread(|| {}), spawn_kernel_thread(|| {})];
+    let stacks: Vec<usize> = pids.iter().map(|pid| {
+        let task = get_task(pid_to_id(*pid).unwrap()).unwrap();
+        assert!(task.state.is_kernel_thread());
+        task.state.kernel_stack.as_ref().unwrap().as_ptr() as usize
+    }).collect();
+
+    let dead_stack = || unsafe { DEAD_STACK.as_ref().map(|stack| stack.as_ptr() as usize) };
+
+    // the last thread exits while it's running, so its stack has to stick around
+    unsafe { CURRENT_TASK = pid_to_id(pids[2]).unwrap(); }
+    kill_task(pid_to_id(pids[2]).unwrap()).unwrap();
+    assert!(pid_to_id(pids[2]).is_none());
+    assert!(dead_stack() == Some(stacks[2]));
+
+    // once the next one exits the last one's stack is freed, and this one's is kept instead
+    unsafe { CURRENT_TASK = pid_to_id(pids[1]).unwrap(); }
+    kill_task(pid_to_id(pids[1]).unwrap()).unwrap();
+    assert!(pid_to_id(pids[1]).is_none());
+    assert!(dead_stack() == Some(stacks[1]));
+
+    // a thread that isn't running has its stack freed along with it
+    unsafe { CURRENT_TASK = usize::MAX; }
+    kill_task(pid_to_id(pids[0]).unwrap()).unwrap();
+    assert!(pid_to_id(pids[0]).is_none());
+    assert!(dead_stack() == Some(stacks[1]));
+
+    unsafe {
+        DEAD_STACK = None;
+        CURRENT_TASK = 0;
+        CURRENT_TERMINATED = false;
+    }
+}
+
+/// make sure threads share their group's address space, and exit_group takes down the whole group
+#[test_case]
+fn thread_group() {
+    // pretend we're somewhere else so none of these are the current task
+    unsafe { CURRENT_TASK = usize::MAX; }
+
+    let mut leader = Task::new();
+    leader.state.registers.cs = 0x1b; // user mode
+    let leader_pid = leader.id;
+    add_task(leader);
+
+    let other = Task::new();
+    let other_pid = other.id;
+    add_task(other);
+
+    let leader_id = pid_to_id(leader_pid).unwrap();
+    let thread_pids = [
+        create_thread(leader_id, 0x1000, 0x2000, 1).unwrap().id,
+        create_thread(leader_id, 0x1000, 0x3000, 2).unwrap().id,
+    ];
+
+    unsafe {
+        let leader = &TASKS[pid_to_id(leader_pid).unwrap()];
+
+        for pid in thread_pids {
+            let thread = &TASKS[pid_to_id(pid).unwrap()];
+
+            assert!(thread.id != leader.id);
+            assert!(thread.tgid == leader_pid);
+            assert!(alloc::rc::Rc::ptr_eq(&thread.state.pages, &leader.state.pages));
+            assert!(alloc::rc::Rc::ptr_eq(&thread.files, &leader.files));
+        }
+
+        assert!(TASKS[pid_to_id(other_pid).unwrap()].tgid == other_pid);
+    }
+
+    // exit_group kills everything else in the group, then the caller
+    kill_thread_group(pid_to_id(thread_pids[0]).unwrap()).unwrap();
+    assert!(pid_to_id(leader_pid).is_none());
+    assert!(pid_to_id(thread_pids[1]).is_none());
+    kill_task(pid_to_id(thread_pids[0]).unwrap()).unwrap();
+    assert!(pid_to_id(thread_pids[0]).is_none());
+
+    // tasks outside of the group are left alone
+    assert!(pid_to_id(other_pid).is_some());
+    kill_task(pid_to_id(other_pid).unwrap()).unwrap();
+
+    unsafe { CURRENT_TASK = 0; }
+}
+
+/// make sure a futex wait blocks, and a wake only wakes tasks waiting on the same futex
+#[test_case]
+fn futex_wait_wake() {
+    let mut task = Task::new();
+    let pid = task.id;
+
+    assert!(wait_futex(&mut task, 0x1000, 1, 1, None).is_ok());
+    assert!(!task.is_runnable());
+
+    task.state.set_syscall_result(u32::MAX);
+
+    unsafe {
+        // waking and removing tasks fiddles with the scheduler's state, which isn't ours to change
+        let (current, need_resched) = (CURRENT_TASK, NEED_RESCHED);
+
+        add_task(task);
+
+        assert!(wake_futex(0x2000, 1, |_| ()) == 0);
+        assert!(wake_futex(0x1000, 1, |task| task.state.set_syscall_result(0)) == 1);
+
+        let task = get_task(pid_to_id(pid).unwrap()).unwrap();
+        assert!(task.is_runnable());
+        assert!(task.state.registers.eax == 0);
+
+        assert!(wake_futex(0x1000, 1, |_| ()) == 0);
+
+        remove_task(pid_to_id(pid).unwrap());
+
+        CURRENT_TASK = current;
+        NEED_RESCHED = need_resched;
+    }
+}
+
+/// make sure a futex wait doesn't block if the futex word doesn't have the expected value
+#[test_case]
+fn futex_value_mismatch() {
+    let mut task = Task::new();
+
+    assert!(matches!(wait_futex(&mut task, 0x1000, 1, 2, None), Err(Errno::TryAgain)));
+    assert!(task.is_runnable());
+    assert!(task.blocked_on.is_none());
+}
+
+/// loads an integer onto the FPU stack
+fn fpu_push(value: u32) {
+    unsafe { asm!("fild dword ptr [{0}]", in(reg) &value as *const u32); }
+}
+
+/// pops an integer off of the FPU stack
+fn fpu_pop() -> u32 {
+    let mut value = 0_u32;
+    unsafe { asm!("fistp dword ptr [{0}]", in(reg) &mut value as *mut u32); }
+    value
+}
+
+/// make sure FPU state follows tasks around: it's saved when another task takes over the FPU,
+/// restored on the next device not available fault, and synced before being copied on fork
+#[test_case]
+fn fpu_switching() {
+    let switch_to = |pid| {
+        unsafe { CURRENT_TASK = pid_to_id(pid).unwrap(); }
+        fpu::switched_to(pid);
+    };
+
+    let first = Task::new();
+    let first_pid = first.id;
+    add_task(first);
+
+    let second = Task::new();
+    let second_pid = second.id;
+    add_task(second);
+
+    // the first task takes the FPU over
+    switch_to(first_pid);
+    fpu_push(1234);
+
+    // once the second task uses the FPU, the first task's state has to be saved
+    switch_to(second_pid);
+    fpu_push(5678);
+    assert!(get_task(pid_to_id(first_pid).unwrap()).unwrap().state.fpu_state.is_some());
+
+    // switching back faults and restores it
+    switch_to(first_pid);
+    assert!(fpu_pop() == 1234);
+
+    // the child gets what's in the FPU right now, not the stale saved state, and the parent keeps it too
+    fpu_push(4321);
+    let child_pid = fork_task(pid_to_id(first_pid).unwrap()).unwrap().id;
+    assert!(fpu_pop() == 4321);
+
+    switch_to(second_pid);
+    assert!(fpu_pop() == 5678);
+
+    switch_to(child_pid);
+    assert!(fpu_pop() == 4321);
+
+    unsafe { CURRENT_TASK = usize::MAX; }
+
+    for pid in [first_pid, second_pid, child_pid] {
+        kill_task(pid_to_id(pid).unwrap()).unwrap();
+    }
+
+    unsafe { CURRENT_TASK = 0; }
+}
+
+/// make sure unknown syscalls fail with ENOSYS, and that errors come back in eax as negative errno values
+#[test_case]
+fn syscall_errors() {
+    let call = |num: Syscalls, arg: u32| {
+        let mut regs = SyscallRegisters { eax: num as u32, ebx: arg, ..Default::default() };
+        dispatch(&mut regs);
+        regs.eax
+    };
+    let errno = |err: Errno| (-(err.number() as i32)) as u32;
+
+    let mut regs = SyscallRegisters { eax: 0xffff, ..Default::default() };
+    dispatch(&mut regs);
+    assert!(regs.eax == errno(Errno::FuncNotSupported));
+
+    assert!(call(Syscalls::IsComputerOn, 0) == 1);
+
+    // futex words have to be aligned, and strings can't be in kernel memory
+    assert!(call(Syscalls::Futex, 0x1001) == errno(Errno::BadAddress));
+    assert!(call(Syscalls::TestLog, LINKED_BASE as u32) == errno(Errno::BadAddress));
+}
+
+/// make sure duplicated file descriptors share their open file's offset, and the file stays open until they're all closed
+#[test_case]
+fn file_descriptor_sharing() {
+    let root = unsafe { ROOT_DIR.as_mut().unwrap() };
+    root.get_files_mut().push(Box::new(TestFile::new("fdtest", "this is fdtest")));
+
+    let mut first = open("fdtest").unwrap();
+    let mut second = first.duplicate().unwrap();
+
+    let mut buf = [0; 4];
+
+    assert!(matches!(first.read(&mut buf), Ok(4)));
+    assert!(&buf == b"this");
+
+    assert!(matches!(second.read(&mut buf), Ok(4)));
+    assert!(&buf == b" is ");
+
+    close(&mut first);
+
+    assert!(matches!(second.read_at(&mut buf, 0), Ok(4)));
+    assert!(matches!(first.read_at(&mut buf, 0), Err(Errno::BadFile)));
+
+    close(&mut second);
+    root.get_files_mut().retain(|file| file.get_name() != "fdtest");
+}
+
+/// make sure what a serial port receives makes it to its tty, by looping what it sends right back into it
+#[test_case]
+fn serial_receive() {
+    let port = get_port(0).expect("no COM1");
+    let tty = get_tty(port.tty.expect("COM1 has no tty")).unwrap();
+
+    // raw mode, and don't echo anything back out
+    let termios = tty.termios;
+    tty.termios.local_flags = tty.termios.local_flags & !(LocalFlags::Canonical | LocalFlags::Echo | LocalFlags::Signals);
+    tty.flush_input();
+
+    without_interrupts(|| {
+        port.flush();
+        port.set_loopback(true);
+        port.write(b"owo");
+        port.receive(0);
+        port.set_loopback(false);
+    });
+
+    // received bytes only get to the tty once the deferred work runs
+    assert!(pending() > 0);
+    without_interrupts(|| unsafe { run_pending() });
+
+    let mut buf = [0; 4];
+    assert!(matches!(tty.read(&mut buf), Ok(3)));
+    assert!(&buf[..3] == b"owo");
+
+    tty.termios = termios;
+}
+
+/// make sure termios baud rates and actual baud rates convert back and forth, and baud rates the UART can't do are clamped
+#[test_case]
+fn serial_baud_rates() {
+    let mut termios = Termios::default();
+
+    for baud in [9600, 19200, 38400, 57600, 115200] {
+        termios.control_flags = (termios.control_flags & !ControlFlags::BaudMask) | baud_termios(baud);
+        assert!(termios_baud(&termios) == Some(baud));
+    }
+
+    // unknown rates fall back to the fastest one
+    assert!(baud_termios(12345) == ControlFlags::from(B115200));
+    assert!(baud_termios(9600) == ControlFlags::from(B9600));
+
+    termios.control_flags = termios.control_flags & !ControlFlags::BaudMask;
+    assert!(termios_baud(&termios).is_none());
+
+    let port = get_port(0).expect("no COM1");
+
+    without_interrupts(|| {
+        port.flush();
+
+        port.set_baud(1);
+        assert!(port.baud == 1);
+
+        port.set_baud(0);
+        assert!(port.baud == 1);
+
+        port.set_baud(1000000);
+        assert!(port.baud == 115200);
+
+        port.set_baud(DEFAULT_BAUD);
+    });
+}
+
+/// make sure monitor command lines are parsed properly, and bad ones are turned away
+#[test_case]
+fn monitor_parsing() {
+    assert!(parse_command("") == Ok(Command::Nothing));
+    assert!(parse_command("  c ") == Ok(Command::Continue));
+    assert!(parse_command("?") == Ok(Command::Help));
+    assert!(parse_command("irqs") == Ok(Command::Irqs));
+    assert!(parse_command("poweroff") == Ok(Command::PowerOff));
+    assert!(parse_command("pages") == Ok(Command::Pages(None)));
+    assert!(parse_command("pages 0xc0000000") == Ok(Command::Pages(Some(0xc0000000))));
+    assert!(parse_command("pages nope") == Err(ParseError::BadArguments("bad address")));
+    assert!(parse_command("mem 4096") == Ok(Command::Memory(4096, DEFAULT_DUMP_SIZE)));
+    assert!(parse_command("mem 0x1000 0x20") == Ok(Command::Memory(0x1000, 0x20)));
+    assert!(matches!(parse_command("mem"), Err(ParseError::BadArguments(_))));
+    assert!(matches!(parse_command("mem lots"), Err(ParseError::BadArguments(_))));
+    assert!(parse_command("frob 1") == Err(ParseError::Unknown("frob")));
+}
+
+/// make sure monitor commands run and write their output to the monitor's channel
+#[test_case]
+fn monitor_dispatch() {
+    static DUMP_TEST: [u8; 16] = *b"monitor mem test";
+
+    let mut monitor = Monitor::new(Channel::Console, None);
+
+    assert!(!monitor.execute(&alloc::format!("mem {:#x} 16", DUMP_TEST.as_ptr() as usize)));
+    assert!(!monitor.execute("frob"));
+    assert!(monitor.execute("continue"));
+
+    let mut contents: Vec<u8> = Vec::new();
+    get_console().unwrap().dump(&mut |b| contents.push(b));
+
+    assert!(contents.windows(18).any(|w| w == b"|monitor mem test|"));
+    assert!(contents.windows(22).any(|w| w == b"unknown command \"frob\""));
+}
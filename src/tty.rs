@@ -0,0 +1,746 @@
+//! terminals and the line discipline
+//! a tty sits between a device (the console, a serial port) and whatever processes are using it,
+//! turning raw input into lines, echoing it back and sending signals to the foreground process group
+
+use bitmask_enum::bitmask;
+use num_enum::TryFromPrimitive;
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use crate::{
+    arch::{
+        syscalls::user_buffer,
+        without_interrupts,
+    },
+    cmdline,
+    console::{NUM_CONSOLES, active_console, get_virtual_console},
+    errno::Errno,
+    fs::{
+        devfs::add_device,
+        tree::{File, LockType},
+        vfs::Permissions,
+    },
+    input::{KeyCode, KeyEvent, set_event_handler},
+    signals::{Signal, send_signal_to_pgroup},
+    tasks::{TASKS, BlockReason, get_current_task, get_current_task_mut, wake_blocked},
+    util::ring::RingBuffer,
+};
+
+/// amount of control characters in a termios structure
+pub const NUM_CONTROL_CHARS: usize = 19;
+
+/// maximum length of a line in canonical mode
+pub const MAX_CANON: usize = 255;
+
+/// size of the buffer holding input that's ready to be read
+pub const INPUT_BUFFER_SIZE: usize = 4096;
+
+/// input mode flags
+#[bitmask(u32)]
+#[repr(transparent)]
+pub enum InputFlags {
+    None                = Self(0),
+    StripHighBit        = Self(0x0020), // ISTRIP
+    MapNLToCR           = Self(0x0040), // INLCR
+    IgnoreCR            = Self(0x0080), // IGNCR
+    MapCRToNL           = Self(0x0100), // ICRNL
+}
+
+/// output mode flags
+#[bitmask(u32)]
+#[repr(transparent)]
+pub enum OutputFlags {
+    None                = Self(0),
+    PostProcess         = Self(0x0001), // OPOST
+    MapNLToCRNL         = Self(0x0004), // ONLCR
+    MapCRToNL           = Self(0x0008), // OCRNL
+}
+
+/// control mode flags, only meaningful for serial ports
+#[bitmask(u32)]
+#[repr(transparent)]
+pub enum ControlFlags {
+    None                = Self(0),
+    BaudMask            = Self(0x100f), // CBAUD
+    CharSizeMask        = Self(0x0030), // CSIZE
+    TwoStopBits         = Self(0x0040), // CSTOPB
+    EnableReceiver      = Self(0x0080), // CREAD
+    ParityEnable        = Self(0x0100), // PARENB
+    OddParity           = Self(0x0200), // PARODD
+}
+
+/// local mode flags
+#[bitmask(u32)]
+#[repr(transparent)]
+pub enum LocalFlags {
+    None                = Self(0),
+    Signals             = Self(0x0001), // ISIG
+    Canonical           = Self(0x0002), // ICANON
+    Echo                = Self(0x0008), // ECHO
+    EchoErase           = Self(0x0010), // ECHOE
+    EchoKill            = Self(0x0020), // ECHOK
+    EchoNL              = Self(0x0040), // ECHONL
+    NoFlush             = Self(0x0080), // NOFLSH
+    EchoControl         = Self(0x0200), // ECHOCTL
+    Extended            = Self(0x8000), // IEXTEN
+}
+
+/// indices of special characters in Termios::control_chars
+#[repr(usize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlChar {
+    Interrupt = 0,      // VINTR
+    Quit = 1,           // VQUIT
+    Erase = 2,          // VERASE
+    Kill = 3,           // VKILL
+    EndOfFile = 4,      // VEOF
+    Time = 5,           // VTIME
+    Min = 6,            // VMIN
+    Suspend = 10,       // VSUSP
+    EndOfLine = 11,     // VEOL
+    WordErase = 14,     // VWERASE
+}
+
+/// terminal settings, laid out the same way as linux's struct termios
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Termios {
+    pub input_flags: InputFlags,        // c_iflag
+    pub output_flags: OutputFlags,      // c_oflag
+    pub control_flags: ControlFlags,    // c_cflag
+    pub local_flags: LocalFlags,        // c_lflag
+    pub line: u8,                       // c_line
+    pub control_chars: [u8; NUM_CONTROL_CHARS], // c_cc
+}
+
+/// baud rate values for ControlFlags::BaudMask
+pub const B9600: u32 = 0x000d;
+pub const B19200: u32 = 0x000e;
+pub const B38400: u32 = 0x000f;
+pub const B57600: u32 = 0x1001;
+pub const B115200: u32 = 0x1002;
+
+/// 8 bit characters for ControlFlags::CharSizeMask
+pub const CS8: u32 = 0x0030;
+
+impl Default for Termios {
+    /// sane defaults for an interactive terminal
+    fn default() -> Self {
+        let mut control_chars = [0; NUM_CONTROL_CHARS];
+        control_chars[ControlChar::Interrupt as usize] = 0x03;  // ^C
+        control_chars[ControlChar::Quit as usize] = 0x1c;       // ^\
+        control_chars[ControlChar::Erase as usize] = 0x7f;      // DEL
+        control_chars[ControlChar::Kill as usize] = 0x15;       // ^U
+        control_chars[ControlChar::EndOfFile as usize] = 0x04;  // ^D
+        control_chars[ControlChar::Min as usize] = 1;
+        control_chars[ControlChar::Suspend as usize] = 0x1a;    // ^Z
+        control_chars[ControlChar::WordErase as usize] = 0x17;  // ^W
+
+        Self {
+            input_flags: InputFlags::MapCRToNL,
+            output_flags: OutputFlags::PostProcess | OutputFlags::MapNLToCRNL,
+            control_flags: ControlFlags::EnableReceiver | ControlFlags(CS8 | B38400),
+            local_flags: LocalFlags::Signals | LocalFlags::Canonical | LocalFlags::Echo | LocalFlags::EchoErase | LocalFlags::EchoKill | LocalFlags::EchoControl | LocalFlags::Extended,
+            line: 0,
+            control_chars,
+        }
+    }
+}
+
+impl Termios {
+    /// gets a special character
+    pub fn char(&self, which: ControlChar) -> u8 {
+        self.control_chars[which as usize]
+    }
+
+    /// checks whether c is the given special character, disabled (0) special characters never match
+    fn is_char(&self, c: u8, which: ControlChar) -> bool {
+        let special = self.char(which);
+        special != 0 && c == special
+    }
+}
+
+/// tty ioctl requests, numbered the same as on linux
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive)]
+pub enum TtyRequest {
+    /// get the current termios settings (TCGETS)
+    GetAttributes = 0x5401,
+
+    /// set termios settings immediately (TCSETS)
+    SetAttributes = 0x5402,
+
+    /// set termios settings once output is drained (TCSETSW), output is never buffered so this is the same as SetAttributes
+    SetAttributesDrain = 0x5403,
+
+    /// set termios settings and discard pending input (TCSETSF)
+    SetAttributesFlush = 0x5404,
+
+    /// get the foreground process group (TIOCGPGRP)
+    GetForegroundGroup = 0x540f,
+
+    /// set the foreground process group (TIOCSPGRP)
+    SetForegroundGroup = 0x5410,
+}
+
+/// something a tty can send output to
+pub trait TtyDriver {
+    /// writes bytes to the device
+    fn write(&mut self, bytes: &[u8]);
+
+    /// called when the termios settings of the tty change, so the driver can reconfigure the device
+    fn set_termios(&mut self, _termios: &Termios) {}
+}
+
+/// a terminal
+pub struct Tty {
+    /// where output goes
+    pub driver: Box<dyn TtyDriver>,
+
+    /// current settings
+    pub termios: Termios,
+
+    /// process group that gets input and signals generated by special characters
+    pub foreground_group: Option<usize>,
+
+    /// line being edited in canonical mode
+    line: [u8; MAX_CANON],
+
+    /// length of the line being edited
+    line_len: usize,
+
+    /// input that's ready to be read
+    input: RingBuffer<u8, INPUT_BUFFER_SIZE>,
+
+    /// amount of end of file markers that haven't been read yet
+    pending_eof: usize,
+}
+
+impl Tty {
+    pub fn new(driver: Box<dyn TtyDriver>) -> Self {
+        Self {
+            driver,
+            termios: Default::default(),
+            foreground_group: None,
+            line: [0; MAX_CANON],
+            line_len: 0,
+            input: RingBuffer::new(0),
+            pending_eof: 0,
+        }
+    }
+
+    /// writes output to the tty, applying output processing
+    pub fn write(&mut self, bytes: &[u8]) {
+        let flags = self.termios.output_flags;
+
+        if flags & OutputFlags::PostProcess == 0 {
+            self.driver.write(bytes);
+            return;
+        }
+
+        let mut processed = Vec::with_capacity(bytes.len());
+
+        for &c in bytes {
+            match c {
+                b'\n' if flags & OutputFlags::MapNLToCRNL != 0 => processed.extend_from_slice(b"\r\n"),
+                b'\r' if flags & OutputFlags::MapCRToNL != 0 => processed.push(b'\n'),
+                _ => processed.push(c),
+            }
+        }
+
+        self.driver.write(&processed);
+    }
+
+    /// echoes a character if echo is enabled, showing control characters as ^X if EchoControl is set
+    fn echo(&mut self, c: u8) {
+        let flags = self.termios.local_flags;
+
+        if flags & LocalFlags::Echo == 0 {
+            if c == b'\n' && flags & LocalFlags::EchoNL != 0 {
+                self.write(b"\n");
+            }
+            return;
+        }
+
+        if flags & LocalFlags::EchoControl != 0 && (c < 0x20 || c == 0x7f) && c != b'\n' && c != b'\t' {
+            self.write(&[b'^', c ^ 0x40]);
+        } else {
+            self.write(&[c]);
+        }
+    }
+
+    /// removes the last character of the line being edited, erasing it from the screen if EchoErase is set
+    fn erase_char(&mut self) -> bool {
+        if self.line_len == 0 {
+            return false;
+        }
+
+        self.line_len -= 1;
+        let c = self.line[self.line_len];
+
+        if self.termios.local_flags & (LocalFlags::Echo | LocalFlags::EchoErase) == LocalFlags::Echo | LocalFlags::EchoErase {
+            // control characters were echoed as two characters
+            let width = if self.termios.local_flags & LocalFlags::EchoControl != 0 && (c < 0x20 || c == 0x7f) { 2 } else { 1 };
+
+            for _i in 0..width {
+                self.write(b"\x08 \x08");
+            }
+        }
+
+        true
+    }
+
+    /// moves the line being edited into the input buffer, making it available to readers
+    fn commit_line(&mut self) {
+        for i in 0..self.line_len {
+            if self.input.push(self.line[i]).is_err() {
+                break;
+            }
+        }
+
+        self.line_len = 0;
+    }
+
+    /// discards all pending input
+    pub fn flush_input(&mut self) {
+        self.line_len = 0;
+        self.input.clear();
+        self.pending_eof = 0;
+    }
+
+    /// handles a byte of input from the device, returns whether there's new input to read
+    pub fn input(&mut self, mut c: u8) -> bool {
+        let termios = self.termios;
+        let input_flags = termios.input_flags;
+        let local_flags = termios.local_flags;
+
+        if input_flags & InputFlags::StripHighBit != 0 {
+            c &= 0x7f;
+        }
+
+        match c {
+            b'\r' if input_flags & InputFlags::IgnoreCR != 0 => return false,
+            b'\r' if input_flags & InputFlags::MapCRToNL != 0 => c = b'\n',
+            b'\n' if input_flags & InputFlags::MapNLToCR != 0 => c = b'\r',
+            _ => (),
+        }
+
+        // special characters that send signals
+        if local_flags & LocalFlags::Signals != 0 {
+            let signal =
+                if termios.is_char(c, ControlChar::Interrupt) {
+                    Some(Signal::Interrupt)
+                } else if termios.is_char(c, ControlChar::Quit) {
+                    Some(Signal::Quit)
+                } else if termios.is_char(c, ControlChar::Suspend) {
+                    Some(Signal::TerminalStop)
+                } else {
+                    None
+                };
+
+            if let Some(signal) = signal {
+                if local_flags & LocalFlags::NoFlush == 0 {
+                    self.flush_input();
+                }
+
+                self.echo(c);
+
+                if let Some(group) = self.foreground_group {
+                    let _ = send_signal_to_pgroup(group, signal);
+                }
+
+                return false;
+            }
+        }
+
+        if local_flags & LocalFlags::Canonical == 0 {
+            self.echo(c);
+            return self.input.push(c).is_ok();
+        }
+
+        // line editing
+        if termios.is_char(c, ControlChar::Erase) || c == 0x08 {
+            self.erase_char();
+            false
+        } else if termios.is_char(c, ControlChar::WordErase) && local_flags & LocalFlags::Extended != 0 {
+            // erase any spaces, then everything up to the next space
+            while self.line_len > 0 && self.line[self.line_len - 1] == b' ' {
+                self.erase_char();
+            }
+            while self.line_len > 0 && self.line[self.line_len - 1] != b' ' {
+                self.erase_char();
+            }
+            false
+        } else if termios.is_char(c, ControlChar::Kill) {
+            if local_flags & LocalFlags::EchoKill != 0 {
+                while self.erase_char() {}
+            } else {
+                self.line_len = 0;
+                self.echo(c);
+            }
+            false
+        } else if termios.is_char(c, ControlChar::EndOfFile) {
+            // an empty line means end of file, otherwise whatever's there is made available without a newline
+            if self.line_len == 0 {
+                self.pending_eof += 1;
+            } else {
+                self.commit_line();
+            }
+            true
+        } else if c == b'\n' || termios.is_char(c, ControlChar::EndOfLine) {
+            if self.line_len < MAX_CANON {
+                self.line[self.line_len] = c;
+                self.line_len += 1;
+            }
+            self.echo(c);
+            self.commit_line();
+            true
+        } else {
+            // leave room for the newline at the end
+            if self.line_len < MAX_CANON - 1 {
+                self.line[self.line_len] = c;
+                self.line_len += 1;
+                self.echo(c);
+            }
+            false
+        }
+    }
+
+    /// reads input that's ready into the provided buffer
+    /// fails with OperationWouldBlock if there's nothing to read yet
+    pub fn read(&mut self, bytes: &mut [u8]) -> Result<usize, Errno> {
+        if bytes.is_empty() {
+            return Ok(0);
+        }
+
+        let canonical = self.termios.local_flags & LocalFlags::Canonical != 0;
+
+        if self.input.is_empty() {
+            if canonical && self.pending_eof > 0 {
+                self.pending_eof -= 1;
+                return Ok(0);
+            }
+
+            // in raw mode with a minimum of 0 characters, reads never wait
+            if !canonical && self.termios.char(ControlChar::Min) == 0 {
+                return Ok(0);
+            }
+
+            return Err(Errno::OperationWouldBlock);
+        }
+
+        let mut read = 0;
+
+        while read < bytes.len() {
+            match self.input.pop() {
+                Some(c) => {
+                    bytes[read] = c;
+                    read += 1;
+
+                    // canonical reads return at most one line
+                    if canonical && c == b'\n' {
+                        break;
+                    }
+                },
+                None => break,
+            }
+        }
+
+        Ok(read)
+    }
+
+    /// handles a tty ioctl request
+    pub fn ioctl(&mut self, request: u32, arg: u32) -> Result<u32, Errno> {
+        let termios_size = core::mem::size_of::<Termios>();
+
+        match TtyRequest::try_from(request).map_err(|_| Errno::WrongIOControl)? {
+            TtyRequest::GetAttributes => {
+                let buf = user_buffer(arg, termios_size)?;
+                unsafe { (buf.as_mut_ptr() as *mut Termios).write_unaligned(self.termios); }
+                Ok(0)
+            },
+            request @ (TtyRequest::SetAttributes | TtyRequest::SetAttributesDrain | TtyRequest::SetAttributesFlush) => {
+                let buf = user_buffer(arg, termios_size)?;
+                self.termios = unsafe { (buf.as_ptr() as *const Termios).read_unaligned() };
+
+                if request == TtyRequest::SetAttributesFlush {
+                    self.flush_input();
+                }
+
+                self.driver.set_termios(&self.termios);
+                Ok(0)
+            },
+            TtyRequest::GetForegroundGroup => {
+                let buf = user_buffer(arg, 4)?;
+                buf.copy_from_slice(&(self.foreground_group.unwrap_or(0) as u32).to_ne_bytes());
+                Ok(0)
+            },
+            TtyRequest::SetForegroundGroup => {
+                let buf = user_buffer(arg, 4)?;
+                let group = u32::from_ne_bytes(buf[..4].try_into().unwrap()) as usize;
+
+                if unsafe { TASKS.iter() }.any(|task| task.pgid == group) {
+                    self.foreground_group = Some(group);
+                    Ok(0)
+                } else {
+                    Err(Errno::OperationNotPermitted)
+                }
+            },
+        }
+    }
+}
+
+/// all the ttys we have
+static mut TTYS: Vec<Tty> = Vec::new();
+
+/// index of the tty used for /dev/console
+pub static mut CONSOLE_TTY: Option<usize> = None;
+
+/// indices of the ttys attached to each virtual console
+static mut VIRTUAL_CONSOLE_TTYS: [Option<usize>; NUM_CONSOLES] = [None; NUM_CONSOLES];
+
+/// names of the ttys, for finding them by name
+static mut TTY_NAMES: Vec<String> = Vec::new();
+
+/// adds a new tty, returning its index
+pub fn register_tty(name: &str, driver: Box<dyn TtyDriver>) -> usize {
+    without_interrupts(|| unsafe {
+        TTYS.push(Tty::new(driver));
+        TTY_NAMES.push(name.to_string());
+        TTYS.len() - 1
+    })
+}
+
+/// finds a tty by its name, like tty1 or ttyS0
+pub fn find_tty(name: &str) -> Option<usize> {
+    unsafe { TTY_NAMES.iter().position(|other| other == name) }
+}
+
+/// gets a tty by its index
+pub fn get_tty(index: usize) -> Option<&'static mut Tty> {
+    unsafe { TTYS.get_mut(index) }
+}
+
+/// feeds input from a device into a tty, waking up anything waiting to read from it
+pub fn tty_input(index: usize, bytes: &[u8]) {
+    if let Some(tty) = get_tty(index) {
+        let mut ready = false;
+
+        for &c in bytes {
+            ready |= tty.input(c);
+        }
+
+        if ready {
+            wake_blocked(BlockReason::TtyRead(index));
+        }
+    }
+}
+
+/// device file for a tty
+pub struct TtyFile {
+    /// name of the device file
+    name: String,
+
+    /// index of the tty this file refers to
+    index: usize,
+}
+
+impl TtyFile {
+    pub fn new(name: &str, index: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            index,
+        }
+    }
+}
+
+impl File for TtyFile {
+    fn get_permissions(&self) -> Permissions {
+        Permissions::OwnerRead | Permissions::OwnerWrite | Permissions::GroupRead | Permissions::GroupWrite | Permissions::OtherRead | Permissions::OtherWrite
+    }
+
+    fn set_permissions(&mut self, _permissions: Permissions) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn write_at(&mut self, bytes: &[u8], _offset: usize) -> Result<usize, Errno> {
+        let tty = get_tty(self.index).ok_or(Errno::NoSuchDevice)?;
+
+        without_interrupts(|| tty.write(bytes));
+
+        Ok(bytes.len())
+    }
+
+    fn can_write_at(&self, _space: usize, _offset: usize) -> bool {
+        true
+    }
+
+    /// reads input from the tty
+    /// if there's nothing to read yet, the current task is blocked until there is and this fails with OperationWouldBlock
+    fn read_at(&self, bytes: &mut [u8], _offset: usize) -> Result<usize, Errno> {
+        let tty = get_tty(self.index).ok_or(Errno::NoSuchDevice)?;
+
+        without_interrupts(|| {
+            // only the foreground process group gets to read
+            if let (Some(current), Some(group)) = (get_current_task(), tty.foreground_group) {
+                if current.pgid != group && !current.state.is_kernel_thread() {
+                    let _ = send_signal_to_pgroup(current.pgid, Signal::TerminalInput);
+                    return Err(Errno::Interrupted);
+                }
+            }
+
+            let result = tty.read(bytes);
+
+            if let Err(Errno::OperationWouldBlock) = result {
+                if let Some(current) = get_current_task_mut() {
+                    current.block(BlockReason::TtyRead(self.index), None);
+                }
+            }
+
+            result
+        })
+    }
+
+    fn can_read_at(&self, _space: usize, _offset: usize) -> bool {
+        get_tty(self.index).map(|tty| !tty.input.is_empty()).unwrap_or(false)
+    }
+
+    fn truncate(&mut self, _size: usize) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn lock(&mut self, _kind: LockType, _size: isize) -> Result<(), Errno> {
+        Err(Errno::NotSupported)
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: &str) -> Result<(), Errno> {
+        self.name = name.to_string();
+        Ok(())
+    }
+
+    fn get_size(&self) -> usize {
+        0
+    }
+
+    fn ioctl(&mut self, request: u32, arg: u32) -> Result<u32, Errno> {
+        let tty = get_tty(self.index).ok_or(Errno::NoSuchDevice)?;
+
+        without_interrupts(|| tty.ioctl(request, arg))
+    }
+}
+
+/// tty driver that writes to a virtual console
+pub struct ConsoleTtyDriver {
+    /// index of the virtual console
+    console: usize,
+}
+
+impl TtyDriver for ConsoleTtyDriver {
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(console) = get_virtual_console(self.console) {
+            console.write_bytes(bytes);
+        }
+    }
+}
+
+/// gets the bytes a key that doesn't type a character sends to a terminal, like a vt220 would
+fn key_sequence(key: KeyCode) -> Option<&'static [u8]> {
+    Some(match key {
+        KeyCode::Up => b"\x1b[A",
+        KeyCode::Down => b"\x1b[B",
+        KeyCode::Right => b"\x1b[C",
+        KeyCode::Left => b"\x1b[D",
+        KeyCode::Home => b"\x1b[H",
+        KeyCode::End => b"\x1b[F",
+        KeyCode::Insert => b"\x1b[2~",
+        KeyCode::Delete => b"\x1b[3~",
+        KeyCode::PageUp => b"\x1b[5~",
+        KeyCode::PageDown => b"\x1b[6~",
+        KeyCode::F1 => b"\x1bOP",
+        KeyCode::F2 => b"\x1bOQ",
+        KeyCode::F3 => b"\x1bOR",
+        KeyCode::F4 => b"\x1bOS",
+        KeyCode::F5 => b"\x1b[15~",
+        KeyCode::F6 => b"\x1b[17~",
+        KeyCode::F7 => b"\x1b[18~",
+        KeyCode::F8 => b"\x1b[19~",
+        KeyCode::F9 => b"\x1b[20~",
+        KeyCode::F10 => b"\x1b[21~",
+        KeyCode::F11 => b"\x1b[23~",
+        KeyCode::F12 => b"\x1b[24~",
+        _ => return None,
+    })
+}
+
+/// turns key events from the keyboard into input for the tty of the virtual console being shown
+fn console_key_event(event: KeyEvent) {
+    let index =
+        match unsafe { VIRTUAL_CONSOLE_TTYS[active_console()] } {
+            Some(index) if event.pressed => index,
+            _ => return,
+        };
+
+    if let Some(c) = event.ascii {
+        // alt sends an escape before the character
+        if event.modifiers.alt() {
+            tty_input(index, &[0x1b, c]);
+        } else {
+            tty_input(index, &[c]);
+        }
+    } else if let Some(sequence) = key_sequence(event.key) {
+        tty_input(index, sequence);
+    }
+}
+
+/// adds a device file for a tty
+fn add_tty_device(name: &str, index: usize) {
+    if let Err(err) = add_device(Box::new(TtyFile::new(name, index))) {
+        log!("couldn't add /dev/{}: {}", name, err);
+    }
+}
+
+/// sets up ttys for the virtual consoles and their device files
+pub fn init() {
+    for console in 0..NUM_CONSOLES {
+        // virtual consoles are numbered from 1 like on linux
+        let name = format!("tty{}", console + 1);
+        let index = register_tty(&name, Box::new(ConsoleTtyDriver { console }));
+
+        unsafe { VIRTUAL_CONSOLE_TTYS[console] = Some(index); }
+
+        add_tty_device(&name, index);
+    }
+
+    if let Some(index) = unsafe { VIRTUAL_CONSOLE_TTYS[0] } {
+        add_tty_device("tty0", index);
+    }
+
+    set_event_handler(Some(console_key_event));
+}
+
+/// picks the tty used for /dev/console according to the console parameter, once all ttys have been registered
+pub fn init_console() {
+    let name = cmdline::get_str("console").unwrap_or("tty1");
+
+    // tty0 is the first virtual console here, not the current one like on linux
+    let index = find_tty(if name == "tty0" { "tty1" } else { name }).or_else(|| {
+        log!("no tty named {}, using tty1", name);
+        find_tty("tty1")
+    });
+
+    unsafe { CONSOLE_TTY = index; }
+
+    // there's no controlling tty yet, so /dev/tty is always the console
+    if let Some(index) = index {
+        for name in ["tty", "console"] {
+            add_tty_device(name, index);
+        }
+    }
+}
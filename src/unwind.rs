@@ -11,28 +11,51 @@
  */
 
 use crate::{
-    console::{get_console, PANIC_COLOR},
-    platform::debug::exit_failure,
+    console::{get_console, switch_console, PANIC_COLOR},
+    platform::debug::{exit_failure, puts, putb},
 };
 
 #[panic_handler]
 pub fn panic_implementation(info: &::core::panic::PanicInfo) -> ! {
+    // registers here, so the monitor's backtrace starts at the panic instead of inside the monitor
+    let regs = crate::arch::ints::current_registers();
+
     let (file,line) = match info.location() {
         Some(loc) => (loc.file(), loc.line(),),
         None => ("", 0),
     };
 
+    // make sure the panic message is actually on screen
+    let _ = switch_console(0);
+
+    // take over logging and write out anything that was waiting, so nothing before the panic is lost
+    crate::logging::panic_flush();
+
     if let Some(console) = get_console() {
         console.set_color(PANIC_COLOR);
     }
 
     if let Some(m) = info.message() {
-        log!("PANIC: file='{}', line={} :: {}", file, line, m);
+        error!("PANIC: file='{}', line={} :: {}", file, line, m);
     } else if let Some(m) = info.payload().downcast_ref::<&str>() {
-        log!("PANIC: file='{}', line={} :: {}", file, line, m);
+        error!("PANIC: file='{}', line={} :: {}", file, line, m);
     } else {
-        log!("PANIC: file='{}', line={} :: ?", file, line);
+        error!("PANIC: file='{}', line={} :: ?", file, line);
+    }
+
+    crate::arch::backtrace::print_backtrace();
+
+    // dump everything on the console to serial, so output that only went to the screen isn't lost
+    if let Some(console) = get_console() {
+        unsafe {
+            puts("=== console scrollback\r\n");
+            console.dump(&mut |b| putb(b));
+            puts("=== end of console scrollback\r\n");
+        }
     }
+
+    // let whoever's watching look around before we stop for good
+    crate::monitor::enter("panic", Some(&regs));
     
     if cfg!(test) {
         exit_failure();
@@ -1 +1,4 @@
 pub mod array;
+pub mod psf;
+pub mod ring;
+pub mod symbols;
@@ -0,0 +1,91 @@
+//! PC screen font (PSF1 and PSF2) parsing
+//! these are the bitmap fonts the linux console uses, each glyph is a bitmap with rows padded to whole bytes
+
+/// magic number at the start of PSF1 fonts
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+
+/// magic number at the start of PSF2 fonts
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+/// PSF1 mode flag for fonts with 512 glyphs instead of 256
+const PSF1_MODE_512: u8 = 1 << 0;
+
+/// size of a PSF1 header
+const PSF1_HEADER_SIZE: usize = 4;
+
+/// a parsed bitmap font
+#[derive(Debug, Clone, Copy)]
+pub struct Font<'a> {
+    /// width of a glyph in pixels
+    pub width: usize,
+
+    /// height of a glyph in pixels
+    pub height: usize,
+
+    /// amount of glyphs in the font
+    pub num_glyphs: usize,
+
+    /// bytes per row of a glyph
+    pub bytes_per_row: usize,
+
+    /// glyph bitmaps, one after another
+    glyphs: &'a [u8],
+}
+
+/// reads a little endian u32 from the given offset
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+impl<'a> Font<'a> {
+    /// parses a PSF1 or PSF2 font
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        let (width, height, num_glyphs, header_size) =
+            if data.starts_with(&PSF2_MAGIC) {
+                let header_size = read_u32(data, 8).ok_or("font is truncated")?;
+                let num_glyphs = read_u32(data, 16).ok_or("font is truncated")?;
+                let height = read_u32(data, 24).ok_or("font is truncated")?;
+                let width = read_u32(data, 28).ok_or("font is truncated")?;
+
+                let bytes_per_glyph = read_u32(data, 20).ok_or("font is truncated")?;
+                if bytes_per_glyph != (width + 7) / 8 * height {
+                    return Err("glyph size doesn't match dimensions");
+                }
+
+                (width, height, num_glyphs, header_size)
+            } else if data.starts_with(&PSF1_MAGIC) {
+                let mode = *data.get(2).ok_or("font is truncated")?;
+                let height = *data.get(3).ok_or("font is truncated")? as usize;
+                let num_glyphs = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+                (8, height, num_glyphs, PSF1_HEADER_SIZE)
+            } else {
+                return Err("not a PSF font");
+            };
+
+        if width == 0 || height == 0 || num_glyphs == 0 {
+            return Err("font is empty");
+        }
+
+        let bytes_per_row = (width + 7) / 8;
+        let glyphs = data.get(header_size..header_size + num_glyphs * bytes_per_row * height).ok_or("font is truncated")?;
+
+        Ok(Self {
+            width, height, num_glyphs, bytes_per_row, glyphs,
+        })
+    }
+
+    /// gets the bitmap for a glyph, falling back to the first glyph if it doesn't exist
+    pub fn glyph(&self, index: usize) -> &'a [u8] {
+        let size = self.bytes_per_row * self.height;
+        let index = if index < self.num_glyphs { index } else { 0 };
+
+        &self.glyphs[index * size..(index + 1) * size]
+    }
+
+    /// checks whether a pixel in a glyph is set
+    pub fn pixel(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        glyph[y * self.bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
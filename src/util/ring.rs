@@ -0,0 +1,95 @@
+//! fixed size ring buffer
+//! doesn't allocate, so it's safe to use from interrupt handlers
+
+/// ring buffer holding up to N items
+pub struct RingBuffer<T: Copy, const N: usize> {
+    /// storage for items
+    buffer: [T; N],
+
+    /// index of the oldest item in the buffer
+    start: usize,
+
+    /// amount of items in the buffer
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// creates a new empty ring buffer, using the provided value to fill unused slots
+    pub const fn new(fill: T) -> Self {
+        Self {
+            buffer: [fill; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// adds an item to the end of the buffer
+    /// if the buffer is full the item is handed back
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(item);
+        }
+
+        self.buffer[(self.start + self.len) % N] = item;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// removes the oldest item from the buffer
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let item = self.buffer[self.start];
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+
+        Some(item)
+    }
+
+    /// gets the oldest item in the buffer without removing it
+    pub fn peek(&self) -> Option<T> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.buffer[self.start])
+        }
+    }
+
+    /// gets an item by its position in the buffer, 0 being the oldest
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index < self.len {
+            Some(self.buffer[(self.start + index) % N])
+        } else {
+            None
+        }
+    }
+
+    /// removes every item from the buffer
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// amount of items in the buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// maximum amount of items the buffer can hold
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// whether the buffer has no items in it
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// whether the buffer can't hold any more items
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+}
@@ -0,0 +1,50 @@
+//! symbol table lookup
+//! the table is plain text, one "address name" line per symbol sorted by address, ending at the first null byte.
+//! symbols.sh generates this from the kernel's ELF after it's linked
+
+/// a table of symbols, sorted by address
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTable<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// wraps the given table, anything after the first null byte is ignored
+    pub fn new(data: &'a [u8]) -> Self {
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+
+        Self { data: &data[..len] }
+    }
+
+    /// whether the table has no symbols in it, i.e. symbols.sh wasn't run on the kernel
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// iterates over the address and name of every symbol in the table, skipping lines that don't parse
+    pub fn symbols(&self) -> impl Iterator<Item = (usize, &'a str)> {
+        let data = self.data;
+
+        data.split(|&b| b == b'\n').filter_map(|line| {
+            let line = core::str::from_utf8(line).ok()?;
+            let (addr, name) = line.split_once(' ')?;
+
+            Some((usize::from_str_radix(addr, 16).ok()?, name))
+        })
+    }
+
+    /// finds the symbol containing the given address, returning its name and the offset of the address into it
+    pub fn lookup(&self, addr: usize) -> Option<(&'a str, usize)> {
+        let mut found = None;
+
+        for (start, name) in self.symbols() {
+            if start > addr {
+                break;
+            }
+
+            found = Some((name, addr - start));
+        }
+
+        found
+    }
+}
@@ -0,0 +1,34 @@
+#!/bin/bash
+# fills in the kernel's embedded symbol table (the .ksymbols section) from its own ELF symbols, so backtraces can show function names
+# usage: ./symbols.sh <kernel>
+
+KERNEL="$1"
+NM="${NM:-nm}"
+OBJCOPY="${OBJCOPY:-objcopy}"
+
+if [ -z "${KERNEL}" ]; then
+    echo "usage: $0 <kernel>" >&2
+    exit 1
+fi
+
+TABLE="$(mktemp)"
+OLD="$(mktemp)"
+trap 'rm -f "${TABLE}" "${OLD}"' EXIT
+
+# one "address name" line per function, sorted by address, with the hashes rust adds to symbol names removed
+"${NM}" -n -C --defined-only "${KERNEL}" \
+    | awk '$2 ~ /^[tT]$/ { addr = $1; $1 = ""; $2 = ""; sub(/^ +/, ""); print addr, $0 }' \
+    | sed 's/::h[0-9a-f]\{16\}$//' > "${TABLE}" || exit 1
+
+# the section is a fixed size, so pad the table out to fill it
+"${OBJCOPY}" -O binary --only-section=.ksymbols "${KERNEL}" "${OLD}" || exit 1
+SIZE="$(stat -c %s "${OLD}")"
+
+if [ "$(stat -c %s "${TABLE}")" -ge "${SIZE}" ]; then
+    echo "symbol table is too big for .ksymbols (${SIZE} bytes), make it bigger in src/arch/i586/boot.ld" >&2
+    exit 1
+fi
+
+truncate -s "${SIZE}" "${TABLE}"
+
+"${OBJCOPY}" --update-section .ksymbols="${TABLE}" "${KERNEL}"